@@ -1,17 +1,22 @@
 pub use crate::errors::*;
+use crate::transport::{client_config, parse_uri, Address};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 const DEFAULT_FETCH_SIZE: usize = 200;
 const DEFAULT_MAX_CONNECTIONS: usize = 16;
+const DEFAULT_MAX_RETRY_TIME: Duration = Duration::from_secs(30);
 
 /// The configuration used to connect to the database, see [`Graph::connect`]
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub(crate) uri: String,
     pub(crate) user: String,
     pub(crate) password: String,
     pub(crate) max_connections: usize,
     pub(crate) db: String,
     pub(crate) fetch_size: usize,
+    pub(crate) max_retry_time: Duration,
+    pub(crate) address: Address,
 }
 
 /// A builder to override default configurations and build the [`Config`]
@@ -22,10 +27,17 @@ pub struct ConfigBuilder {
     db: Option<String>,
     fetch_size: Option<usize>,
     max_connections: Option<usize>,
+    max_retry_time: Option<Duration>,
+    ca_certificates: Option<PathBuf>,
+    client_certificate: Option<(PathBuf, PathBuf)>,
 }
 
 impl ConfigBuilder {
-    ///the uri of the neo4j server
+    ///the uri of the neo4j server, like `bolt://localhost:7687`
+    ///
+    ///`bolt+s://` connects over TLS and verifies the certificate of the server, `bolt+ssc://`
+    ///connects over TLS and accepts any certificate, like a self-signed one. A uri without
+    ///scheme is a plain `bolt://` one, the port defaults to 7687.
     pub fn uri(mut self, uri: &str) -> Self {
         self.uri = Some(uri.to_owned());
         self
@@ -63,25 +75,69 @@ impl ConfigBuilder {
         self
     }
 
+    ///how long [`Graph::write_transaction`] and [`Graph::read_transaction`] keep retrying a
+    ///transaction that failed with a transient error, default max_retry_time is 30 seconds
+    pub fn max_retry_time(mut self, max_retry_time: Duration) -> Self {
+        self.max_retry_time = Some(max_retry_time);
+        self
+    }
+
+    ///PEM file of the certificate authorities trusted by `bolt+s://` connections, instead of the
+    ///built-in ones
+    pub fn ca_certificates(mut self, path: impl AsRef<Path>) -> Self {
+        self.ca_certificates = Some(path.as_ref().to_owned());
+        self
+    }
+
+    ///PEM files of the certificate chain and the private key presented to a server which asks
+    ///the client for a certificate
+    pub fn client_certificate(mut self, chain: impl AsRef<Path>, key: impl AsRef<Path>) -> Self {
+        self.client_certificate = Some((chain.as_ref().to_owned(), key.as_ref().to_owned()));
+        self
+    }
+
+    ///fails when a setting is missing, when the uri has an unknown scheme, or when the
+    ///certificates can't be read
     pub fn build(self) -> Result<Config> {
-        if self.uri.is_none()
-            || self.user.is_none()
-            || self.password.is_none()
-            || self.fetch_size.is_none()
-            || self.max_connections.is_none()
-            || self.db.is_none()
-        {
-            Err(Error::InvalidConfig)
-        } else {
-            //The config attributes are validated before unwrapping
-            Ok(Config {
-                uri: self.uri.unwrap(),
-                user: self.user.unwrap(),
-                password: self.password.unwrap(),
-                fetch_size: self.fetch_size.unwrap(),
-                max_connections: self.max_connections.unwrap(),
-                db: self.db.unwrap(),
-            })
+        match (
+            self.uri,
+            self.user,
+            self.password,
+            self.fetch_size,
+            self.max_connections,
+            self.db,
+            self.max_retry_time,
+        ) {
+            (
+                Some(uri),
+                Some(user),
+                Some(password),
+                Some(fetch_size),
+                Some(max_connections),
+                Some(db),
+                Some(max_retry_time),
+            ) => {
+                let (encryption, host, port) = parse_uri(&uri)?;
+                let client_certificate = self
+                    .client_certificate
+                    .as_ref()
+                    .map(|(chain, key)| (chain.as_path(), key.as_path()));
+                let tls = client_config(
+                    encryption,
+                    self.ca_certificates.as_deref(),
+                    client_certificate,
+                )?;
+                Ok(Config {
+                    user,
+                    password,
+                    fetch_size,
+                    max_connections,
+                    db,
+                    max_retry_time,
+                    address: Address { host, port, tls },
+                })
+            }
+            _ => Err(Error::InvalidConfig),
         }
     }
 }
@@ -95,6 +151,9 @@ pub fn config() -> ConfigBuilder {
         db: Some("".to_owned()),
         max_connections: Some(DEFAULT_MAX_CONNECTIONS),
         fetch_size: Some(DEFAULT_FETCH_SIZE),
+        max_retry_time: Some(DEFAULT_MAX_RETRY_TIME),
+        ca_certificates: None,
+        client_certificate: None,
     }
 }
 
@@ -111,14 +170,18 @@ mod tests {
             .db("some_db")
             .fetch_size(10)
             .max_connections(5)
+            .max_retry_time(Duration::from_secs(5))
             .build()
             .unwrap();
-        assert_eq!(config.uri, "127.0.0.1:7687");
+        assert_eq!(config.address.host, "127.0.0.1");
+        assert_eq!(config.address.port, 7687);
+        assert!(config.address.tls.is_none());
         assert_eq!(config.user, "some_user");
         assert_eq!(config.password, "some_password");
         assert_eq!(config.db, "some_db");
         assert_eq!(config.fetch_size, 10);
         assert_eq!(config.max_connections, 5);
+        assert_eq!(config.max_retry_time, Duration::from_secs(5));
     }
 
     #[tokio::test]
@@ -129,12 +192,15 @@ mod tests {
             .password("some_password")
             .build()
             .unwrap();
-        assert_eq!(config.uri, "127.0.0.1:7687");
+        assert_eq!(config.address.host, "127.0.0.1");
+        assert_eq!(config.address.port, 7687);
+        assert!(config.address.tls.is_none());
         assert_eq!(config.user, "some_user");
         assert_eq!(config.password, "some_password");
         assert_eq!(config.db, "");
         assert_eq!(config.fetch_size, 200);
         assert_eq!(config.max_connections, 16);
+        assert_eq!(config.max_retry_time, Duration::from_secs(30));
     }
 
     #[tokio::test]
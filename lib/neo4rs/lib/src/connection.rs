@@ -1,54 +1,120 @@
-use crate::errors::{unexpected, Error, Result};
+use crate::errors::{unexpected_response, Error, Result};
 use crate::messages::*;
+use crate::transport::{Address, Stream};
 use crate::version::Version;
 use bytes::*;
 use std::mem;
 use tokio::io::BufStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 const MAX_CHUNK_SIZE: usize = 65_535 - mem::size_of::<u16>();
 
+/// A single Bolt connection
+///
+/// A FAILURE leaves the server ignoring every request until it is RESET, so the connection resets
+/// itself as soon as one is received, which also rolls back the transaction in progress.
+/// A connection that failed to send or receive is broken for good, the pool discards it.
+///
+/// The pool uses plain and TLS streams alike, any other stream carrying Bolt will do as well.
 #[derive(Debug)]
-pub struct Connection {
+pub struct Connection<S = Stream> {
     version: Version,
-    stream: BufStream<TcpStream>,
+    stream: BufStream<S>,
+    broken: bool,
+    resets: usize,
 }
 
 impl Connection {
-    pub async fn new(uri: &str, user: &str, password: &str) -> Result<Connection> {
-        let mut stream = BufStream::new(TcpStream::connect(uri).await?);
+    pub(crate) async fn new(address: &Address, user: &str, password: &str) -> Result<Connection> {
+        let stream = Stream::connect(address).await?;
+        Connection::from_stream(stream, user, password).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    /// Does the handshake and says HELLO over a stream that is already connected
+    pub async fn from_stream(stream: S, user: &str, password: &str) -> Result<Connection<S>> {
+        let mut stream = BufStream::new(stream);
         stream.write_all(&[0x60, 0x60, 0xB0, 0x17]).await?;
         stream.write_all(&Version::supported_versions()).await?;
         stream.flush().await?;
         let mut response = [0, 0, 0, 0];
         stream.read_exact(&mut response).await?;
         let version = Version::parse(response)?;
-        let mut connection = Connection { version, stream };
+        let mut connection = Connection {
+            version,
+            stream,
+            broken: false,
+            resets: 0,
+        };
         let hello = BoltRequest::hello("neo4rs", user.to_owned(), password.to_owned());
-        match connection.send_recv(hello).await? {
+        // the server hangs up after a failed HELLO, there is nothing left to reset
+        connection.send(hello).await?;
+        match connection.recv_message().await? {
             BoltResponse::SuccessMessage(_msg) => Ok(connection),
             BoltResponse::FailureMessage(msg) => {
                 Err(Error::AuthenticationError(msg.get("message").unwrap()))
             }
 
-            msg => Err(unexpected(msg, "HELLO")),
+            msg => Err(unexpected_response(msg, "HELLO")),
         }
     }
 
     pub async fn reset(&mut self) -> Result<()> {
-        match self.send_recv(BoltRequest::reset()).await? {
-            BoltResponse::SuccessMessage(_) => Ok(()),
-            msg => Err(unexpected(msg, "RESET")),
+        self.send(BoltRequest::reset()).await?;
+        loop {
+            // requests sent after a FAILURE are answered with IGNORED before the RESET is
+            match self.recv_message().await? {
+                BoltResponse::IgnoredMessage(_) => continue,
+                BoltResponse::SuccessMessage(_) => return Ok(()),
+                msg => {
+                    self.broken = true;
+                    return Err(unexpected_response(msg, "RESET"));
+                }
+            }
         }
     }
 
+    /// Whether the connection can't be used anymore, see [`Connection`]
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// How many times the connection has been reset after a FAILURE, a transaction begun
+    /// before the last reset is over
+    pub fn resets(&self) -> usize {
+        self.resets
+    }
+
     pub async fn send_recv(&mut self, message: BoltRequest) -> Result<BoltResponse> {
         self.send(message).await?;
         self.recv().await
     }
 
     pub async fn send(&mut self, message: BoltRequest) -> Result<()> {
+        let result = self.send_message(message).await;
+        self.broken |= result.is_err();
+        result
+    }
+
+    /// Receives the next response, a FAILURE is returned once the connection has been reset
+    pub async fn recv(&mut self) -> Result<BoltResponse> {
+        let response = self.recv_message().await?;
+        if let BoltResponse::FailureMessage(_) = response {
+            self.resets += 1;
+            self.reset().await?;
+        }
+        Ok(response)
+    }
+
+    async fn recv_message(&mut self) -> Result<BoltResponse> {
+        // a message that can't be read leaves the stream somewhere in the middle of the next one
+        let result = self.read_message().await;
+        self.broken |= result.is_err();
+        result
+    }
+
+    async fn send_message(&mut self, message: BoltRequest) -> Result<()> {
         let end_marker: [u8; 2] = [0, 0];
         let bytes: Bytes = message.into_bytes(self.version)?;
         for c in bytes.chunks(MAX_CHUNK_SIZE) {
@@ -60,7 +126,7 @@ impl Connection {
         Ok(())
     }
 
-    pub async fn recv(&mut self) -> Result<BoltResponse> {
+    async fn read_message(&mut self) -> Result<BoltResponse> {
         let mut bytes = BytesMut::new();
         let mut chunk_size = 0;
         while chunk_size == 0 {
@@ -73,7 +139,7 @@ impl Connection {
             chunk_size = self.read_u16().await?;
         }
 
-        Ok(BoltResponse::parse(self.version, bytes.freeze())?)
+        BoltResponse::parse(self.version, bytes.freeze())
     }
 
     async fn read(&mut self, size: u16) -> Result<Vec<u8>> {
@@ -88,3 +154,60 @@ impl Connection {
         Ok(u16::from_be_bytes(data))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use crate::types::BoltMap;
+    use tokio::net::TcpStream;
+
+    const SYNTAX_ERROR: &str = "Neo.ClientError.Statement.SyntaxError";
+
+    fn run() -> BoltRequest {
+        BoltRequest::run("neo4j", "RETRUN 1", BoltMap::default())
+    }
+
+    #[tokio::test]
+    async fn should_reset_after_failure_and_skip_ignored_requests() {
+        let server = StubServer::start(
+            Script::new()
+                .expect(Expect::run("RETRUN 1"))
+                .reply(Reply::failure(SYNTAX_ERROR, "Invalid input 'RETRUN'"))
+                .expect(Expect::run("RETRUN 1"))
+                .reply(Reply::ignored())
+                .expect(Expect::reset())
+                .reply(Reply::success()),
+        )
+        .await;
+        let stream = TcpStream::connect(server.uri()).await.unwrap();
+        let mut connection = Connection::from_stream(stream, "neo4j", "secret")
+            .await
+            .unwrap();
+
+        connection.send(run()).await.unwrap();
+        connection.send(run()).await.unwrap();
+        match connection.recv().await.unwrap() {
+            BoltResponse::FailureMessage(failure) => {
+                assert_eq!(failure.get::<String>("code").unwrap(), SYNTAX_ERROR)
+            }
+            msg => panic!("expected a failure, got {:?}", msg),
+        }
+        server.finish();
+        assert_eq!(connection.resets(), 1);
+        assert!(!connection.is_broken());
+    }
+
+    #[tokio::test]
+    async fn should_break_when_the_server_hangs_up() {
+        let server = StubServer::start(Script::new()).await;
+        let stream = TcpStream::connect(server.uri()).await.unwrap();
+        let mut connection = Connection::from_stream(stream, "neo4j", "secret")
+            .await
+            .unwrap();
+        drop(server);
+
+        assert!(connection.send_recv(run()).await.is_err());
+        assert!(connection.is_broken());
+    }
+}
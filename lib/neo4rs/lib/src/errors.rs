@@ -1,3 +1,5 @@
+use crate::messages::BoltResponse;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -17,6 +19,81 @@ pub enum Error {
     AuthenticationError(String),
     InvalidTypeMarker(String),
     DeserializationError(String),
+    /// A certificate or key that can't be used, or a host name that can't be verified
+    TlsError(String),
+    /// A FAILURE sent by the server, `code` looks like `Neo.ClientError.Statement.SyntaxError`
+    /// and is split into its classification, category and title
+    Neo4j {
+        code: String,
+        message: String,
+        classification: String,
+        category: String,
+        title: String,
+    },
+}
+
+impl Error {
+    pub fn neo4j(code: String, message: String) -> Error {
+        let mut parts = code.split('.').skip(1).map(str::to_owned);
+        let classification = parts.next().unwrap_or_default();
+        let category = parts.next().unwrap_or_default();
+        let title = parts.next().unwrap_or_default();
+        Error::Neo4j {
+            code,
+            message,
+            classification,
+            category,
+            title,
+        }
+    }
+
+    /// Whether the same work may succeed when tried again, like after a deadlock
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Neo4j {
+                code,
+                classification,
+                ..
+            } => match classification.as_str() {
+                // terminated on purpose by a user, trying again would defeat that
+                "TransientError" => !matches!(
+                    code.as_str(),
+                    "Neo.TransientError.Transaction.Terminated"
+                        | "Neo.TransientError.Transaction.LockClientStopped"
+                ),
+                // the leader moved, the next attempt may reach it
+                "ClientError" => matches!(
+                    code.as_str(),
+                    "Neo.ClientError.Cluster.NotALeader"
+                        | "Neo.ClientError.General.ForbiddenOnReadOnlyDatabase"
+                ),
+                _ => false,
+            },
+            Error::IOError { .. } | Error::ConnectionError => true,
+            _ => false,
+        }
+    }
+
+    /// A uniqueness or existence constraint rejected the write
+    pub fn is_constraint_violation(&self) -> bool {
+        matches!(self, Error::Neo4j { code, .. } if code == "Neo.ClientError.Schema.ConstraintValidationFailed")
+    }
+
+    pub fn is_syntax_error(&self) -> bool {
+        matches!(self, Error::Neo4j { code, .. } if code == "Neo.ClientError.Statement.SyntaxError")
+    }
+}
+
+/// The error of the work given to [`Graph::write_transaction`] and [`Graph::read_transaction`],
+/// the work is tried again when it fails with a retryable error
+pub trait TransactionError: From<Error> {
+    fn is_retryable(&self) -> bool;
+}
+
+impl TransactionError for Error {
+    fn is_retryable(&self) -> bool {
+        Error::is_retryable(self)
+    }
 }
 
 impl std::convert::From<std::io::Error> for Error {
@@ -42,3 +119,106 @@ pub fn unexpected<T: std::fmt::Debug>(response: T, request: &str) -> Error {
         request, response
     ))
 }
+
+/// A FAILURE becomes an [`Error::Neo4j`], any other response an [`Error::UnexpectedMessage`]
+pub(crate) fn unexpected_response(response: BoltResponse, request: &str) -> Error {
+    match response {
+        BoltResponse::FailureMessage(failure) => Error::neo4j(
+            failure.get("code").unwrap_or_default(),
+            failure.get("message").unwrap_or_default(),
+        ),
+        BoltResponse::IgnoredMessage(_) => Error::UnexpectedMessage(format!(
+            "{} was ignored by the server after a failure",
+            request
+        )),
+        msg => unexpected(msg, request),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::Version;
+    use bytes::Bytes;
+
+    fn parse_failure(data: &'static [u8]) -> Error {
+        let response = BoltResponse::parse(Version::V4_1, Bytes::from_static(data)).unwrap();
+        unexpected_response(response, "RUN")
+    }
+
+    #[test]
+    fn should_parse_constraint_violation() {
+        let error = parse_failure(&[
+            0xB1, 0x7F, 0xA2, 0x84, 0x63, 0x6F, 0x64, 0x65, 0xD0, 0x31, 0x4E, 0x65, 0x6F, 0x2E,
+            0x43, 0x6C, 0x69, 0x65, 0x6E, 0x74, 0x45, 0x72, 0x72, 0x6F, 0x72, 0x2E, 0x53, 0x63,
+            0x68, 0x65, 0x6D, 0x61, 0x2E, 0x43, 0x6F, 0x6E, 0x73, 0x74, 0x72, 0x61, 0x69, 0x6E,
+            0x74, 0x56, 0x61, 0x6C, 0x69, 0x64, 0x61, 0x74, 0x69, 0x6F, 0x6E, 0x46, 0x61, 0x69,
+            0x6C, 0x65, 0x64, 0x87, 0x6D, 0x65, 0x73, 0x73, 0x61, 0x67, 0x65, 0xD0, 0x47, 0x4E,
+            0x6F, 0x64, 0x65, 0x28, 0x30, 0x29, 0x20, 0x61, 0x6C, 0x72, 0x65, 0x61, 0x64, 0x79,
+            0x20, 0x65, 0x78, 0x69, 0x73, 0x74, 0x73, 0x20, 0x77, 0x69, 0x74, 0x68, 0x20, 0x6C,
+            0x61, 0x62, 0x65, 0x6C, 0x20, 0x60, 0x55, 0x73, 0x65, 0x72, 0x60, 0x20, 0x61, 0x6E,
+            0x64, 0x20, 0x70, 0x72, 0x6F, 0x70, 0x65, 0x72, 0x74, 0x79, 0x20, 0x60, 0x65, 0x6D,
+            0x61, 0x69, 0x6C, 0x60, 0x20, 0x3D, 0x20, 0x27, 0x61, 0x40, 0x62, 0x2E, 0x63, 0x27,
+        ]);
+
+        match &error {
+            Error::Neo4j {
+                code,
+                message,
+                classification,
+                category,
+                title,
+            } => {
+                assert_eq!(code, "Neo.ClientError.Schema.ConstraintValidationFailed");
+                assert_eq!(
+                    message,
+                    "Node(0) already exists with label `User` and property `email` = 'a@b.c'"
+                );
+                assert_eq!(classification, "ClientError");
+                assert_eq!(category, "Schema");
+                assert_eq!(title, "ConstraintValidationFailed");
+            }
+            e => panic!("expected a Neo4j error, got {:?}", e),
+        }
+        assert!(error.is_constraint_violation());
+        assert!(!error.is_syntax_error());
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn should_parse_syntax_error() {
+        let error = parse_failure(&[
+            0xB1, 0x7F, 0xA2, 0x84, 0x63, 0x6F, 0x64, 0x65, 0xD0, 0x25, 0x4E, 0x65, 0x6F, 0x2E,
+            0x43, 0x6C, 0x69, 0x65, 0x6E, 0x74, 0x45, 0x72, 0x72, 0x6F, 0x72, 0x2E, 0x53, 0x74,
+            0x61, 0x74, 0x65, 0x6D, 0x65, 0x6E, 0x74, 0x2E, 0x53, 0x79, 0x6E, 0x74, 0x61, 0x78,
+            0x45, 0x72, 0x72, 0x6F, 0x72, 0x87, 0x6D, 0x65, 0x73, 0x73, 0x61, 0x67, 0x65, 0xD0,
+            0x16, 0x49, 0x6E, 0x76, 0x61, 0x6C, 0x69, 0x64, 0x20, 0x69, 0x6E, 0x70, 0x75, 0x74,
+            0x20, 0x27, 0x52, 0x45, 0x54, 0x52, 0x55, 0x4E, 0x27,
+        ]);
+
+        assert!(error.is_syntax_error());
+        assert!(!error.is_constraint_violation());
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn should_parse_transient_error() {
+        let error = parse_failure(&[
+            0xB1, 0x7F, 0xA2, 0x84, 0x63, 0x6F, 0x64, 0x65, 0xD0, 0x2F, 0x4E, 0x65, 0x6F, 0x2E,
+            0x54, 0x72, 0x61, 0x6E, 0x73, 0x69, 0x65, 0x6E, 0x74, 0x45, 0x72, 0x72, 0x6F, 0x72,
+            0x2E, 0x54, 0x72, 0x61, 0x6E, 0x73, 0x61, 0x63, 0x74, 0x69, 0x6F, 0x6E, 0x2E, 0x44,
+            0x65, 0x61, 0x64, 0x6C, 0x6F, 0x63, 0x6B, 0x44, 0x65, 0x74, 0x65, 0x63, 0x74, 0x65,
+            0x64, 0x87, 0x6D, 0x65, 0x73, 0x73, 0x61, 0x67, 0x65, 0xD0, 0x2C, 0x46, 0x6F, 0x72,
+            0x73, 0x65, 0x74, 0x69, 0x43, 0x6C, 0x69, 0x65, 0x6E, 0x74, 0x5B, 0x33, 0x5D, 0x20,
+            0x63, 0x61, 0x6E, 0x27, 0x74, 0x20, 0x61, 0x63, 0x71, 0x75, 0x69, 0x72, 0x65, 0x20,
+            0x45, 0x78, 0x63, 0x6C, 0x75, 0x73, 0x69, 0x76, 0x65, 0x4C, 0x6F, 0x63, 0x6B,
+        ]);
+
+        assert!(error.is_retryable());
+        let terminated = Error::neo4j(
+            "Neo.TransientError.Transaction.Terminated".to_owned(),
+            String::new(),
+        );
+        assert!(!terminated.is_retryable());
+    }
+}
@@ -1,18 +1,38 @@
 use crate::config::{config, Config};
 use crate::errors::*;
+use crate::messages::BoltRequest;
 use crate::pool::{create_pool, ConnectionPool};
 use crate::query::Query;
 use crate::stream::RowStream;
 use crate::txn::Txn;
+use futures::future::BoxFuture;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const RETRY_DELAY_MULTIPLIER: u32 = 2;
+const RETRY_DELAY_JITTER: f64 = 0.2;
+
 /// A neo4j database abstraction
 pub struct Graph {
     config: Config,
     pool: ConnectionPool,
 }
 
+/// A snapshot of the connection pool, see [`Graph::pool_status`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolStatus {
+    /// The maximum number of connections the pool will open
+    pub max_size: usize,
+    /// The number of connections currently opened
+    pub size: usize,
+    /// The number of idle connections, negative when callers are waiting for one
+    pub available: isize,
+}
+
 /// Returns a [`Query`] which provides methods like [`Query::param`] to add parameters to the query
 pub fn query(q: &str) -> Query {
     Query::new(q.to_owned())
@@ -36,7 +56,93 @@ impl Graph {
     /// should be executed using either [`Txn::run`] or [`Txn::execute`]
     pub async fn start_txn(&self) -> Result<Txn> {
         let connection = self.pool.get().await?;
-        Txn::new(self.config.clone(), connection).await
+        Txn::new(self.config.clone(), connection, BoltRequest::begin()).await
+    }
+
+    /// Runs `work` in a transaction which is committed when the work succeeds and rolled back when
+    /// it fails
+    ///
+    /// When the work or the commit fails with a retryable error, like a deadlock or a lost
+    /// connection, the whole work is tried again in a new transaction after an exponential
+    /// backoff, until [`ConfigBuilder::max_retry_time`] has passed. The work may therefore run
+    /// more than once and shouldn't have side effects outside of the transaction.
+    ///
+    /// ```no_run
+    /// # use neo4rs::*;
+    /// # async fn example(graph: Graph) -> Result<()> {
+    /// graph
+    ///     .write_transaction(|txn| {
+    ///         Box::pin(async move { txn.run(query("CREATE (p:Person)")).await })
+    ///     })
+    ///     .await
+    /// # }
+    /// ```
+    pub async fn write_transaction<T, E, F>(&self, work: F) -> std::result::Result<T, E>
+    where
+        F: for<'a> FnMut(&'a Txn) -> BoxFuture<'a, std::result::Result<T, E>>,
+        E: TransactionError,
+    {
+        self.retry_transaction(BoltRequest::begin, work).await
+    }
+
+    /// Like [`Graph::write_transaction`], the transaction is started in read mode so that a
+    /// cluster can run it on a follower
+    pub async fn read_transaction<T, E, F>(&self, work: F) -> std::result::Result<T, E>
+    where
+        F: for<'a> FnMut(&'a Txn) -> BoxFuture<'a, std::result::Result<T, E>>,
+        E: TransactionError,
+    {
+        self.retry_transaction(BoltRequest::begin_read, work).await
+    }
+
+    async fn retry_transaction<T, E, F>(
+        &self,
+        begin: fn() -> BoltRequest,
+        mut work: F,
+    ) -> std::result::Result<T, E>
+    where
+        F: for<'a> FnMut(&'a Txn) -> BoxFuture<'a, std::result::Result<T, E>>,
+        E: TransactionError,
+    {
+        let started = Instant::now();
+        let mut delay = INITIAL_RETRY_DELAY;
+        loop {
+            let error = match self.try_transaction(begin(), &mut work).await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            let wait = jitter(delay);
+            if !error.is_retryable() || started.elapsed() + wait > self.config.max_retry_time {
+                return Err(error);
+            }
+            tracing::warn!(delay = ?wait, "retrying a transaction after a transient error");
+            tokio::time::sleep(wait).await;
+            delay *= RETRY_DELAY_MULTIPLIER;
+        }
+    }
+
+    async fn try_transaction<T, E, F>(
+        &self,
+        begin: BoltRequest,
+        work: &mut F,
+    ) -> std::result::Result<T, E>
+    where
+        F: for<'a> FnMut(&'a Txn) -> BoxFuture<'a, std::result::Result<T, E>>,
+        E: TransactionError,
+    {
+        let connection = self.pool.get().await.map_err(Error::from)?;
+        let txn = Txn::new(self.config.clone(), connection, begin).await?;
+        match work(&txn).await {
+            Ok(value) => {
+                txn.commit().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                // the work already failed, a failed rollback leaves the connection to the pool
+                let _ = txn.rollback().await;
+                Err(e)
+            }
+        }
     }
 
     /// Runs a query using a connection from the connection pool, it doesn't return any
@@ -55,4 +161,149 @@ impl Graph {
         let connection = Arc::new(Mutex::new(self.pool.get().await?));
         q.execute(&self.config, connection).await
     }
+
+    /// Returns the current size and availability of the connection pool
+    pub fn pool_status(&self) -> PoolStatus {
+        let status = self.pool.status();
+        PoolStatus {
+            max_size: status.max_size,
+            size: status.size,
+            available: status.available,
+        }
+    }
+}
+
+/// Spreads `delay` by up to [`RETRY_DELAY_JITTER`] either way, so that transactions which
+/// deadlocked on each other don't retry in lockstep
+fn jitter(delay: Duration) -> Duration {
+    // a fresh RandomState is randomly keyed, which is random enough here
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    delay.mul_f64(1.0 - RETRY_DELAY_JITTER + 2.0 * RETRY_DELAY_JITTER * random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const DEADLOCK: &str = "Neo.TransientError.Transaction.DeadlockDetected";
+
+    async fn connect(server: &StubServer, max_retry_time: Duration) -> Graph {
+        let config = config()
+            .uri(server.uri())
+            .user("neo4j")
+            .password("secret")
+            .max_retry_time(max_retry_time)
+            .build()
+            .unwrap();
+        Graph::connect(config).await.unwrap()
+    }
+
+    async fn create(graph: &Graph, attempts: &AtomicUsize) -> Result<()> {
+        graph
+            .write_transaction(|txn| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Box::pin(async move { txn.run(query("CREATE (n)")).await })
+            })
+            .await
+    }
+
+    fn deadlock() -> Script {
+        Script::new()
+            .expect(Expect::begin())
+            .reply(Reply::success())
+            .expect(Expect::run("CREATE (n)"))
+            .reply(Reply::failure(DEADLOCK, "ForsetiClient can't acquire ExclusiveLock"))
+            .expect(Expect::reset())
+            .reply(Reply::success())
+    }
+
+    #[tokio::test]
+    async fn should_retry_transaction_after_deadlock() {
+        let server = StubServer::start(
+            deadlock()
+                // recycled by the pool
+                .expect(Expect::reset())
+                .reply(Reply::success())
+                .expect(Expect::begin())
+                .reply(Reply::success())
+                .expect(Expect::run("CREATE (n)"))
+                .reply(Reply::success())
+                .expect(Expect::discard())
+                .reply(Reply::success())
+                .expect(Expect::commit())
+                .reply(Reply::success()),
+        )
+        .await;
+        let graph = connect(&server, Duration::from_secs(30)).await;
+        let attempts = AtomicUsize::new(0);
+
+        create(&graph, &attempts).await.unwrap();
+        server.finish();
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_and_not_retry_client_errors() {
+        let server = StubServer::start(
+            Script::new()
+                .expect(Expect::begin())
+                .reply(Reply::success())
+                .expect(Expect::run("CREATE (n)"))
+                .reply(Reply::failure(
+                    "Neo.ClientError.Statement.SyntaxError",
+                    "Invalid input",
+                ))
+                .expect(Expect::reset())
+                .reply(Reply::success()),
+        )
+        .await;
+        let graph = connect(&server, Duration::from_secs(30)).await;
+        let attempts = AtomicUsize::new(0);
+
+        assert!(create(&graph, &attempts).await.unwrap_err().is_syntax_error());
+        server.finish();
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_give_up_after_max_retry_time() {
+        let server = StubServer::start(deadlock()).await;
+        let graph = connect(&server, Duration::from_millis(500)).await;
+        let attempts = AtomicUsize::new(0);
+
+        assert!(create(&graph, &attempts).await.unwrap_err().is_retryable());
+        server.finish();
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_when_the_work_fails() {
+        let server = StubServer::start(
+            Script::new()
+                .expect(Expect::begin())
+                .reply(Reply::success())
+                .expect(Expect::rollback())
+                .reply(Reply::success()),
+        )
+        .await;
+        let graph = connect(&server, Duration::from_secs(30)).await;
+
+        let result: Result<()> = graph
+            .read_transaction(|_| {
+                Box::pin(async { Err(Error::UnexpectedMessage("no rows".to_owned())) })
+            })
+            .await;
+        assert!(matches!(result, Err(Error::UnexpectedMessage(_))));
+        server.finish();
+    }
+
+    #[test]
+    fn should_jitter_retry_delay() {
+        for _ in 0..100 {
+            let delay = jitter(Duration::from_secs(1));
+            assert!(delay >= Duration::from_millis(800) && delay <= Duration::from_millis(1200));
+        }
+    }
 }
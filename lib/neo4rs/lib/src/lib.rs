@@ -4,6 +4,9 @@
 //! * async/await apis using [tokio][tokio]
 //! * Supports bolt 4.2 specification
 //! * tested with Neo4j versions: 4.0, 4.1, 4.2
+//! * TLS with `bolt+s://` uris, or `bolt+ssc://` to accept self-signed certificates
+//! * a scripted Bolt server to test against without a database, see `testing` (behind the
+//!   `testing` feature)
 //!
 //!
 //! [bolt]: https://7687.org/
@@ -499,7 +502,7 @@
 //!
 //! * [NaiveTime][naive_time] captures only the time of the day
 //! * `tuple`([NaiveTime][naive_time], `Option`<[FixedOffset][fixed_offset]>) captures the time of the day along with the
-//! offset
+//!   offset
 //!
 //! [naive_time]: https://docs.rs/chrono/0.4.19/chrono/naive/struct.NaiveTime.html
 //! [fixed_offset]: https://docs.rs/chrono/0.4.19/chrono/offset/struct.FixedOffset.html
@@ -759,13 +762,16 @@ mod pool;
 mod query;
 mod row;
 mod stream;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod transport;
 mod txn;
 mod types;
 mod version;
 
 pub use crate::config::{config, Config, ConfigBuilder};
 pub use crate::errors::*;
-pub use crate::graph::{query, Graph};
+pub use crate::graph::{query, Graph, PoolStatus};
 pub use crate::query::Query;
 pub use crate::row::{Node, Path, Point2D, Point3D, Relation, Row, UnboundedRelation};
 pub use crate::stream::RowStream;
@@ -4,6 +4,7 @@ mod commit;
 mod discard;
 mod failure;
 mod hello;
+mod ignored;
 mod pull;
 mod record;
 mod reset;
@@ -19,6 +20,7 @@ use commit::Commit;
 use discard::Discard;
 use failure::Failure;
 use hello::Hello;
+use ignored::Ignored;
 use pull::Pull;
 use record::Record;
 use reset::Reset;
@@ -28,13 +30,16 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use success::Success;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum BoltResponse {
     SuccessMessage(Success),
     FailureMessage(Failure),
     RecordMessage(Record),
+    IgnoredMessage(Ignored),
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum BoltRequest {
     HelloMessage(Hello),
@@ -73,6 +78,13 @@ impl BoltRequest {
         BoltRequest::BeginMessage(Begin::new(BoltMap::default()))
     }
 
+    /// A BEGIN that lets a cluster route the transaction to a follower
+    pub fn begin_read() -> BoltRequest {
+        BoltRequest::BeginMessage(Begin::new(
+            vec![("mode".into(), "r".into())].into_iter().collect(),
+        ))
+    }
+
     pub fn commit() -> BoltRequest {
         BoltRequest::CommitMessage(Commit::new())
     }
@@ -100,6 +112,68 @@ impl BoltRequest {
         };
         Ok(bytes)
     }
+
+    /// The other end of [`BoltRequest::into_bytes`], used by the stub server in `testing`
+    #[cfg(any(test, feature = "testing"))]
+    pub fn parse(version: Version, request: Bytes) -> Result<BoltRequest> {
+        match Rc::new(RefCell::new(request)) {
+            input if Hello::can_parse(version, input.clone()) => {
+                Ok(BoltRequest::HelloMessage(Hello::parse(version, input)?))
+            }
+            input if Run::can_parse(version, input.clone()) => {
+                Ok(BoltRequest::RunMessage(Run::parse(version, input)?))
+            }
+            input if Pull::can_parse(version, input.clone()) => {
+                Ok(BoltRequest::PullMessage(Pull::parse(version, input)?))
+            }
+            input if Discard::can_parse(version, input.clone()) => Ok(
+                BoltRequest::DiscardMessage(Discard::parse(version, input)?),
+            ),
+            input if Begin::can_parse(version, input.clone()) => {
+                Ok(BoltRequest::BeginMessage(Begin::parse(version, input)?))
+            }
+            input if Commit::can_parse(version, input.clone()) => {
+                Ok(BoltRequest::CommitMessage(Commit::parse(version, input)?))
+            }
+            input if Rollback::can_parse(version, input.clone()) => Ok(
+                BoltRequest::RollbackMessage(Rollback::parse(version, input)?),
+            ),
+            input if Reset::can_parse(version, input.clone()) => {
+                Ok(BoltRequest::ResetMessage(Reset::parse(version, input)?))
+            }
+            msg => Err(Error::UnknownMessage(format!("unknown message {:?}", msg))),
+        }
+    }
+}
+
+// the server side of the protocol, used by the stub server in `testing`
+#[cfg(any(test, feature = "testing"))]
+impl BoltResponse {
+    pub fn success(metadata: BoltMap) -> BoltResponse {
+        BoltResponse::SuccessMessage(Success::new(metadata))
+    }
+
+    pub fn failure(metadata: BoltMap) -> BoltResponse {
+        BoltResponse::FailureMessage(Failure::new(metadata))
+    }
+
+    pub fn record(data: BoltList) -> BoltResponse {
+        BoltResponse::RecordMessage(Record::new(data))
+    }
+
+    pub fn ignored() -> BoltResponse {
+        BoltResponse::IgnoredMessage(Ignored)
+    }
+
+    pub fn into_bytes(self, version: Version) -> Result<Bytes> {
+        let bytes: Bytes = match self {
+            BoltResponse::SuccessMessage(success) => success.into_bytes(version)?,
+            BoltResponse::FailureMessage(failure) => failure.into_bytes(version)?,
+            BoltResponse::RecordMessage(record) => record.into_bytes(version)?,
+            BoltResponse::IgnoredMessage(ignored) => ignored.into_bytes(version)?,
+        };
+        Ok(bytes)
+    }
 }
 
 impl BoltResponse {
@@ -114,6 +188,9 @@ impl BoltResponse {
             input if Record::can_parse(version, input.clone()) => {
                 Ok(BoltResponse::RecordMessage(Record::parse(version, input)?))
             }
+            input if Ignored::can_parse(version, input.clone()) => Ok(
+                BoltResponse::IgnoredMessage(Ignored::parse(version, input)?),
+            ),
             msg => Err(Error::UnknownMessage(format!("unknown message {:?}", msg))),
         }
     }
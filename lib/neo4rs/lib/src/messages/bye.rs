@@ -2,6 +2,7 @@ use neo4rs_macros::BoltStruct;
 
 #[derive(Debug, PartialEq, Eq, Clone, BoltStruct)]
 #[signature(0xB0, 0x02)]
+#[allow(dead_code)]
 pub struct Bye;
 
 #[cfg(test)]
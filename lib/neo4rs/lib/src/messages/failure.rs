@@ -8,6 +8,11 @@ pub struct Failure {
 }
 
 impl Failure {
+    #[cfg(any(test, feature = "testing"))]
+    pub fn new(metadata: BoltMap) -> Failure {
+        Failure { metadata }
+    }
+
     pub fn get<T: std::convert::TryFrom<BoltType>>(&self, key: &str) -> Option<T> {
         self.metadata.get(key)
     }
@@ -7,6 +7,13 @@ pub struct Record {
     pub data: BoltList,
 }
 
+impl Record {
+    #[cfg(any(test, feature = "testing"))]
+    pub fn new(data: BoltList) -> Record {
+        Record { data }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
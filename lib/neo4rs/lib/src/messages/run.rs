@@ -4,8 +4,8 @@ use neo4rs_macros::BoltStruct;
 #[derive(Debug, PartialEq, Clone, BoltStruct)]
 #[signature(0xB1, 0x10)]
 pub struct Run {
-    query: BoltString,
-    parameters: BoltMap,
+    pub query: BoltString,
+    pub parameters: BoltMap,
     extra: BoltMap,
 }
 
@@ -82,12 +82,12 @@ mod tests {
                 b'e',
                 b'r',
                 b'y',
-                map::TINY | 0,
+                map::TINY,
                 map::TINY | 1,
                 string::TINY | 2,
                 b'd',
                 b'b',
-                string::TINY | 0,
+                string::TINY,
             ])
         );
     }
@@ -8,6 +8,11 @@ pub struct Success {
 }
 
 impl Success {
+    #[cfg(any(test, feature = "testing"))]
+    pub fn new(metadata: BoltMap) -> Success {
+        Success { metadata }
+    }
+
     pub fn get<T: std::convert::TryFrom<BoltType>>(&self, key: &str) -> Option<T> {
         self.metadata.get(key)
     }
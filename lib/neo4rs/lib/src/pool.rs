@@ -1,6 +1,7 @@
 use crate::config::Config;
 use crate::connection::Connection;
 use crate::errors::Error;
+use crate::transport::Address;
 use async_trait::async_trait;
 use log::info;
 
@@ -8,15 +9,15 @@ pub type ConnectionPool = deadpool::managed::Pool<Connection, Error>;
 pub type ManagedConnection = deadpool::managed::Object<Connection, Error>;
 
 pub struct ConnectionManager {
-    uri: String,
+    address: Address,
     user: String,
     password: String,
 }
 
 impl ConnectionManager {
-    pub fn new(uri: &str, user: &str, password: &str) -> ConnectionManager {
+    pub(crate) fn new(address: &Address, user: &str, password: &str) -> ConnectionManager {
         ConnectionManager {
-            uri: uri.to_owned(),
+            address: address.clone(),
             user: user.to_owned(),
             password: password.to_owned(),
         }
@@ -27,16 +28,21 @@ impl ConnectionManager {
 impl deadpool::managed::Manager<Connection, Error> for ConnectionManager {
     async fn create(&self) -> std::result::Result<Connection, Error> {
         info!("creating new connection...");
-        Connection::new(&self.uri, &self.user, &self.password).await
+        Connection::new(&self.address, &self.user, &self.password).await
     }
 
     async fn recycle(&self, conn: &mut Connection) -> deadpool::managed::RecycleResult<Error> {
+        if conn.is_broken() {
+            return Err(deadpool::managed::RecycleError::Message(
+                "connection is broken".to_owned(),
+            ));
+        }
         Ok(conn.reset().await?)
     }
 }
 
 pub async fn create_pool(config: &Config) -> ConnectionPool {
-    let mgr = ConnectionManager::new(&config.uri, &config.user, &config.password);
+    let mgr = ConnectionManager::new(&config.address, &config.user, &config.password);
     info!(
         "creating connection pool with max size {}",
         config.max_connections
@@ -5,7 +5,9 @@ use crate::pool::*;
 use crate::stream::*;
 use crate::types::*;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::Mutex;
+use tracing::Instrument;
 
 /// Abstracts a cypher query that is sent to neo4j server.
 #[derive(Clone)]
@@ -27,22 +29,57 @@ impl Query {
         self
     }
 
+    /// A span carrying the query text and the parameter names, parameter values are never recorded
+    fn span(&self) -> tracing::Span {
+        tracing::info_span!("cypher", query = %self.text(), params = ?self.param_names())
+    }
+
+    /// The query on a single line, indentation collapsed
+    fn text(&self) -> String {
+        self.query.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Records how long the server took to accept the query in `neo4rs_query_duration_seconds`
+    fn observe<T>(operation: &'static str, started: Instant, result: &Result<T>) {
+        let status = if result.is_ok() { "ok" } else { "error" };
+        metrics::histogram!(
+            "neo4rs_query_duration_seconds",
+            "operation" => operation,
+            "status" => status
+        )
+        .record(started.elapsed().as_secs_f64());
+    }
+
+    fn param_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.params.value.keys().map(|k| k.value.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
     pub(crate) async fn run(
         self,
         config: &Config,
         connection: Arc<Mutex<ManagedConnection>>,
     ) -> Result<()> {
-        let run = BoltRequest::run(&config.db, &self.query, self.params.clone());
-        let mut connection = connection.lock().await;
-        match connection.send_recv(run).await? {
-            BoltResponse::SuccessMessage(_) => {
-                match connection.send_recv(BoltRequest::discard()).await? {
-                    BoltResponse::SuccessMessage(_) => Ok(()),
-                    msg => Err(unexpected(msg, "DISCARD")),
+        let span = self.span();
+        let started = Instant::now();
+        let result = async move {
+            let run = BoltRequest::run(&config.db, &self.query, self.params.clone());
+            let mut connection = connection.lock().await;
+            match connection.send_recv(run).await? {
+                BoltResponse::SuccessMessage(_) => {
+                    match connection.send_recv(BoltRequest::discard()).await? {
+                        BoltResponse::SuccessMessage(_) => Ok(()),
+                        msg => Err(unexpected_response(msg, "DISCARD")),
+                    }
                 }
+                msg => Err(unexpected_response(msg, "RUN")),
             }
-            msg => Err(unexpected(msg, "RUN")),
         }
+        .instrument(span)
+        .await;
+        Self::observe("run", started, &result);
+        result
     }
 
     pub(crate) async fn execute(
@@ -50,19 +87,42 @@ impl Query {
         config: &Config,
         connection: Arc<Mutex<ManagedConnection>>,
     ) -> Result<RowStream> {
-        let run = BoltRequest::run(&config.db, &self.query, self.params);
-        match connection.lock().await.send_recv(run).await {
-            Ok(BoltResponse::SuccessMessage(success)) => {
-                let fields: BoltList = success.get("fields").unwrap_or_else(BoltList::new);
-                let qid: i64 = success.get("qid").unwrap_or(-1);
-                Ok(RowStream::new(
-                    qid,
-                    fields,
-                    config.fetch_size,
-                    connection.clone(),
-                ))
+        let span = self.span();
+        let started = Instant::now();
+        let result = async move {
+            let run = BoltRequest::run(&config.db, &self.query, self.params);
+            match connection.lock().await.send_recv(run).await {
+                Ok(BoltResponse::SuccessMessage(success)) => {
+                    let fields: BoltList = success.get("fields").unwrap_or_else(BoltList::new);
+                    let qid: i64 = success.get("qid").unwrap_or(-1);
+                    Ok(RowStream::new(
+                        qid,
+                        fields,
+                        config.fetch_size,
+                        connection.clone(),
+                    ))
+                }
+                Ok(msg) => Err(unexpected_response(msg, "RUN")),
+                Err(e) => Err(e),
             }
-            msg => Err(unexpected(msg, "RUN")),
         }
+        .instrument(span)
+        .await;
+        Self::observe("execute", started, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_describe_query_without_values() {
+        let q = Query::new("MATCH (n)\n    WHERE n.name = $name\n    RETURN n".to_owned())
+            .param("name", "secret")
+            .param("age", 42);
+        assert_eq!(q.text(), "MATCH (n) WHERE n.name = $name RETURN n");
+        assert_eq!(q.param_names(), vec!["age", "name"]);
     }
 }
@@ -12,6 +12,8 @@ use tokio::sync::Mutex;
 ///
 /// A stream will contain a connection from the connection pool which will be released to the pool
 /// when the stream is dropped.
+///
+/// Every row received from the server is counted in `neo4rs_rows_streamed_total`.
 pub struct RowStream {
     qid: i64,
     fields: BoltList,
@@ -67,10 +69,12 @@ impl RowStream {
                         }
                     }
                     Ok(BoltResponse::RecordMessage(record)) => {
+                        metrics::counter!("neo4rs_rows_streamed_total").increment(1);
                         let row = Row::new(self.fields.clone(), record.data);
                         self.buffer.push_back(row);
                     }
-                    msg => return Err(unexpected(msg, "PULL")),
+                    Ok(msg) => return Err(unexpected_response(msg, "PULL")),
+                    Err(e) => return Err(e),
                 },
                 State::Buffered => {
                     if !self.buffer.is_empty() {
@@ -0,0 +1,630 @@
+//! A Bolt server that plays back a script, to test code using the driver without a database
+//!
+//! Enabled by the `testing` feature. The server accepts connections on a local port, one after
+//! the other. It does the handshake and answers HELLO by itself, then expects the scripted
+//! requests in order and answers each of them with its scripted replies. [`Graph`], [`Txn`] and
+//! [`RowStream`] are used as usual, only the uri points to the stub:
+//!
+//! ```
+//! use neo4rs::testing::{Expect, Script, StubServer};
+//! use neo4rs::*;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let server = StubServer::start(
+//!     Script::new()
+//!         .expect(Expect::run("MATCH (p:Person) WHERE p.name = $name RETURN p.age AS age").param("name", "Alice"))
+//!         .returns(&["age"], vec![vec![42i64.into()]]),
+//! )
+//! .await;
+//! let graph = Graph::new(server.uri(), "neo4j", "secret").await.unwrap();
+//!
+//! let q = query("MATCH (p:Person) WHERE p.name = $name RETURN p.age AS age").param("name", "Alice");
+//! let mut result = graph.execute(q).await.unwrap();
+//! let row = result.next().await.unwrap().unwrap();
+//! assert_eq!(row.get::<i64>("age"), Some(42));
+//! server.finish();
+//! # }
+//! ```
+//!
+//! A request that doesn't match the script is answered with a FAILURE describing the mismatch,
+//! and so is every request after it but RESET. [`StubServer::finish`] panics with the mismatch,
+//! or when part of the script was never played.
+//!
+//! Remember that the pool resets a connection every time it hands it out again, so a script
+//! running more than one transaction or query through a [`Graph`] expects a RESET in between.
+
+use crate::errors::{Error, Result};
+use crate::messages::{BoltRequest, BoltResponse};
+use crate::transport::{certificates, private_key};
+use crate::types::{BoltList, BoltMap, BoltNode};
+use crate::version::Version;
+use bytes::{BufMut, Bytes, BytesMut};
+use rustls::ServerConfig;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+
+pub use crate::types::BoltType;
+
+const MAX_CHUNK_SIZE: usize = 65_535 - std::mem::size_of::<u16>();
+const MISMATCH_CODE: &str = "Neo.ClientError.Request.Invalid";
+
+/// A request the stub waits for, see [`Script::expect`]
+pub struct Expect {
+    request: Request,
+    params: Option<BoltMap>,
+}
+
+enum Request {
+    Run(QueryText),
+    Pull,
+    Discard,
+    Begin,
+    Commit,
+    Rollback,
+    Reset,
+}
+
+// queries are compared with their whitespace collapsed, so that indentation doesn't matter
+enum QueryText {
+    Exact(String),
+    Containing(String),
+}
+
+impl Expect {
+    /// A RUN of exactly `query`, parameters aren't checked unless [`Expect::param`] is used
+    pub fn run(query: &str) -> Expect {
+        Expect::request(Request::Run(QueryText::Exact(collapse(query))))
+    }
+
+    /// A RUN of any query containing `fragment`, for queries that are built on the fly
+    pub fn run_containing(fragment: &str) -> Expect {
+        Expect::request(Request::Run(QueryText::Containing(collapse(fragment))))
+    }
+
+    pub fn pull() -> Expect {
+        Expect::request(Request::Pull)
+    }
+
+    pub fn discard() -> Expect {
+        Expect::request(Request::Discard)
+    }
+
+    pub fn begin() -> Expect {
+        Expect::request(Request::Begin)
+    }
+
+    pub fn commit() -> Expect {
+        Expect::request(Request::Commit)
+    }
+
+    pub fn rollback() -> Expect {
+        Expect::request(Request::Rollback)
+    }
+
+    pub fn reset() -> Expect {
+        Expect::request(Request::Reset)
+    }
+
+    /// Adds a parameter the RUN must have, once one is given the parameters must be exactly these
+    pub fn param<T: Into<BoltType>>(mut self, key: &str, value: T) -> Expect {
+        assert!(
+            matches!(self.request, Request::Run(_)),
+            "only a RUN has parameters"
+        );
+        self.params
+            .get_or_insert_with(BoltMap::default)
+            .put(key.into(), value.into());
+        self
+    }
+
+    fn request(request: Request) -> Expect {
+        Expect {
+            request,
+            params: None,
+        }
+    }
+
+    fn matches(&self, request: &BoltRequest) -> bool {
+        match (&self.request, request) {
+            (Request::Run(text), BoltRequest::RunMessage(run)) => {
+                let query = collapse(&run.query.value);
+                let same_query = match text {
+                    QueryText::Exact(x) => query == *x,
+                    QueryText::Containing(x) => query.contains(x.as_str()),
+                };
+                same_query && self.params.as_ref().is_none_or(|x| *x == run.parameters)
+            }
+            (Request::Pull, BoltRequest::PullMessage(_))
+            | (Request::Discard, BoltRequest::DiscardMessage(_))
+            | (Request::Begin, BoltRequest::BeginMessage(_))
+            | (Request::Commit, BoltRequest::CommitMessage(_))
+            | (Request::Rollback, BoltRequest::RollbackMessage(_))
+            | (Request::Reset, BoltRequest::ResetMessage(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Expect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.request {
+            Request::Run(QueryText::Exact(x)) => write!(f, "RUN {:?}", x)?,
+            Request::Run(QueryText::Containing(x)) => write!(f, "RUN containing {:?}", x)?,
+            Request::Pull => write!(f, "PULL")?,
+            Request::Discard => write!(f, "DISCARD")?,
+            Request::Begin => write!(f, "BEGIN")?,
+            Request::Commit => write!(f, "COMMIT")?,
+            Request::Rollback => write!(f, "ROLLBACK")?,
+            Request::Reset => write!(f, "RESET")?,
+        }
+        match &self.params {
+            Some(params) => write!(f, " with {}", describe_params(params)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A response the stub sends, see [`Script::reply`]
+pub struct Reply(BoltResponse);
+
+impl Reply {
+    /// A SUCCESS without metadata, what most requests are answered with
+    pub fn success() -> Reply {
+        Reply(BoltResponse::success(BoltMap::default()))
+    }
+
+    /// The SUCCESS answering a RUN, with the names of the columns the records will have
+    pub fn fields(names: &[&str]) -> Reply {
+        let mut metadata = BoltMap::default();
+        metadata.put("fields".into(), BoltType::List(list(names)));
+        Reply(BoltResponse::success(metadata))
+    }
+
+    /// One row, the values in the order of [`Reply::fields`]
+    pub fn record(values: Vec<BoltType>) -> Reply {
+        Reply(BoltResponse::record(values.into()))
+    }
+
+    /// A FAILURE with a code like `Neo.TransientError.Transaction.DeadlockDetected`
+    pub fn failure(code: &str, message: &str) -> Reply {
+        Reply(failure(code, message))
+    }
+
+    /// What the server answers requests with after a FAILURE, until it is RESET
+    pub fn ignored() -> Reply {
+        Reply(BoltResponse::ignored())
+    }
+}
+
+/// A node to put in a [`Reply::record`]
+pub fn node(id: i64, labels: &[&str], properties: Vec<(&str, BoltType)>) -> BoltType {
+    let properties: BoltMap = properties
+        .into_iter()
+        .map(|(key, value)| (key.into(), value))
+        .collect();
+    BoltType::Node(BoltNode::new(id.into(), list(labels), properties))
+}
+
+/// The requests a [`StubServer`] expects, in order, and how it answers them
+#[derive(Default)]
+pub struct Script {
+    steps: VecDeque<Step>,
+}
+
+struct Step {
+    expect: Expect,
+    replies: Vec<BoltResponse>,
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script::default()
+    }
+
+    /// Expects `request` next, it is answered with the replies added after it
+    pub fn expect(mut self, request: Expect) -> Script {
+        self.steps.push_back(Step {
+            expect: request,
+            replies: vec![],
+        });
+        self
+    }
+
+    /// Adds a reply to the last expected request
+    pub fn reply(mut self, reply: Reply) -> Script {
+        self.steps
+            .back_mut()
+            .expect("a reply needs a request to answer")
+            .replies
+            .push(reply.0);
+        self
+    }
+
+    /// Answers the last expected RUN with `fields`, then expects the PULL and answers it with
+    /// one record per row
+    pub fn returns(self, fields: &[&str], rows: Vec<Vec<BoltType>>) -> Script {
+        let script = rows
+            .into_iter()
+            .fold(self.reply(Reply::fields(fields)).expect(Expect::pull()), |script, row| {
+                script.reply(Reply::record(row))
+            });
+        script.reply(Reply::success())
+    }
+}
+
+/// A local Bolt server playing back a [`Script`], see the [module](self) documentation
+pub struct StubServer {
+    uri: String,
+    playback: Arc<Mutex<Playback>>,
+    task: JoinHandle<()>,
+}
+
+struct Playback {
+    steps: VecDeque<Step>,
+    error: Option<String>,
+}
+
+impl StubServer {
+    /// Starts listening on a free port of 127.0.0.1
+    pub async fn start(script: Script) -> StubServer {
+        StubServer::listen(script, None).await
+    }
+
+    /// Like [`StubServer::start`], connections are wrapped in TLS with the given PEM certificate
+    /// chain and private key, for `bolt+s://` and `bolt+ssc://` uris
+    pub async fn start_tls(script: Script, chain: &str, key: &str) -> StubServer {
+        let chain = certificates(&mut chain.as_bytes()).expect("invalid certificate chain");
+        let key = private_key(&mut key.as_bytes())
+            .ok()
+            .flatten()
+            .expect("invalid private key");
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .expect("invalid certificate or key");
+        StubServer::listen(script, Some(TlsAcceptor::from(Arc::new(config)))).await
+    }
+
+    async fn listen(script: Script, tls: Option<TlsAcceptor>) -> StubServer {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind the stub server");
+        let uri = listener.local_addr().unwrap().to_string();
+        let playback = Arc::new(Mutex::new(Playback {
+            steps: script.steps,
+            error: None,
+        }));
+        let task = tokio::spawn(serve(listener, tls, playback.clone()));
+        StubServer {
+            uri,
+            playback,
+            task,
+        }
+    }
+
+    /// The address to give to [`Graph::new`] or [`ConfigBuilder::uri`], without scheme
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Panics when a request didn't match the script, or when part of the script wasn't played
+    pub fn finish(self) {
+        let playback = self.playback.lock().unwrap();
+        if let Some(error) = &playback.error {
+            panic!("{}", error);
+        }
+        if let Some(step) = playback.steps.front() {
+            panic!(
+                "expected {} next, {} request(s) of the script were never sent",
+                step.expect,
+                playback.steps.len()
+            );
+        }
+    }
+}
+
+impl Drop for StubServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Playback {
+    fn answer(&mut self, request: &BoltRequest) -> Vec<BoltResponse> {
+        if let Some(error) = &self.error {
+            // like a real server after a FAILURE, except that RESET doesn't make it right
+            return match request {
+                BoltRequest::ResetMessage(_) => vec![BoltResponse::success(BoltMap::default())],
+                _ => vec![failure(MISMATCH_CODE, error)],
+            };
+        }
+        match self.steps.front() {
+            Some(step) if step.expect.matches(request) => self.steps.pop_front().unwrap().replies,
+            step => {
+                let error = match step {
+                    Some(step) => format!("expected {}, got {}", step.expect, describe(request)),
+                    None => format!("got {} after the end of the script", describe(request)),
+                };
+                let reply = failure(MISMATCH_CODE, &error);
+                self.error = Some(error);
+                vec![reply]
+            }
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, tls: Option<TlsAcceptor>, playback: Arc<Mutex<Playback>>) {
+    let mut connections = 0;
+    while let Ok((socket, _)) = listener.accept().await {
+        connections += 1;
+        // the client hanging up is fine, a pool drops connections whenever it likes, and so is
+        // a client refusing the certificate
+        let _ = match &tls {
+            None => serve_connection(BufStream::new(socket), connections, &playback).await,
+            Some(acceptor) => match acceptor.accept(socket).await {
+                Ok(socket) => serve_connection(BufStream::new(socket), connections, &playback).await,
+                Err(e) => Err(e.into()),
+            },
+        };
+    }
+}
+
+async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut socket: BufStream<S>,
+    id: usize,
+    playback: &Mutex<Playback>,
+) -> Result<()> {
+    let version = handshake(&mut socket).await?;
+    match read_request(&mut socket, version).await? {
+        BoltRequest::HelloMessage(_) => {
+            let mut metadata = BoltMap::default();
+            metadata.put("server".into(), "Neo4j/4.1.0".into());
+            metadata.put("connection_id".into(), format!("bolt-{}", id).into());
+            write_response(&mut socket, version, BoltResponse::success(metadata)).await?;
+        }
+        request => {
+            let error = format!("expected HELLO, got {}", describe(&request));
+            playback.lock().unwrap().error = Some(error.clone());
+            return Err(Error::UnexpectedMessage(error));
+        }
+    }
+    loop {
+        let request = read_request(&mut socket, version).await?;
+        let replies = playback.lock().unwrap().answer(&request);
+        for reply in replies {
+            write_response(&mut socket, version, reply).await?;
+        }
+    }
+}
+
+/// Agrees on the first version proposed by the client that the driver supports as well
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut BufStream<S>,
+) -> Result<Version> {
+    let mut preamble = [0; 4];
+    socket.read_exact(&mut preamble).await?;
+    if preamble != [0x60, 0x60, 0xB0, 0x17] {
+        return Err(Error::UnexpectedMessage("not a Bolt client".to_owned()));
+    }
+    let mut proposed = [0; 16];
+    socket.read_exact(&mut proposed).await?;
+    let supported = Version::supported_versions();
+    let agreed = proposed
+        .chunks(4)
+        .find(|x| *x != [0, 0, 0, 0] && supported.chunks(4).any(|y| y == *x));
+    let version = match agreed {
+        Some(x) => x.try_into().unwrap(),
+        None => [0, 0, 0, 0],
+    };
+    socket.write_all(&version).await?;
+    socket.flush().await?;
+    Version::parse(version)
+}
+
+async fn read_request<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut BufStream<S>,
+    version: Version,
+) -> Result<BoltRequest> {
+    let mut bytes = BytesMut::new();
+    let mut chunk_size = 0;
+    while chunk_size == 0 {
+        chunk_size = socket.read_u16().await?;
+    }
+    while chunk_size > 0 {
+        let mut chunk = vec![0; chunk_size as usize];
+        socket.read_exact(&mut chunk).await?;
+        bytes.put_slice(&chunk);
+        chunk_size = socket.read_u16().await?;
+    }
+    BoltRequest::parse(version, bytes.freeze())
+}
+
+async fn write_response<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut BufStream<S>,
+    version: Version,
+    response: BoltResponse,
+) -> Result<()> {
+    let bytes: Bytes = response.into_bytes(version)?;
+    for c in bytes.chunks(MAX_CHUNK_SIZE) {
+        socket.write_u16(c.len() as u16).await?;
+        socket.write_all(c).await?;
+    }
+    socket.write_all(&[0, 0]).await?;
+    socket.flush().await?;
+    Ok(())
+}
+
+fn failure(code: &str, message: &str) -> BoltResponse {
+    let mut metadata = BoltMap::default();
+    metadata.put("code".into(), code.into());
+    metadata.put("message".into(), message.into());
+    BoltResponse::failure(metadata)
+}
+
+fn list(values: &[&str]) -> BoltList {
+    values
+        .iter()
+        .map(|x| BoltType::String((*x).into()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn collapse(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn describe(request: &BoltRequest) -> String {
+    match request {
+        BoltRequest::RunMessage(run) => format!(
+            "RUN {:?} with {}",
+            collapse(&run.query.value),
+            describe_params(&run.parameters)
+        ),
+        BoltRequest::HelloMessage(_) => "HELLO".to_owned(),
+        BoltRequest::PullMessage(_) => "PULL".to_owned(),
+        BoltRequest::DiscardMessage(_) => "DISCARD".to_owned(),
+        BoltRequest::BeginMessage(_) => "BEGIN".to_owned(),
+        BoltRequest::CommitMessage(_) => "COMMIT".to_owned(),
+        BoltRequest::RollbackMessage(_) => "ROLLBACK".to_owned(),
+        BoltRequest::ResetMessage(_) => "RESET".to_owned(),
+    }
+}
+
+/// `{id: 42, name: "Alice"}`, sorted by name so that a mismatch is easy to spot
+fn describe_params(params: &BoltMap) -> String {
+    let mut params: Vec<String> = params
+        .value
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key.value, describe_value(value)))
+        .collect();
+    params.sort_unstable();
+    format!("{{{}}}", params.join(", "))
+}
+
+fn describe_value(value: &BoltType) -> String {
+    match value {
+        BoltType::String(x) => format!("{:?}", x.value),
+        BoltType::Integer(x) => x.value.to_string(),
+        BoltType::Float(x) => x.value.to_string(),
+        BoltType::Boolean(x) => x.value.to_string(),
+        BoltType::Null(_) => "null".to_owned(),
+        BoltType::List(x) => format!(
+            "[{}]",
+            x.value
+                .iter()
+                .map(describe_value)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        x => format!("{:?}", x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config;
+    use crate::graph::{query, Graph};
+    use crate::types::BoltBoolean;
+
+    async fn connect(server: &StubServer) -> Graph {
+        let config = config()
+            .uri(server.uri())
+            .user("neo4j")
+            .password("secret")
+            .fetch_size(2)
+            .build()
+            .unwrap();
+        Graph::connect(config).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_stream_rows_in_batches() {
+        let server = StubServer::start(
+            Script::new()
+                .expect(Expect::run("UNWIND range(1, 3) AS n RETURN n"))
+                .reply(Reply::fields(&["n"]))
+                .expect(Expect::pull())
+                .reply(Reply::record(vec![1i64.into()]))
+                .reply(Reply::record(vec![2i64.into()]))
+                .reply(Reply(BoltResponse::success(
+                    vec![("has_more".into(), BoltType::Boolean(BoltBoolean::new(true)))]
+                        .into_iter()
+                        .collect(),
+                )))
+                .expect(Expect::pull())
+                .reply(Reply::record(vec![3i64.into()]))
+                .reply(Reply::success()),
+        )
+        .await;
+        let graph = connect(&server).await;
+
+        let mut result = graph
+            .execute(query("UNWIND range(1, 3) AS n\n    RETURN n"))
+            .await
+            .unwrap();
+        let mut rows = vec![];
+        while let Some(row) = result.next().await.unwrap() {
+            rows.push(row.get::<i64>("n").unwrap());
+        }
+        assert_eq!(rows, vec![1, 2, 3]);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_check_query_and_params() {
+        let server = StubServer::start(
+            Script::new()
+                .expect(
+                    Expect::run("CREATE (p:Person {name: $name}) RETURN p")
+                        .param("name", "Alice"),
+                )
+                .returns(
+                    &["p"],
+                    vec![vec![node(7, &["Person"], vec![("name", "Alice".into())])]],
+                )
+                .expect(Expect::reset())
+                .reply(Reply::success())
+                .expect(Expect::run_containing("RETURN p").param("name", "Alice")),
+        )
+        .await;
+        let graph = connect(&server).await;
+
+        let q = query("CREATE (p:Person {name: $name}) RETURN p").param("name", "Alice");
+        let row = graph.execute(q).await.unwrap().next().await.unwrap().unwrap();
+        let person: crate::row::Node = row.get("p").unwrap();
+        assert_eq!(person.id(), 7);
+        assert_eq!(person.get::<String>("name").unwrap(), "Alice");
+
+        let q = query("MATCH (p:Person {name: $name}) RETURN p").param("name", "Bob");
+        match graph.execute(q).await {
+            Err(Error::Neo4j { code, message, .. }) => {
+                assert_eq!(code, MISMATCH_CODE);
+                assert!(message.starts_with("expected RUN containing \"RETURN p\""));
+            }
+            _ => panic!("expected the mismatch to fail the query"),
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| server.finish()));
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_report_requests_never_sent() {
+        let server = StubServer::start(
+            Script::new()
+                .expect(Expect::begin())
+                .reply(Reply::success()),
+        )
+        .await;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| server.finish()));
+        assert!(result.is_err());
+    }
+}
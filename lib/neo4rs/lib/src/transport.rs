@@ -0,0 +1,381 @@
+use crate::errors::{Error, Result};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName};
+use std::convert::TryFrom;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+const DEFAULT_PORT: u16 = 7687;
+
+/// How a connection is secured, picked by the scheme of the uri
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Encryption {
+    /// `bolt://`, or no scheme at all
+    None,
+    /// `bolt+s://`, the certificate is verified against the trusted authorities
+    Tls,
+    /// `bolt+ssc://`, any certificate is accepted, like a self-signed one
+    TlsAnyCertificate,
+}
+
+/// Where to connect to and how, see [`crate::ConfigBuilder::uri`]
+#[derive(Debug, Clone)]
+pub(crate) struct Address {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) tls: Option<Arc<ClientConfig>>,
+}
+
+/// Splits `bolt+s://db.example.com:7687` into its encryption, host and port, the port defaults
+/// to 7687
+pub(crate) fn parse_uri(uri: &str) -> Result<(Encryption, String, u16)> {
+    let (encryption, address) = match uri.split_once("://") {
+        None => (Encryption::None, uri),
+        Some(("bolt", x)) => (Encryption::None, x),
+        Some(("bolt+s", x)) => (Encryption::Tls, x),
+        Some(("bolt+ssc", x)) => (Encryption::TlsAnyCertificate, x),
+        Some(_) => return Err(Error::InvalidConfig),
+    };
+    let address = address.trim_end_matches('/');
+    // an IPv6 address is written in brackets, like [::1]:7687
+    let (host, port) = match address.strip_prefix('[') {
+        Some(x) => match x.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => (host, Some(port.strip_prefix(':').ok_or(Error::InvalidConfig)?)),
+            None => return Err(Error::InvalidConfig),
+        },
+        None => match address.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (address, None),
+        },
+    };
+    let port = match port {
+        Some(x) => x.parse().map_err(|_| Error::InvalidConfig)?,
+        None => DEFAULT_PORT,
+    };
+    if host.is_empty() {
+        return Err(Error::InvalidConfig);
+    }
+    Ok((encryption, host.to_owned(), port))
+}
+
+/// The TLS settings of every connection, `None` for plain ones
+///
+/// `ca_certificates` replaces the built-in authorities, `client_certificate` is the PEM
+/// certificate chain and private key presented to servers asking for one.
+pub(crate) fn client_config(
+    encryption: Encryption,
+    ca_certificates: Option<&Path>,
+    client_certificate: Option<(&Path, &Path)>,
+) -> Result<Option<Arc<ClientConfig>>> {
+    let verifier: Arc<dyn ServerCertVerifier> = match encryption {
+        Encryption::None => return Ok(None),
+        Encryption::Tls => Arc::new(WebPkiVerifier::new(roots(ca_certificates)?, None)),
+        Encryption::TlsAnyCertificate => Arc::new(AnyCertificate),
+    };
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier);
+    let config = match client_certificate {
+        Some((chain, key)) => builder
+            .with_client_auth_cert(read_certificates(chain)?, read_private_key(key)?)
+            .map_err(|e| Error::TlsError(e.to_string()))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Some(Arc::new(config)))
+}
+
+fn roots(ca_certificates: Option<&Path>) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match ca_certificates {
+        Some(path) => {
+            for certificate in read_certificates(path)? {
+                roots
+                    .add(&certificate)
+                    .map_err(|e| Error::TlsError(e.to_string()))?;
+            }
+        }
+        None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|x| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                x.subject,
+                x.spki,
+                x.name_constraints,
+            )
+        })),
+    }
+    Ok(roots)
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let certificates = certificates(&mut BufReader::new(std::fs::File::open(path)?))?;
+    if certificates.is_empty() {
+        return Err(Error::TlsError(format!(
+            "no certificate in {}",
+            path.display()
+        )));
+    }
+    Ok(certificates)
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKey> {
+    private_key(&mut BufReader::new(std::fs::File::open(path)?))?.ok_or_else(|| {
+        Error::TlsError(format!("no private key in {}", path.display()))
+    })
+}
+
+/// Every certificate of a PEM file
+pub(crate) fn certificates(pem: &mut dyn BufRead) -> Result<Vec<Certificate>> {
+    Ok(rustls_pemfile::certs(pem)?
+        .into_iter()
+        .map(Certificate)
+        .collect())
+}
+
+/// The first private key of a PEM file, whatever its format
+pub(crate) fn private_key(pem: &mut dyn BufRead) -> Result<Option<PrivateKey>> {
+    for item in rustls_pemfile::read_all(pem)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(x)
+            | rustls_pemfile::Item::RSAKey(x)
+            | rustls_pemfile::Item::ECKey(x) => return Ok(Some(PrivateKey(x))),
+            _ => continue,
+        }
+    }
+    Ok(None)
+}
+
+/// Trusts whatever the server presents, the connection is encrypted but not authenticated
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// A TCP stream, wrapped in TLS for `bolt+s://` and `bolt+ssc://`
+#[derive(Debug)]
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Stream {
+    pub(crate) async fn connect(address: &Address) -> Result<Stream> {
+        let stream = TcpStream::connect((address.host.as_str(), address.port)).await?;
+        match &address.tls {
+            None => Ok(Stream::Plain(stream)),
+            Some(config) => {
+                let name = ServerName::try_from(address.host.as_str())
+                    .map_err(|e| Error::TlsError(e.to_string()))?;
+                let stream = TlsConnector::from(config.clone())
+                    .connect(name, stream)
+                    .await?;
+                Ok(Stream::Tls(Box::new(stream)))
+            }
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(x) => Pin::new(x).poll_read(cx, buf),
+            Stream::Tls(x) => Pin::new(x).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(x) => Pin::new(x).poll_write(cx, buf),
+            Stream::Tls(x) => Pin::new(x).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(x) => Pin::new(x).poll_flush(cx),
+            Stream::Tls(x) => Pin::new(x).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(x) => Pin::new(x).poll_shutdown(cx),
+            Stream::Tls(x) => Pin::new(x).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config;
+    use crate::testing::{Expect, Reply, Script, StubServer};
+    use crate::{query, Graph};
+    use std::path::PathBuf;
+
+    struct SelfSigned {
+        chain: String,
+        key: String,
+        chain_file: PathBuf,
+        key_file: PathBuf,
+    }
+
+    impl SelfSigned {
+        fn new() -> SelfSigned {
+            let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+            let chain = certificate.serialize_pem().unwrap();
+            let key = certificate.serialize_private_key_pem();
+            let name = uuid::Uuid::new_v4();
+            let chain_file = std::env::temp_dir().join(format!("neo4rs-{}.crt", name));
+            let key_file = std::env::temp_dir().join(format!("neo4rs-{}.key", name));
+            std::fs::write(&chain_file, &chain).unwrap();
+            std::fs::write(&key_file, &key).unwrap();
+            SelfSigned {
+                chain,
+                key,
+                chain_file,
+                key_file,
+            }
+        }
+    }
+
+    impl Drop for SelfSigned {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.chain_file);
+            let _ = std::fs::remove_file(&self.key_file);
+        }
+    }
+
+    fn create() -> Script {
+        Script::new()
+            .expect(Expect::run("CREATE (n)"))
+            .reply(Reply::success())
+            .expect(Expect::discard())
+            .reply(Reply::success())
+    }
+
+    fn port(server: &StubServer) -> &str {
+        server.uri().rsplit(':').next().unwrap()
+    }
+
+    #[test]
+    fn should_parse_uri() {
+        let plain = (Encryption::None, "127.0.0.1".to_owned(), 7687);
+        assert_eq!(parse_uri("127.0.0.1:7687").unwrap(), plain);
+        assert_eq!(parse_uri("127.0.0.1").unwrap(), plain);
+        assert_eq!(parse_uri("bolt://127.0.0.1:7687/").unwrap(), plain);
+        assert_eq!(
+            parse_uri("bolt+s://db.example.com:7688").unwrap(),
+            (Encryption::Tls, "db.example.com".to_owned(), 7688)
+        );
+        assert_eq!(
+            parse_uri("bolt+ssc://[::1]").unwrap(),
+            (Encryption::TlsAnyCertificate, "::1".to_owned(), 7687)
+        );
+        assert_eq!(
+            parse_uri("bolt://[::1]:7688").unwrap(),
+            (Encryption::None, "::1".to_owned(), 7688)
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_uri() {
+        assert!(matches!(parse_uri("http://127.0.0.1"), Err(Error::InvalidConfig)));
+        assert!(matches!(parse_uri("neo4j+s://127.0.0.1"), Err(Error::InvalidConfig)));
+        assert!(matches!(parse_uri("127.0.0.1:bolt"), Err(Error::InvalidConfig)));
+        assert!(matches!(parse_uri("bolt://:7687"), Err(Error::InvalidConfig)));
+        assert!(matches!(parse_uri("bolt://[::1"), Err(Error::InvalidConfig)));
+    }
+
+    #[tokio::test]
+    async fn should_connect_with_trusted_certificate() {
+        let certificate = SelfSigned::new();
+        let server = StubServer::start_tls(create(), &certificate.chain, &certificate.key).await;
+        let config = config()
+            .uri(&format!("bolt+s://localhost:{}", port(&server)))
+            .user("neo4j")
+            .password("secret")
+            .ca_certificates(&certificate.chain_file)
+            .build()
+            .unwrap();
+        let graph = Graph::connect(config).await.unwrap();
+        graph.run(query("CREATE (n)")).await.unwrap();
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_refuse_untrusted_certificate() {
+        let certificate = SelfSigned::new();
+        let server = StubServer::start_tls(Script::new(), &certificate.chain, &certificate.key).await;
+        let graph = Graph::new(
+            &format!("bolt+s://localhost:{}", port(&server)),
+            "neo4j",
+            "secret",
+        )
+        .await
+        .unwrap();
+        assert!(graph.run(query("CREATE (n)")).await.is_err());
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_accept_any_certificate_with_bolt_ssc() {
+        let certificate = SelfSigned::new();
+        let server = StubServer::start_tls(create(), &certificate.chain, &certificate.key).await;
+        let graph = Graph::new(
+            &format!("bolt+ssc://localhost:{}", port(&server)),
+            "neo4j",
+            "secret",
+        )
+        .await
+        .unwrap();
+        graph.run(query("CREATE (n)")).await.unwrap();
+        server.finish();
+    }
+
+    #[test]
+    fn should_read_client_certificate() {
+        let certificate = SelfSigned::new();
+        let config = client_config(
+            Encryption::Tls,
+            None,
+            Some((&certificate.chain_file, &certificate.key_file)),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(config.client_auth_cert_resolver.has_certs());
+        // the key is not a certificate
+        assert!(matches!(
+            client_config(Encryption::Tls, Some(&certificate.key_file), None),
+            Err(Error::TlsError(_))
+        ));
+    }
+}
@@ -14,17 +14,22 @@ use tokio::sync::Mutex;
 pub struct Txn {
     config: Config,
     connection: Arc<Mutex<ManagedConnection>>,
+    resets: usize,
 }
 
 impl Txn {
-    pub(crate) async fn new(config: Config, mut connection: ManagedConnection) -> Result<Self> {
-        let begin = BoltRequest::begin();
+    pub(crate) async fn new(
+        config: Config,
+        mut connection: ManagedConnection,
+        begin: BoltRequest,
+    ) -> Result<Self> {
         match connection.send_recv(begin).await? {
             BoltResponse::SuccessMessage(_) => Ok(Txn {
                 config,
+                resets: connection.resets(),
                 connection: Arc::new(Mutex::new(connection)),
             }),
-            msg => Err(unexpected(msg, "BEGIN")),
+            msg => Err(unexpected_response(msg, "BEGIN")),
         }
     }
 
@@ -47,20 +52,36 @@ impl Txn {
     }
 
     /// Commits the transaction in progress
+    ///
+    /// Fails without asking the server when a query of the transaction failed, the transaction
+    /// was rolled back by then.
     pub async fn commit(self) -> Result<()> {
+        let mut connection = self.connection.lock().await;
+        if connection.resets() != self.resets {
+            return Err(Error::UnexpectedMessage(
+                "transaction was rolled back after a failure".to_owned(),
+            ));
+        }
         let commit = BoltRequest::commit();
-        match self.connection.lock().await.send_recv(commit).await? {
+        match connection.send_recv(commit).await? {
             BoltResponse::SuccessMessage(_) => Ok(()),
-            msg => Err(unexpected(msg, "COMMIT")),
+            msg => Err(unexpected_response(msg, "COMMIT")),
         }
     }
 
     /// rollback/abort the current transaction
+    ///
+    /// Nothing is sent when a query of the transaction failed, the transaction was rolled back by
+    /// then.
     pub async fn rollback(self) -> Result<()> {
+        let mut connection = self.connection.lock().await;
+        if connection.resets() != self.resets {
+            return Ok(());
+        }
         let rollback = BoltRequest::rollback();
-        match self.connection.lock().await.send_recv(rollback).await? {
+        match connection.send_recv(rollback).await? {
             BoltResponse::SuccessMessage(_) => Ok(()),
-            msg => Err(unexpected(msg, "ROLLBACK")),
+            msg => Err(unexpected_response(msg, "ROLLBACK")),
         }
     }
 }
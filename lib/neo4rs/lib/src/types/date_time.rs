@@ -27,14 +27,14 @@ pub struct BoltDateTimeZoneId {
     tz_id: BoltString,
 }
 
-impl Into<BoltDateTimeZoneId> for (NaiveDateTime, &str) {
-    fn into(self) -> BoltDateTimeZoneId {
-        let seconds = self.0.timestamp().into();
-        let nanoseconds = (self.0.timestamp_subsec_nanos() as i64).into();
+impl From<(NaiveDateTime, &str)> for BoltDateTimeZoneId {
+    fn from(val: (NaiveDateTime, &str)) -> Self {
+        let seconds = val.0.and_utc().timestamp().into();
+        let nanoseconds = (val.0.and_utc().timestamp_subsec_nanos() as i64).into();
         BoltDateTimeZoneId {
             seconds,
             nanoseconds,
-            tz_id: self.1.into(),
+            tz_id: val.1.into(),
         }
     }
 }
@@ -43,16 +43,17 @@ impl TryInto<(NaiveDateTime, String)> for BoltDateTimeZoneId {
     type Error = Error;
 
     fn try_into(self) -> Result<(NaiveDateTime, String)> {
-        let datetime =
-            NaiveDateTime::from_timestamp(self.seconds.value, self.nanoseconds.value as u32);
+        let datetime = DateTime::from_timestamp(self.seconds.value, self.nanoseconds.value as u32)
+            .ok_or(Error::ConverstionError)?
+            .naive_utc();
         Ok((datetime, self.tz_id.into()))
     }
 }
 
-impl Into<BoltLocalDateTime> for NaiveDateTime {
-    fn into(self) -> BoltLocalDateTime {
-        let seconds = self.timestamp().into();
-        let nanoseconds = (self.nanosecond() as i64).into();
+impl From<NaiveDateTime> for BoltLocalDateTime {
+    fn from(val: NaiveDateTime) -> Self {
+        let seconds = val.and_utc().timestamp().into();
+        let nanoseconds = (val.nanosecond() as i64).into();
 
         BoltLocalDateTime {
             seconds,
@@ -65,18 +66,17 @@ impl TryInto<NaiveDateTime> for BoltLocalDateTime {
     type Error = Error;
 
     fn try_into(self) -> Result<NaiveDateTime> {
-        Ok(NaiveDateTime::from_timestamp(
-            self.seconds.value,
-            self.nanoseconds.value as u32,
-        ))
+        DateTime::from_timestamp(self.seconds.value, self.nanoseconds.value as u32)
+            .map(|datetime| datetime.naive_utc())
+            .ok_or(Error::ConverstionError)
     }
 }
 
-impl Into<BoltDateTime> for DateTime<FixedOffset> {
-    fn into(self) -> BoltDateTime {
-        let seconds = (self.timestamp() + self.offset().fix().local_minus_utc() as i64).into();
-        let nanoseconds = (self.nanosecond() as i64).into();
-        let tz_offset_seconds = self.offset().fix().local_minus_utc().into();
+impl From<DateTime<FixedOffset>> for BoltDateTime {
+    fn from(val: DateTime<FixedOffset>) -> Self {
+        let seconds = (val.timestamp() + val.offset().fix().local_minus_utc() as i64).into();
+        let nanoseconds = (val.nanosecond() as i64).into();
+        let tz_offset_seconds = val.offset().fix().local_minus_utc().into();
 
         BoltDateTime {
             seconds,
@@ -91,11 +91,12 @@ impl TryInto<DateTime<FixedOffset>> for BoltDateTime {
 
     fn try_into(self) -> Result<DateTime<FixedOffset>> {
         let seconds = self.seconds.value - self.tz_offset_seconds.value;
-        let datetime = NaiveDateTime::from_timestamp(seconds, self.nanoseconds.value as u32);
-        Ok(DateTime::from_utc(
-            datetime,
-            FixedOffset::east(self.tz_offset_seconds.value as i32),
-        ))
+        let datetime = DateTime::from_timestamp(seconds, self.nanoseconds.value as u32)
+            .ok_or(Error::ConverstionError)?
+            .naive_utc();
+        let offset = FixedOffset::east_opt(self.tz_offset_seconds.value as i32)
+            .ok_or(Error::ConverstionError)?;
+        Ok(DateTime::from_naive_utc_and_offset(datetime, offset))
     }
 }
 
@@ -66,9 +66,9 @@ impl IntoIterator for BoltList {
     }
 }
 
-impl Into<Vec<String>> for BoltList {
-    fn into(self) -> Vec<String> {
-        self.value.into_iter().map(|x| x.to_string()).collect()
+impl From<BoltList> for Vec<String> {
+    fn from(val: BoltList) -> Self {
+        val.value.into_iter().map(|x| x.to_string()).collect()
     }
 }
 
@@ -139,9 +139,9 @@ impl BoltList {
     }
 }
 
-impl Into<Vec<BoltType>> for BoltList {
-    fn into(self) -> Vec<BoltType> {
-        self.value
+impl From<BoltList> for Vec<BoltType> {
+    fn from(val: BoltList) -> Self {
+        val.value
     }
 }
 
@@ -169,6 +169,15 @@ mod tests {
         assert_eq!(&b[..], Bytes::from_static(&[0x92, 0x81, 0x61, 0x01]));
     }
 
+    #[test]
+    fn should_serialize_list_of_integers() {
+        let list: BoltType = vec![1i64, 2].into();
+
+        let b: Bytes = list.into_bytes(Version::V4_1).unwrap();
+
+        assert_eq!(&b[..], Bytes::from_static(&[0x92, 0x01, 0x02]));
+    }
+
     #[test]
     fn should_deserialize_list() {
         let b = Rc::new(RefCell::new(Bytes::from_static(&[0x92, 0x81, 0x61, 0x01])));
@@ -15,19 +15,11 @@ pub const SMALL: u8 = 0xD8;
 pub const MEDIUM: u8 = 0xD9;
 pub const LARGE: u8 = 0xDA;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct BoltMap {
     pub value: HashMap<BoltString, BoltType>,
 }
 
-impl Default for BoltMap {
-    fn default() -> Self {
-        BoltMap {
-            value: HashMap::new(),
-        }
-    }
-}
-
 impl BoltMap {
     pub fn with_capacity(capacity: usize) -> Self {
         BoltMap {
@@ -45,13 +37,7 @@ impl BoltMap {
 
     pub fn get<T: std::convert::TryFrom<BoltType>>(&self, key: &str) -> Option<T> {
         match self.value.get(&BoltString::new(key)) {
-            Some(bolt_type) => {
-                if let Ok(value) = TryInto::<T>::try_into(bolt_type.clone()) {
-                    Some(value)
-                } else {
-                    None
-                }
-            }
+            Some(bolt_type) => TryInto::<T>::try_into(bolt_type.clone()).ok(),
             _ => None,
         }
     }
@@ -51,9 +51,9 @@ impl From<String> for BoltString {
     }
 }
 
-impl Into<String> for BoltString {
-    fn into(self) -> String {
-        self.value
+impl From<BoltString> for String {
+    fn from(val: BoltString) -> Self {
+        val.value
     }
 }
 
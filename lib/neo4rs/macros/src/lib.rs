@@ -8,7 +8,7 @@ pub fn derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
     let struct_name = &ast.ident;
 
-    let meta = ast.attrs.get(0).unwrap().parse_meta().unwrap();
+    let meta = ast.attrs.first().unwrap().parse_meta().unwrap();
 
     let values: Vec<syn::LitInt> = match meta {
         syn::Meta::List(MetaList { nested, .. }) => {
@@ -25,11 +25,11 @@ pub fn derive(input: TokenStream) -> TokenStream {
     .collect();
 
     let (struct_marker, struct_signature) = if values.len() == 2 {
-        let marker = values.get(0).unwrap();
+        let marker = values.first().unwrap();
         let sig = values.get(1).unwrap();
         (quote! { #marker}, quote! {Some(#sig)})
     } else {
-        let marker = values.get(0).unwrap();
+        let marker = values.first().unwrap();
         (quote! { #marker}, quote! { None::<u8> })
     };
 
@@ -0,0 +1,114 @@
+use serde_json::Value;
+use std::{
+    sync::Arc,
+    vec::Vec,
+};
+
+use crate::audit::{AuditAction, AuditEventResponse};
+use crate::error_handler::ApiError;
+use crate::helpers::{
+    fetch_one,
+    parse_id,
+};
+use crate::pagination::{Page, Pagination};
+
+// events keep `actorId` and `targetId` as properties as well as the relationships,
+// so history stays readable after the actor or the target has been erased
+
+/// Writes an AuditEvent for a change, must run in the transaction of the change itself
+///
+/// CHANGED only links a target that still exists, erasing it detaches the edge again,
+/// so history of an erased target is found through `targetId` alone.
+pub async fn record(
+    txn: &neo4rs::Txn,
+    actor_id: i64,
+    action: AuditAction,
+    label: &str,
+    target_id: i64,
+    diff: Value,
+) -> Result<(), ApiError> {
+    // label is one of our own, never a client value
+    let q: neo4rs::Query = neo4rs::query(&format!("
+        CREATE (e:AuditEvent {{
+            action: $action,
+            at: datetime(),
+            diff: $diff,
+            actorId: $actor_id,
+            target: $label,
+            targetId: $target_id
+        }})
+        WITH e
+        OPTIONAL MATCH (a:User)
+        WHERE id(a) = $actor_id
+        FOREACH (x IN CASE WHEN a IS NULL THEN [] ELSE [a] END | CREATE (x)-[:PERFORMED]->(e))
+        WITH e
+        OPTIONAL MATCH (t:{})
+        WHERE id(t) = $target_id
+        FOREACH (x IN CASE WHEN t IS NULL THEN [] ELSE [t] END | CREATE (e)-[:CHANGED]->(x))
+    ", label))
+    .param("action", action.as_str())
+    .param("diff", diff.to_string())
+    .param("actor_id", actor_id)
+    .param("label", label)
+    .param("target_id", target_id);
+
+    txn.run(q).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(id = %id))]
+pub async fn find_company_history(
+    id: String,
+    pagination: Pagination,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    find_history("Company", parse_id(&id)?, pagination, &graph).await
+}
+
+#[tracing::instrument(skip_all, fields(id = %id))]
+pub async fn find_user_history(
+    id: String,
+    pagination: Pagination,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    find_history("User", parse_id(&id)?, pagination, &graph).await
+}
+
+/// Newest first, an erased target still has its history
+async fn find_history(
+    label: &str,
+    id: i64,
+    pagination: Pagination,
+    graph: &neo4rs::Graph,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (e:AuditEvent)
+        WHERE e.target = $label AND e.targetId = $id
+        RETURN count(e) AS total
+    ")
+    .param("label", label)
+    .param("id", id);
+    let row: neo4rs::Row = fetch_one(graph, q, "AuditEvent").await?;
+    let total: i64 = row.get("total").unwrap_or(0);
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (e:AuditEvent)
+        WHERE e.target = $label AND e.targetId = $id
+        OPTIONAL MATCH (a:User)-[:PERFORMED]->(e)
+        RETURN e, a.name AS actor_name
+        ORDER BY e.at DESC, id(e) DESC
+        SKIP $skip
+        LIMIT $limit
+    ")
+    .param("label", label)
+    .param("id", id)
+    .param("skip", pagination.offset)
+    .param("limit", pagination.per_page);
+
+    let mut result: neo4rs::RowStream = graph.execute(q).await.map_err(ApiError::from)?;
+    let mut records: Vec<AuditEventResponse> = vec![];
+    while let Some(row) = result.next().await.map_err(ApiError::from)? {
+        records.push(AuditEventResponse::from_row(row));
+    }
+    Ok(Page::new(records, total, &pagination).into_reply(&pagination))
+}
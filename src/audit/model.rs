@@ -0,0 +1,143 @@
+use chrono::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+// what a mutation did, stored on an AuditEvent node
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Trash,
+    Restore,
+    Erase,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Trash => "trash",
+            AuditAction::Restore => "restore",
+            AuditAction::Erase => "erase",
+        }
+    }
+
+    /// `mode` of a delete request
+    pub fn from_delete_mode(mode: &str) -> Option<AuditAction> {
+        match mode {
+            "trash" => Some(AuditAction::Trash),
+            "restore" => Some(AuditAction::Restore),
+            "erase" => Some(AuditAction::Erase),
+            _ => None,
+        }
+    }
+}
+
+// bookkeeping fields change on every write and embedded records aren't part of the node
+const UNTRACKED: &[&str] = &["id", "createdAt", "updatedAt", "version", "members", "companies"];
+
+/// Changed fields as `{"name": {"from": "old", "to": "new"}}`
+///
+/// `before` is None for create and `after` is None for erase, so every field is reported once.
+pub fn diff<T: Serialize>(before: Option<&T>, after: Option<&T>) -> Value {
+    let before = to_map(before);
+    let after = to_map(after);
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        if UNTRACKED.contains(&key.as_str()) || changes.contains_key(key) {
+            continue;
+        }
+        let from = before.get(key).cloned().unwrap_or(Value::Null);
+        let to = after.get(key).cloned().unwrap_or(Value::Null);
+        if from != to {
+            changes.insert(key.clone(), json!({"from": from, "to": to}));
+        }
+    }
+    Value::Object(changes)
+}
+
+fn to_map<T: Serialize>(record: Option<&T>) -> Map<String, Value> {
+    match record.map(serde_json::to_value) {
+        Some(Ok(Value::Object(map))) => map,
+        _ => Map::new(),
+    }
+}
+
+// response
+
+/// `name` is gone once the actor has been erased
+#[derive(Clone, Debug, Serialize)]
+pub struct ActorSummary {
+    pub id: i64,
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventResponse {
+    pub id: i64,
+    pub action: String,
+    pub at: DateTime<FixedOffset>,
+    pub actor: ActorSummary,
+    pub diff: Value,
+}
+
+impl AuditEventResponse {
+    pub fn from_row(row: neo4rs::Row) -> AuditEventResponse {
+        let e: neo4rs::Node = row.get("e").unwrap();
+        let diff: String = e.get("diff").unwrap_or_default();
+        AuditEventResponse {
+            id: e.id(),
+            action: e.get("action").unwrap(),
+            at: e.get("at").unwrap(),
+            actor: ActorSummary {
+                id: e.get("actorId").unwrap(),
+                name: row.get("actor_name"),
+            },
+            diff: serde_json::from_str(&diff).unwrap_or(Value::Null),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Record {
+        id: i64,
+        name: String,
+        updated_at: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        deleted_at: Option<String>,
+    }
+
+    fn record(name: &str, deleted_at: Option<&str>) -> Record {
+        Record {
+            id: 1,
+            name: name.to_string(),
+            updated_at: name.to_string(),
+            deleted_at: deleted_at.map(String::from),
+        }
+    }
+
+    #[test]
+    fn should_report_changed_fields_only() {
+        let before = record("Acme", None);
+        let after = record("Acme Inc", Some("2021-01-01"));
+        assert_eq!(diff(Some(&before), Some(&after)), json!({
+            "name": {"from": "Acme", "to": "Acme Inc"},
+            "deletedAt": {"from": null, "to": "2021-01-01"},
+        }));
+    }
+
+    #[test]
+    fn should_report_every_field_on_create_and_erase() {
+        let r = record("Acme", None);
+        assert_eq!(diff(None, Some(&r)), json!({"name": {"from": null, "to": "Acme"}}));
+        assert_eq!(diff(Some(&r), None), json!({"name": {"from": "Acme", "to": null}}));
+    }
+}
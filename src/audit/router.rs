@@ -0,0 +1,41 @@
+use std::sync::Arc;
+use warp::Filter;
+
+use crate::helpers::with_db;
+use crate::auth::{require_auth, JwtKeys};
+use crate::pagination::with_pagination;
+use crate::audit;
+
+pub fn init(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    find_company_history(graph.clone(), keys.clone())
+        .or(find_user_history(graph, keys))
+}
+
+/// GET /companies/:id/history
+fn find_company_history(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / String / "history")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_pagination())
+        .and(with_db(graph))
+        .and_then(audit::find_company_history)
+}
+
+/// GET /users/:id/history
+fn find_user_history(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("users" / String / "history")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_pagination())
+        .and(with_db(graph))
+        .and_then(audit::find_user_history)
+}
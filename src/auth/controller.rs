@@ -0,0 +1,358 @@
+use bcrypt::{DEFAULT_COST, hash, verify};
+use std::sync::Arc;
+use warp::http::StatusCode;
+
+use crate::auth::{
+    Claims,
+    JwtKeys,
+    LoginParams,
+    RefreshParams,
+    TokenKind,
+    TokenResponse,
+    ACCESS_TOKEN_TTL,
+};
+use crate::config::AuthSettings;
+use crate::error_handler::ApiError;
+
+#[tracing::instrument(skip_all)]
+pub async fn login(
+    params: LoginParams,
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)
+        WHERE u.email = $email AND u.deletedAt IS NULL
+        RETURN u
+    ")
+    .param("email", params.email.unwrap());
+
+    // the stream is dropped before the tokens are stored, its connection can be used for that
+    let node: Option<neo4rs::Node> = {
+        let mut result: neo4rs::RowStream = graph.execute(q).await.map_err(ApiError::from)?;
+        match result.next().await.map_err(ApiError::from)? {
+            Some(row) => row.get("u"),
+            None => None,
+        }
+    };
+    // same answer for unknown email and wrong password
+    let user_id: i64 = match node {
+        Some(u) => {
+            let hashed: String = u.get("password").unwrap_or_default();
+            if verify(params.password.unwrap(), &hashed).unwrap_or(false) {
+                u.id()
+            } else {
+                return Err(ApiError::Unauthorized("Invalid email or password".to_string()).into());
+            }
+        },
+        None => return Err(ApiError::Unauthorized("Invalid email or password".to_string()).into()),
+    };
+
+    let record: TokenResponse = issue_tokens(&graph, &keys, user_id).await?;
+    Ok(warp::reply::json(&record))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn refresh(
+    params: RefreshParams,
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let claims: Claims = keys.decode(&params.refresh_token.unwrap(), TokenKind::Refresh)?;
+
+    // rotate: the presented token is revoked as soon as it is used
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (t:RefreshToken {jti: $jti})-[:ISSUED_TO]->(u:User)
+        WHERE t.revokedAt IS NULL AND u.deletedAt IS NULL
+        SET t.revokedAt = datetime()
+        RETURN id(u) AS id
+    ")
+    .param("jti", claims.jti);
+
+    let user_id: i64 = {
+        let mut result: neo4rs::RowStream = graph.execute(q).await.map_err(ApiError::from)?;
+        match result.next().await.map_err(ApiError::from)? {
+            Some(row) => row.get("id").unwrap(),
+            None => return Err(ApiError::Unauthorized("Refresh token has been revoked".to_string()).into()),
+        }
+    };
+
+    let record: TokenResponse = issue_tokens(&graph, &keys, user_id).await?;
+    Ok(warp::reply::json(&record))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn logout(
+    params: RefreshParams,
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let claims: Claims = keys.decode(&params.refresh_token.unwrap(), TokenKind::Refresh)?;
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (t:RefreshToken {jti: $jti})
+        WHERE t.revokedAt IS NULL
+        SET t.revokedAt = datetime()
+    ")
+    .param("jti", claims.jti);
+
+    graph.run(q).await.map_err(ApiError::from)?;
+    let empty: Vec<u8> = vec![];
+    Ok(warp::reply::with_status(
+        warp::reply::json(&empty),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+/// Creates the user from `auth.admin_email` unless a user has that email already, POST /users
+/// needs a signed in user so this is the only way into a fresh database
+pub async fn seed_admin(graph: &neo4rs::Graph, settings: &AuthSettings) -> Result<Option<i64>, ApiError> {
+    if settings.admin_email.is_empty() {
+        return Ok(None);
+    }
+    let q: neo4rs::Query = neo4rs::query("
+        OPTIONAL MATCH (existing:User {email: $email})
+        WITH existing
+        WHERE existing IS NULL
+        CREATE (u:User {
+            name: $name,
+            email: $email,
+            password: $password,
+            avatar: '',
+            version: 1,
+            createdAt: datetime(),
+            updatedAt: datetime()
+        })
+        RETURN id(u) AS id
+    ")
+    .param("name", "Administrator")
+    .param("email", settings.admin_email.clone())
+    .param("password", hash(&settings.admin_password, DEFAULT_COST).unwrap());
+
+    let mut result: neo4rs::RowStream = graph.execute(q).await?;
+    let id: Option<i64> = result.next().await?.and_then(|row| row.get("id"));
+    while result.next().await?.is_some() {}
+    Ok(id)
+}
+
+async fn issue_tokens(
+    graph: &neo4rs::Graph,
+    keys: &JwtKeys,
+    user_id: i64,
+) -> Result<TokenResponse, ApiError> {
+    let (access_token, _) = keys.encode(user_id, TokenKind::Access)?;
+    let (refresh_token, claims) = keys.encode(user_id, TokenKind::Refresh)?;
+
+    // refresh tokens are tracked so that they can be revoked
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)
+        WHERE id(u) = $id
+        CREATE (t:RefreshToken {
+            jti: $jti,
+            createdAt: datetime(),
+            expiresAt: datetime({epochSeconds: $exp})
+        })-[:ISSUED_TO]->(u)
+    ")
+    .param("id", user_id)
+    .param("jti", claims.jti)
+    .param("exp", claims.exp);
+    graph.run(q).await?;
+
+    Ok(TokenResponse {
+        access_token,
+        refresh_token,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_TTL,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neo4rs::testing::{node, Expect, Reply, Script, StubServer};
+    use crate::test_support::connect;
+
+    const ISSUE: &str = "CREATE (t:RefreshToken {";
+
+    fn keys() -> Arc<JwtKeys> {
+        Arc::new(JwtKeys::new(b"secret"))
+    }
+
+    fn login_params(password: &str) -> LoginParams {
+        LoginParams {
+            email: Some("jane@example.com".to_string()),
+            password: Some(password.to_string()),
+        }
+    }
+
+    /// Jane, whose password is "secret"
+    fn login_script() -> Script {
+        Script::new()
+            .expect(Expect::run_containing("WHERE u.email = $email AND u.deletedAt IS NULL").param("email", "jane@example.com"))
+            .returns(&["u"], vec![vec![node(7, &["User"], vec![
+                ("email", "jane@example.com".into()),
+                ("password", hash("secret", 4).unwrap().into()),
+            ])]])
+    }
+
+    /// Refresh token of user 7 stored for the next request
+    fn issue(script: Script) -> Script {
+        script
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing(ISSUE))
+            .reply(Reply::success())
+            .expect(Expect::discard())
+            .reply(Reply::success())
+    }
+
+    async fn tokens(reply: impl warp::Reply) -> TokenResponse {
+        let response = warp::Reply::into_response(reply);
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        TokenResponse {
+            access_token: body["accessToken"].as_str().unwrap().to_string(),
+            refresh_token: body["refreshToken"].as_str().unwrap().to_string(),
+            token_type: body["tokenType"].as_str().unwrap().to_string(),
+            expires_in: body["expiresIn"].as_i64().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_issue_tokens_for_right_password() {
+        let server = StubServer::start(issue(login_script())).await;
+
+        let record = tokens(login(login_params("secret"), connect(&server).await, keys()).await.unwrap()).await;
+        assert_eq!(record.token_type, "Bearer");
+        assert_eq!(keys().decode(&record.access_token, TokenKind::Access).unwrap().sub, 7);
+        assert_eq!(keys().decode(&record.refresh_token, TokenKind::Refresh).unwrap().sub, 7);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_issue_nothing_for_wrong_password() {
+        let server = StubServer::start(login_script()).await;
+
+        let rejection = login(login_params("wrong"), connect(&server).await, keys()).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Unauthorized(_))));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_rotate_refresh_token() {
+        let (refresh_token, claims) = keys().encode(7, TokenKind::Refresh).unwrap();
+        let script = Script::new()
+            .expect(Expect::run_containing("SET t.revokedAt = datetime() RETURN id(u) AS id").param("jti", claims.jti.clone()))
+            .returns(&["id"], vec![vec![7i64.into()]]);
+        let server = StubServer::start(issue(script)).await;
+        let params = RefreshParams {
+            refresh_token: Some(refresh_token.clone()),
+        };
+
+        let record = tokens(refresh(params, connect(&server).await, keys()).await.unwrap()).await;
+        assert_ne!(record.refresh_token, refresh_token);
+        assert_ne!(keys().decode(&record.refresh_token, TokenKind::Refresh).unwrap().jti, claims.jti);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_refresh_with_revoked_token() {
+        let (refresh_token, _) = keys().encode(7, TokenKind::Refresh).unwrap();
+        let script = Script::new()
+            .expect(Expect::run_containing("SET t.revokedAt = datetime() RETURN id(u) AS id"))
+            .returns(&["id"], vec![]);
+        let server = StubServer::start(script).await;
+        let params = RefreshParams {
+            refresh_token: Some(refresh_token),
+        };
+
+        let rejection = refresh(params, connect(&server).await, keys()).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Unauthorized(_))));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_refresh_with_access_token() {
+        // the database isn't asked at all
+        let server = StubServer::start(Script::new()).await;
+        let (access_token, _) = keys().encode(7, TokenKind::Access).unwrap();
+        let params = RefreshParams {
+            refresh_token: Some(access_token),
+        };
+
+        let rejection = refresh(params, connect(&server).await, keys()).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Unauthorized(_))));
+        server.finish();
+    }
+
+    fn settings(admin_email: &str) -> AuthSettings {
+        AuthSettings {
+            jwt_secret: "secret".to_string(),
+            admin_email: admin_email.to_string(),
+            admin_password: "secret".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_seed_first_user_once() {
+        let script = Script::new()
+            .expect(Expect::run_containing("WHERE existing IS NULL CREATE (u:User"))
+            .returns(&["id"], vec![vec![1i64.into()]])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("WHERE existing IS NULL CREATE (u:User"))
+            .returns(&["id"], vec![]);
+        let server = StubServer::start(script).await;
+        let graph = connect(&server).await;
+
+        assert_eq!(seed_admin(&graph, &settings("admin@example.com")).await.unwrap(), Some(1));
+        assert_eq!(seed_admin(&graph, &settings("admin@example.com")).await.unwrap(), None);
+        // nothing to seed, the database isn't touched
+        assert_eq!(seed_admin(&graph, &settings("")).await.unwrap(), None);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_sign_in_seeded_admin() {
+        // the node as seeded by earlier releases, which didn't set an avatar
+        let now = chrono::DateTime::parse_from_rfc3339("2021-06-01T09:00:00+00:00").unwrap();
+        let admin = node(1, &["User"], vec![
+            ("name", "Administrator".into()),
+            ("email", "admin@example.com".into()),
+            ("password", hash("secret", 4).unwrap().into()),
+            ("version", 1i64.into()),
+            ("createdAt", now.into()),
+            ("updatedAt", now.into()),
+        ]);
+        let script = Script::new()
+            .expect(Expect::run_containing("WHERE existing IS NULL CREATE (u:User"))
+            .returns(&["id"], vec![vec![1i64.into()]])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("WHERE u.email = $email AND u.deletedAt IS NULL").param("email", "admin@example.com"))
+            .returns(&["u"], vec![vec![admin.clone()]]);
+        let script = issue(script)
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run("MATCH (u:User) WHERE id(u) = $id RETURN u").param("id", 1i64))
+            .returns(&["u"], vec![vec![admin]]);
+        let server = StubServer::start(script).await;
+        let graph = connect(&server).await;
+
+        assert_eq!(seed_admin(&graph, &settings("admin@example.com")).await.unwrap(), Some(1));
+        let params = LoginParams {
+            email: Some("admin@example.com".to_string()),
+            password: Some("secret".to_string()),
+        };
+        let record = tokens(login(params, graph.clone(), keys()).await.unwrap()).await;
+        let user = warp::test::request()
+            .header("authorization", format!("Bearer {}", record.access_token))
+            .filter(&crate::auth::with_auth(graph, keys()))
+            .await
+            .unwrap();
+        assert_eq!(user.id, 1);
+        assert_eq!(user.avatar, "");
+        server.finish();
+    }
+}
@@ -0,0 +1,9 @@
+mod model;
+mod controller;
+mod router;
+mod token;
+
+pub use model::*;
+pub use controller::*;
+pub use router::init;
+pub use token::*;
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+// login
+
+#[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
+pub struct LoginParams {
+    #[validate(required, email)]
+    pub email: Option<String>,
+    #[validate(required)]
+    pub password: Option<String>,
+}
+
+// refresh & logout
+
+#[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
+pub struct RefreshParams {
+    #[validate(required)]
+    pub refresh_token: Option<String>,
+}
+
+// response
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+}
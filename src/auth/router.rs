@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use warp::Filter;
+
+use crate::helpers::{with_db, with_json_body};
+use crate::auth::{
+    self,
+    with_keys,
+    JwtKeys,
+    LoginParams,
+    RefreshParams,
+};
+
+pub fn init(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    login(graph.clone(), keys.clone())
+        .or(refresh(graph.clone(), keys.clone()))
+        .or(logout(graph, keys))
+}
+
+/// POST /auth/login
+fn login(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("auth" / "login")
+        .and(warp::post())
+        .and(with_login_params())
+        .and(with_db(graph))
+        .and(with_keys(keys))
+        .and_then(auth::login)
+}
+
+/// POST /auth/refresh
+fn refresh(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("auth" / "refresh")
+        .and(warp::post())
+        .and(with_refresh_params())
+        .and(with_db(graph))
+        .and(with_keys(keys))
+        .and_then(auth::refresh)
+}
+
+/// POST /auth/logout
+fn logout(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("auth" / "logout")
+        .and(warp::post())
+        .and(with_refresh_params())
+        .and(with_db(graph))
+        .and(with_keys(keys))
+        .and_then(auth::logout)
+}
+
+fn with_login_params() -> impl Filter<Extract = (LoginParams, ), Error = warp::Rejection> + Clone {
+    with_json_body()
+}
+
+fn with_refresh_params() -> impl Filter<Extract = (RefreshParams, ), Error = warp::Rejection> + Clone {
+    with_json_body()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handler::ApiError;
+
+    #[tokio::test]
+    async fn should_reject_empty_login_body() {
+        let rejection = warp::test::request()
+            .header("content-type", "application/json")
+            .body("{}")
+            .filter(&with_login_params())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::ValidationErrors(_))));
+
+        let rejection = warp::test::request()
+            .header("content-type", "application/json")
+            .body("[]")
+            .filter(&with_refresh_params())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::ParsingError(path, _)) if path == "."));
+    }
+}
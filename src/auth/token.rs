@@ -0,0 +1,209 @@
+use chrono::Utc;
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    sync::Arc,
+};
+use uuid::Uuid;
+use warp::Filter;
+
+use crate::error_handler::ApiError;
+use crate::helpers::{fetch_one, with_db};
+use crate::query_builder::QueryBuilder;
+use crate::user::UserResponse;
+
+pub const ACCESS_TOKEN_TTL: i64 = 15 * 60; // 15 minutes
+pub const REFRESH_TOKEN_TTL: i64 = 14 * 24 * 60 * 60; // 2 weeks
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: i64, // id of user node
+    pub jti: String,
+    pub kind: TokenKind,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Clone)]
+pub struct JwtKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl JwtKeys {
+    pub fn new(secret: &[u8]) -> Self {
+        JwtKeys {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn encode(&self, user_id: i64, kind: TokenKind) -> Result<(String, Claims), ApiError> {
+        let now = Utc::now().timestamp();
+        let ttl = match kind {
+            TokenKind::Access => ACCESS_TOKEN_TTL,
+            TokenKind::Refresh => REFRESH_TOKEN_TTL,
+        };
+        let claims = Claims {
+            sub: user_id,
+            jti: Uuid::new_v4().to_string(),
+            kind,
+            iat: now,
+            exp: now + ttl,
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)
+            .map_err(|e| ApiError::Unauthorized(e.to_string()))?;
+        Ok((token, claims))
+    }
+
+    /// Verifies signature and expiry, and makes sure a refresh token is never accepted as access token
+    pub fn decode(&self, token: &str, kind: TokenKind) -> Result<Claims, ApiError> {
+        let data = jsonwebtoken::decode::<Claims>(token, &self.decoding, &Validation::new(Algorithm::HS256))
+            .map_err(|_| ApiError::Unauthorized("Invalid or expired token".to_string()))?;
+        if data.claims.kind != kind {
+            return Err(ApiError::Unauthorized("Invalid token type".to_string()));
+        }
+        Ok(data.claims)
+    }
+}
+
+pub fn with_keys(
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = (Arc<JwtKeys>, ), Error = Infallible> + Clone {
+    warp::any().map(move || {
+        keys.clone()
+    })
+}
+
+/// Extracts the signed in user from `Authorization: Bearer <access token>`
+pub fn with_auth(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = (UserResponse, ), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(with_db(graph))
+        .and(with_keys(keys))
+        .and_then(authenticate)
+}
+
+/// Same as `with_auth` for routes which only need the caller to be signed in
+pub fn require_auth(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    with_auth(graph, keys)
+        .map(|_: UserResponse| ())
+        .untuple_one()
+}
+
+async fn authenticate(
+    authorization: Option<String>,
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> Result<UserResponse, warp::Rejection> {
+    let token = match authorization.as_deref().and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(token) => token.trim(),
+        None => {
+            return Err(warp::reject::custom(
+                ApiError::Unauthorized("Missing bearer token".to_string())
+            ));
+        },
+    };
+    let claims: Claims = keys.decode(token, TokenKind::Access)?;
+
+    let q: neo4rs::Query = QueryBuilder::new("u", "User")
+        .where_id(claims.sub)
+        .returns()
+        .build();
+    let row: neo4rs::Row = match fetch_one(&graph, q, "User").await {
+        Ok(row) => row,
+        Err(ApiError::NotFound(_)) => {
+            return Err(warp::reject::custom(
+                ApiError::Unauthorized("User no longer exists".to_string())
+            ));
+        },
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let user: UserResponse = UserResponse::from_row(row);
+    if user.deleted_at.is_some() {
+        return Err(warp::reject::custom(
+            ApiError::Unauthorized("User no longer exists".to_string())
+        ));
+    }
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_KEY: &[u8] = b"test-signing-key";
+
+    #[test]
+    fn should_decode_issued_token() {
+        let keys = JwtKeys::new(TEST_KEY);
+        let (token, claims) = keys.encode(42, TokenKind::Access).unwrap();
+        let decoded = keys.decode(&token, TokenKind::Access).unwrap();
+        assert_eq!(decoded.sub, 42);
+        assert_eq!(decoded.jti, claims.jti);
+        assert_eq!(decoded.exp - decoded.iat, ACCESS_TOKEN_TTL);
+    }
+
+    #[test]
+    fn should_reject_refresh_token_as_access_token() {
+        let keys = JwtKeys::new(TEST_KEY);
+        let (token, _) = keys.encode(42, TokenKind::Refresh).unwrap();
+        assert!(keys.decode(&token, TokenKind::Access).is_err());
+        assert!(keys.decode(&token, TokenKind::Refresh).is_ok());
+    }
+
+    #[test]
+    fn should_reject_token_signed_with_other_key() {
+        let (token, _) = JwtKeys::new(b"other-key").encode(42, TokenKind::Access).unwrap();
+        assert!(JwtKeys::new(TEST_KEY).decode(&token, TokenKind::Access).is_err());
+    }
+
+    #[test]
+    fn should_reject_expired_token() {
+        let keys = JwtKeys::new(TEST_KEY);
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: 42,
+            jti: Uuid::new_v4().to_string(),
+            kind: TokenKind::Access,
+            iat: now - 2 * ACCESS_TOKEN_TTL,
+            exp: now - ACCESS_TOKEN_TTL,
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &keys.encoding).unwrap();
+        assert!(keys.decode(&token, TokenKind::Access).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_reject_request_without_bearer_token() {
+        // the pool connects lazily, so nothing is dialed when the token is rejected up front
+        let graph = Arc::new(neo4rs::Graph::new("127.0.0.1:1", "neo4j", "neo4j").await.unwrap());
+        let keys = Arc::new(JwtKeys::new(TEST_KEY));
+        let filter = with_auth(graph, keys);
+
+        let missing = warp::test::request().filter(&filter).await.err().unwrap();
+        assert!(matches!(missing.find::<ApiError>(), Some(ApiError::Unauthorized(_))));
+
+        let garbage = warp::test::request()
+            .header("authorization", "Bearer garbage")
+            .filter(&filter)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(garbage.find::<ApiError>(), Some(ApiError::Unauthorized(_))));
+    }
+}
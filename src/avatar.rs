@@ -0,0 +1,637 @@
+use bytes::Bytes;
+use image::{
+    imageops::FilterType,
+    DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits,
+};
+use serde::Deserialize;
+use std::{
+    io::{Cursor, ErrorKind},
+    sync::Arc,
+};
+use warp::{
+    http::{
+        header::{
+            ACCEPT_RANGES, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+        Response, StatusCode,
+    },
+    hyper::Body,
+    path::Tail,
+    Filter,
+};
+
+use crate::error_handler::{self, ApiError};
+use crate::storage::{with_storage, Storage};
+
+// avatars are served from /storage, outside of /api/v1, so that they can be used in <img> tags
+// their keys contain a random uuid, so links can't be guessed and the content behind a key never changes
+// every upload is decoded and encoded again, which drops EXIF and any other metadata,
+// and square thumbnails are stored next to it, like `42/<uuid>_64.png`
+
+pub const THUMBNAIL_SIZES: &[u32] = &[64, 128, 256];
+
+const MAX_DIMENSION: u32 = 8192;
+
+/// Encoded images by thumbnail size, None is the original
+type Variants = Vec<(Option<u32>, Bytes)>;
+
+// a key never changes its content, see above
+const CACHE_CONTROL_VALUE: &str = "public, max-age=31536000, immutable";
+
+/// GET /storage/:id/:filename
+pub fn init(
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path("storage")
+        .and(
+            warp::path::tail()
+                .and(warp::get())
+                .and(warp::query::<ServeParams>())
+                .and(warp::header::optional::<String>("range"))
+                .and(warp::header::optional::<String>("if-none-match"))
+                .and(with_storage(storage))
+                .and_then(serve)
+                .recover(error_handler::handle_rejection)
+        )
+}
+
+#[derive(Deserialize)]
+struct ServeParams {
+    size: Option<String>,
+}
+
+#[tracing::instrument(skip_all, fields(key = tail.as_str()))]
+async fn serve(
+    tail: Tail,
+    params: ServeParams,
+    range: Option<String>,
+    if_none_match: Option<String>,
+    storage: Arc<dyn Storage>,
+) -> Result<Response<Body>, warp::Rejection> {
+    let key: &str = tail.as_str();
+    // only avatars of users are served, not the uploads waiting in tmp/
+    let is_avatar: bool = match key.split_once('/') {
+        Some((id, filename)) => id.parse::<i64>().is_ok() && !filename.is_empty() && !filename.contains('/'),
+        None => false,
+    };
+    if !is_avatar {
+        return Err(warp::reject::custom(ApiError::NotFound("File".to_string())));
+    }
+    let size: Option<u32> = match params.size {
+        None => None,
+        Some(x) => match x.parse::<u32>() {
+            Ok(x) if THUMBNAIL_SIZES.contains(&x) => Some(x),
+            _ => {
+                return Err(warp::reject::custom(
+                    ApiError::ParsingError("size".to_string(), "Must be one of 64, 128, 256".to_string())
+                ));
+            },
+        },
+    };
+
+    // avatars uploaded before thumbnails existed only have the original
+    let mut served: String = variant_key(key, size);
+    let mut found = storage.get(&served).await;
+    if size.is_some() && is_missing(&found) {
+        served = key.to_string();
+        found = storage.get(&served).await;
+    }
+    let data: Bytes = match found {
+        Ok(x) => x,
+        Err(ApiError::Storage(e)) if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::InvalidInput => {
+            return Err(warp::reject::custom(ApiError::NotFound("File".to_string())));
+        },
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let etag: String = format!("\"{}\"", served.rsplit('/').next().unwrap());
+    let builder = Response::builder()
+        .header(CONTENT_TYPE, content_type(&served))
+        .header(CACHE_CONTROL, CACHE_CONTROL_VALUE)
+        .header(ETAG, &etag)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(X_CONTENT_TYPE_OPTIONS, "nosniff");
+
+    if if_none_match.is_some_and(|x| x.split(',').any(|tag| tag.trim() == "*" || tag.trim().trim_start_matches("W/") == etag)) {
+        return Ok(builder.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap());
+    }
+    let len: u64 = data.len() as u64;
+    let response = match range.and_then(|x| parse_range(&x, len)) {
+        None => builder
+            .header(CONTENT_LENGTH, len)
+            .body(Body::from(data)),
+        Some(Ok((start, end))) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+            .header(CONTENT_LENGTH, end - start + 1)
+            .body(Body::from(data.slice(start as usize..=end as usize))),
+        Some(Err(_)) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Body::empty()),
+    };
+    Ok(response.unwrap())
+}
+
+/// Finds the format of an upload from its first bytes, the client can't be trusted with it
+///
+/// The declared content type and the extension of the filename, when there are any, must agree.
+pub fn check_upload(
+    field: &str,
+    data: &[u8],
+    content_type: Option<&str>,
+    filename: &str,
+) -> Result<ImageFormat, ApiError> {
+    let mismatch = |msg: String| ApiError::UnsupportedMediaType(field.to_string(), msg);
+
+    let format: ImageFormat = sniff(data).ok_or_else(|| mismatch("Must be a PNG, JPEG, GIF or WebP image".to_string()))?;
+    if let Some(declared) = content_type {
+        let declared: String = declared.split(';').next().unwrap().trim().to_ascii_lowercase();
+        let declared: &str = if declared == "image/jpg" { "image/jpeg" } else { &declared };
+        if declared != format.to_mime_type() {
+            return Err(mismatch(format!("Declared as {} but the file is {}", declared, format.to_mime_type())));
+        }
+    }
+    if let Some((_, extension)) = filename.rsplit_once('.') {
+        if !format.extensions_str().contains(&extension.to_ascii_lowercase().as_str()) {
+            return Err(mismatch(format!("The extension .{} doesn't match {}", extension, format.to_mime_type())));
+        }
+    }
+    Ok(format)
+}
+
+/// Decodes an upload and stores it under `stem` with the extension of its format, along with its thumbnails
+///
+/// Returns the key of the original.
+pub async fn store(storage: &dyn Storage, stem: &str, data: Bytes) -> Result<String, ApiError> {
+    let (format, variants) = tokio::task::spawn_blocking(move || render(&data))
+        .await
+        .map_err(|e| ApiError::Storage(std::io::Error::other(e)))??;
+    let key: String = format!("{}.{}", stem, format.extensions_str()[0]);
+    for (size, data) in variants {
+        storage.put(&variant_key(&key, size), data, format.to_mime_type()).await?;
+    }
+    Ok(key)
+}
+
+/// Avatar files changed along with a transaction
+///
+/// New files are put in place while the transaction runs, the files they replace are only deleted
+/// once it has been committed. When it fails, the new files and the upload are deleted instead,
+/// so that the node and its files either both change or neither does.
+/// A transaction that is tried again rewinds first, the upload is back in tmp/ for the next attempt.
+pub struct Staged {
+    storage: Arc<dyn Storage>,
+    upload: Option<String>,
+    added: Vec<String>,
+    replaced: Vec<String>,
+}
+
+impl Staged {
+    /// `upload` is the file in tmp/ that came with the request, if any
+    pub fn new(storage: Arc<dyn Storage>, upload: Option<String>) -> Staged {
+        Staged {
+            storage,
+            upload,
+            added: vec![],
+            replaced: vec![],
+        }
+    }
+
+    /// Moves an upload to `key`
+    pub async fn add(&mut self, upload: &str, key: &str) -> Result<(), ApiError> {
+        // recorded first, a move that fails halfway may have moved some of the thumbnails
+        self.added.push(key.to_string());
+        move_to(self.storage.as_ref(), upload, key).await
+    }
+
+    /// Moves the files of an attempt that failed back to the upload, so that the transaction can be tried again
+    pub async fn rewind(&mut self) -> Result<(), ApiError> {
+        self.replaced.clear();
+        if let Some(upload) = &self.upload {
+            while let Some(key) = self.added.last() {
+                move_to(self.storage.as_ref(), key, upload).await?;
+                self.added.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes `key` once the transaction has been committed
+    pub fn replace(&mut self, key: &str) {
+        self.replaced.push(key.to_string());
+    }
+
+    /// Keeps the new files if `result` is the one of a committed transaction, discards them otherwise
+    ///
+    /// The transaction is over by then, so files that can't be deleted are only logged.
+    pub async fn settle<T>(&mut self, result: Result<T, ApiError>) -> Result<T, ApiError> {
+        let garbage: Vec<String> = match result {
+            Ok(_) => std::mem::take(&mut self.replaced),
+            Err(_) => std::mem::take(&mut self.added).into_iter().chain(self.upload.take()).collect(),
+        };
+        for key in garbage {
+            if let Err(e) = delete(self.storage.as_ref(), &key).await {
+                tracing::error!(key = %key, error = %e, "Failed to delete avatar");
+            }
+        }
+        result
+    }
+}
+
+/// Moves the original and its thumbnails
+pub async fn move_to(storage: &dyn Storage, from: &str, to: &str) -> Result<(), ApiError> {
+    storage.move_to(from, to).await?;
+    for size in THUMBNAIL_SIZES {
+        let from: String = variant_key(from, Some(*size));
+        match storage.move_to(&from, &variant_key(to, Some(*size))).await {
+            Err(ApiError::Storage(e)) if e.kind() == ErrorKind::NotFound => (),
+            x => x?,
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the original and its thumbnails
+pub async fn delete(storage: &dyn Storage, key: &str) -> Result<(), ApiError> {
+    storage.delete(key).await?;
+    for size in THUMBNAIL_SIZES {
+        storage.delete(&variant_key(key, Some(*size))).await?;
+    }
+    Ok(())
+}
+
+/// `42/<uuid>.png` with 64 becomes `42/<uuid>_64.png`, None is the original
+fn variant_key(key: &str, size: Option<u32>) -> String {
+    match (size, key.rsplit_once('.')) {
+        (None, _) => key.to_string(),
+        (Some(size), Some((stem, extension))) => format!("{}_{}.{}", stem, size, extension),
+        (Some(size), None) => format!("{}_{}", key, size),
+    }
+}
+
+/// The original without metadata, followed by one thumbnail per size
+///
+/// The EXIF orientation is applied to the pixels before it is dropped.
+/// Animated GIFs keep their first frame only.
+fn render(data: &[u8]) -> Result<(ImageFormat, Variants), ApiError> {
+    let invalid = |e: image::ImageError| ApiError::ParsingError("avatar".to_string(), e.to_string());
+
+    let format: ImageFormat = sniff(data).ok_or_else(|| {
+        ApiError::UnsupportedMediaType("avatar".to_string(), "Must be a PNG, JPEG, GIF or WebP image".to_string())
+    })?;
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(invalid)?;
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image: DynamicImage = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    image.apply_orientation(orientation);
+
+    let mut variants: Variants = vec![(None, encode(&image, format)?)];
+    for size in THUMBNAIL_SIZES {
+        let thumbnail: DynamicImage = image.resize_to_fill(*size, *size, FilterType::Lanczos3);
+        variants.push((Some(*size), encode(&thumbnail, format)?));
+    }
+    Ok((format, variants))
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Bytes, ApiError> {
+    // JPEG has no alpha channel
+    let image: DynamicImage = match format {
+        ImageFormat::Jpeg if image.color().has_alpha() => DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ => image.clone(),
+    };
+    let mut buffer: Vec<u8> = vec![];
+    image
+        .write_to(&mut Cursor::new(&mut buffer), format)
+        .map_err(|e| ApiError::Storage(std::io::Error::other(e)))?;
+    Ok(buffer.into())
+}
+
+/// Only the formats avatars may have are recognized
+fn sniff(data: &[u8]) -> Option<ImageFormat> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageFormat::Png)
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ImageFormat::Jpeg)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(ImageFormat::Gif)
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some(ImageFormat::WebP)
+    } else {
+        None
+    }
+}
+
+fn content_type(key: &str) -> &'static str {
+    match key.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+fn is_missing(result: &Result<Bytes, ApiError>) -> bool {
+    matches!(result, Err(ApiError::Storage(e)) if e.kind() == ErrorKind::NotFound)
+}
+
+/// First and last byte of a single `bytes=` range, Err if it can't be satisfied
+///
+/// None means the whole file, which is also the answer to several ranges or a malformed header.
+fn parse_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec: &str = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end): (u64, u64) = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        // the last n bytes
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(Err(()));
+            }
+            (len.saturating_sub(suffix), len - 1)
+        },
+        (start, "") => (start.parse().ok()?, len.saturating_sub(1)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.min(len.saturating_sub(1)))
+        },
+    };
+    if start >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+    use async_trait::async_trait;
+    use image::{GenericImageView, RgbImage};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    fn jpeg_with_exif() -> Vec<u8> {
+        let mut jpeg: Vec<u8> = vec![];
+        DynamicImage::ImageRgb8(RgbImage::new(300, 200))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        // an APP1 segment right after SOI, with a TIFF header and an empty IFD
+        let exif: &[u8] = b"Exif\0\0II*\0\x08\0\0\0\0\0\0\0\0\0";
+        let mut data: Vec<u8> = jpeg[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        data.extend_from_slice(exif);
+        data.extend_from_slice(&jpeg[2..]);
+        data
+    }
+
+    #[test]
+    fn should_strip_exif_and_make_thumbnails() {
+        let data: Vec<u8> = jpeg_with_exif();
+        assert!(data.windows(4).any(|x| x == b"Exif"));
+
+        let (format, variants) = render(&data).unwrap();
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert_eq!(variants.len(), 1 + THUMBNAIL_SIZES.len());
+        for (size, data) in variants {
+            assert!(!data.windows(4).any(|x| x == b"Exif"));
+            let image: DynamicImage = image::load_from_memory(&data).unwrap();
+            match size {
+                None => assert_eq!(image.dimensions(), (300, 200)),
+                Some(size) => assert_eq!(image.dimensions(), (size, size)),
+            }
+        }
+    }
+
+    #[test]
+    fn should_reject_data_that_is_not_an_image() {
+        match render(b"%PDF-1.4") {
+            Err(ApiError::UnsupportedMediaType(field, _)) => assert_eq!(field, "avatar"),
+            _ => panic!("expected an unsupported media type"),
+        }
+        // right magic bytes, broken data
+        match render(b"\x89PNG\r\n\x1a\nbroken") {
+            Err(ApiError::ParsingError(field, _)) => assert_eq!(field, "avatar"),
+            _ => panic!("expected a parsing error"),
+        }
+    }
+
+    #[test]
+    fn should_check_uploads_against_their_magic_bytes() {
+        let jpeg: Vec<u8> = jpeg_with_exif();
+        assert_eq!(check_upload("avatar", &jpeg, Some("image/jpeg"), "me.JPG").unwrap(), ImageFormat::Jpeg);
+        assert_eq!(check_upload("avatar", &jpeg, Some("image/jpg"), "me").unwrap(), ImageFormat::Jpeg);
+        assert_eq!(check_upload("avatar", b"GIF89a...", None, "me.gif").unwrap(), ImageFormat::Gif);
+        assert_eq!(check_upload("avatar", b"RIFF\0\0\0\0WEBPVP8 ", None, "me.webp").unwrap(), ImageFormat::WebP);
+
+        for (content_type, filename) in [(Some("image/png"), "me.jpg"), (Some("image/jpeg"), "me.png"), (None, "me.exe")] {
+            match check_upload("avatar", &jpeg, content_type, filename) {
+                Err(ApiError::UnsupportedMediaType(field, _)) => assert_eq!(field, "avatar"),
+                _ => panic!("expected an unsupported media type for {:?} {}", content_type, filename),
+            }
+        }
+        assert!(check_upload("avatar", b"<svg></svg>", Some("image/svg+xml"), "me.svg").is_err());
+    }
+
+    #[test]
+    fn should_parse_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=500-5000", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(variant_key("42/abc.png", Some(64)), "42/abc_64.png");
+    }
+
+    #[tokio::test]
+    async fn should_serve_avatars_with_ranges_and_thumbnails() {
+        let root = std::env::temp_dir().join(format!("groupware-avatar-{}", std::process::id()));
+        let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(root.clone(), "/storage", b"secret"));
+        let key: String = store(storage.as_ref(), "tmp/abc", jpeg_with_exif().into()).await.unwrap();
+        move_to(storage.as_ref(), &key, "42/abc.jpg").await.unwrap();
+        let filter = init(storage.clone());
+
+        let res = warp::test::request().path("/storage/42/abc.jpg").reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "image/jpeg");
+        assert_eq!(res.headers()[CACHE_CONTROL], CACHE_CONTROL_VALUE);
+        let len: usize = res.body().len();
+
+        let res = warp::test::request().path("/storage/42/abc.jpg").header("range", "bytes=0-9").reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_RANGE], format!("bytes 0-9/{}", len));
+        assert_eq!(res.body().len(), 10);
+
+        let res = warp::test::request().path("/storage/42/abc.jpg").header("if-none-match", "\"abc.jpg\"").reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let res = warp::test::request().path("/storage/42/abc.jpg?size=64").reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(image::load_from_memory(res.body()).unwrap().dimensions(), (64, 64));
+
+        let res = warp::test::request().path("/storage/42/abc.jpg?size=65").reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = warp::test::request().path("/storage/tmp/abc.jpg").reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        delete(storage.as_ref(), "42/abc.jpg").await.unwrap();
+        let res = warp::test::request().path("/storage/42/abc.jpg?size=64").reply(&filter).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+
+    /// Local files, but moves start failing after `moves_left` of them
+    struct Flaky {
+        inner: LocalStorage,
+        moves_left: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Storage for Flaky {
+        async fn put(&self, key: &str, data: Bytes, content_type: &str) -> Result<(), ApiError> {
+            self.inner.put(key, data, content_type).await
+        }
+
+        async fn get(&self, key: &str) -> Result<Bytes, ApiError> {
+            self.inner.get(key).await
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), ApiError> {
+            self.inner.delete(key).await
+        }
+
+        async fn move_to(&self, from: &str, to: &str) -> Result<(), ApiError> {
+            if self.moves_left.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| x.checked_sub(1)).is_err() {
+                return Err(ApiError::Storage(std::io::Error::other("disk on fire")));
+            }
+            self.inner.move_to(from, to).await
+        }
+
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, ApiError> {
+            self.inner.list(prefix).await
+        }
+
+        async fn presign(&self, key: &str, expires_in: Duration) -> Result<String, ApiError> {
+            self.inner.presign(key, expires_in).await
+        }
+
+        async fn check(&self) -> Result<(), ApiError> {
+            self.inner.check().await
+        }
+
+        fn location(&self) -> String {
+            self.inner.location()
+        }
+    }
+
+    async fn flaky_storage(name: &str, moves: usize) -> (Arc<Flaky>, String) {
+        let root = std::env::temp_dir().join(format!("groupware-staged-{}-{}", name, std::process::id()));
+        let storage = Flaky {
+            inner: LocalStorage::new(root, "/storage", b"secret"),
+            moves_left: AtomicUsize::new(usize::MAX),
+        };
+        let upload: String = store(&storage, "tmp/new", jpeg_with_exif().into()).await.unwrap();
+        storage.moves_left.store(moves, Ordering::SeqCst);
+        (Arc::new(storage), upload)
+    }
+
+    async fn keys(storage: &Flaky) -> Vec<String> {
+        let mut keys: Vec<String> = storage.list("").await.unwrap();
+        keys.sort();
+        keys
+    }
+
+    fn failure() -> Result<(), ApiError> {
+        Err(ApiError::NotFound("User".to_string()))
+    }
+
+    // each step of creating a user: the query before the move, the move itself, and what comes after it
+    #[tokio::test]
+    async fn should_leave_no_file_behind_when_creation_fails() {
+        let (storage, upload) = flaky_storage("before", usize::MAX).await;
+        let mut files = Staged::new(storage.clone(), Some(upload));
+        assert!(files.settle(failure()).await.is_err());
+        assert!(keys(&storage).await.is_empty());
+        let _ = tokio::fs::remove_dir_all(&storage.inner.location()).await;
+
+        // the original and one thumbnail are moved before the storage gives up
+        let (storage, upload) = flaky_storage("moving", 2).await;
+        let mut files = Staged::new(storage.clone(), Some(upload.clone()));
+        let result = files.add(&upload, "42/new.jpg").await;
+        assert!(result.is_err());
+        assert!(files.settle(result).await.is_err());
+        assert!(keys(&storage).await.is_empty());
+        let _ = tokio::fs::remove_dir_all(&storage.inner.location()).await;
+
+        let (storage, upload) = flaky_storage("after", usize::MAX).await;
+        let mut files = Staged::new(storage.clone(), Some(upload.clone()));
+        files.add(&upload, "42/new.jpg").await.unwrap();
+        assert!(files.settle(failure()).await.is_err());
+        assert!(keys(&storage).await.is_empty());
+        let _ = tokio::fs::remove_dir_all(&storage.inner.location()).await;
+    }
+
+    #[tokio::test]
+    async fn should_rewind_for_another_attempt() {
+        let (storage, upload) = flaky_storage("rewind", usize::MAX).await;
+        let uploaded: Vec<String> = keys(&storage).await;
+        let mut files = Staged::new(storage.clone(), Some(upload.clone()));
+        files.add(&upload, "41/new.jpg").await.unwrap();
+        files.replace("41/old.jpg");
+        files.rewind().await.unwrap();
+        assert_eq!(keys(&storage).await, uploaded);
+
+        // the next attempt may create another node, and so move the upload to another key
+        files.add(&upload, "42/new.jpg").await.unwrap();
+        assert!(files.settle(failure()).await.is_err());
+        assert!(keys(&storage).await.is_empty());
+        let _ = tokio::fs::remove_dir_all(&storage.inner.location()).await;
+    }
+
+    #[tokio::test]
+    async fn should_delete_replaced_avatar_only_after_commit() {
+        let (storage, upload) = flaky_storage("replace", usize::MAX).await;
+        move_to(storage.as_ref(), &upload, "42/old.jpg").await.unwrap();
+        let old: Vec<String> = keys(&storage).await;
+
+        let upload: String = store(storage.as_ref(), "tmp/new", jpeg_with_exif().into()).await.unwrap();
+        let mut files = Staged::new(storage.clone(), Some(upload.clone()));
+        files.add(&upload, "42/new.jpg").await.unwrap();
+        files.replace("42/old.jpg");
+        assert!(files.settle(failure()).await.is_err());
+        assert_eq!(keys(&storage).await, old);
+
+        let upload: String = store(storage.as_ref(), "tmp/new", jpeg_with_exif().into()).await.unwrap();
+        let mut files = Staged::new(storage.clone(), Some(upload.clone()));
+        files.add(&upload, "42/new.jpg").await.unwrap();
+        files.replace("42/old.jpg");
+        assert!(files.settle(Ok(())).await.is_ok());
+        assert_eq!(keys(&storage).await, vec![
+            "42/new.jpg".to_string(),
+            "42/new_128.jpg".to_string(),
+            "42/new_256.jpg".to_string(),
+            "42/new_64.jpg".to_string(),
+        ]);
+        let _ = tokio::fs::remove_dir_all(&storage.inner.location()).await;
+    }
+}
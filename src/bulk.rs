@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use warp::{
+    http::StatusCode,
+    Reply,
+};
+
+use crate::error_handler::ApiError;
+
+// bulk requests run every id inside one transaction
+// the first id that can't be processed rolls back all the others
+
+/// `action` of the body, anything else is rejected while parsing it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkAction {
+    Trash,
+    Restore,
+    Erase,
+    Update,
+}
+
+/// `patch` is optional in the body, but can't be missing for `update`
+pub fn require_patch<T>(action: BulkAction, patch: &Option<T>) -> Result<(), ApiError> {
+    if action == BulkAction::Update && patch.is_none() {
+        return Err(ApiError::ParsingError("patch".to_string(), "Required for update".to_string()));
+    }
+    Ok(())
+}
+
+/// Every repetition of an id after the first one is a failure
+pub fn find_duplicates(ids: &[i64]) -> Vec<(i64, ApiError)> {
+    let mut seen: HashSet<i64> = HashSet::new();
+    ids.iter()
+        .filter(|id| !seen.insert(**id))
+        .map(|id| (*id, ApiError::Conflict("Duplicate id".to_string())))
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BulkResult {
+    pub id: i64,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BulkResponse {
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    results: Vec<BulkResult>,
+}
+
+/// One result per id in request order, ids that were fine but rolled back are reported as 424
+pub fn results(ids: &[i64], failed: &[(i64, ApiError)]) -> Vec<BulkResult> {
+    ids.iter()
+        .map(|id| match failed.iter().find(|(failed_id, _)| failed_id == id) {
+            Some((_, e)) => BulkResult {
+                id: *id,
+                status: e.status_code().as_u16(),
+                message: Some(e.public_message()),
+            },
+            None if !failed.is_empty() => BulkResult {
+                id: *id,
+                status: StatusCode::FAILED_DEPENDENCY.as_u16(),
+                message: Some("Rolled back".to_string()),
+            },
+            None => BulkResult {
+                id: *id,
+                status: StatusCode::OK.as_u16(),
+                message: None,
+            },
+        })
+        .collect()
+}
+
+pub fn into_reply(ids: &[i64], failed: Vec<(i64, ApiError)>) -> warp::reply::Response {
+    let (code, message) = if failed.is_empty() {
+        (StatusCode::OK, None)
+    } else {
+        (StatusCode::CONFLICT, Some("Bulk action was rolled back".to_string()))
+    };
+    let json = warp::reply::json(&BulkResponse {
+        success: failed.is_empty(),
+        message,
+        results: results(ids, &failed),
+    });
+    warp::reply::with_status(json, code).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_flag_repeated_ids() {
+        let duplicates = find_duplicates(&[1, 2, 1, 3, 2]);
+        let ids: Vec<i64> = duplicates.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn should_report_every_id_as_ok_when_committed() {
+        let results = results(&[1, 2], &[]);
+        assert!(results.iter().all(|x| x.status == 200 && x.message.is_none()));
+    }
+
+    #[test]
+    fn should_report_rolled_back_ids() {
+        let results = results(&[1, 2, 3], &[(2, ApiError::NotFound("Company".to_string()))]);
+        assert_eq!(results[0].status, 424);
+        assert_eq!(results[1], BulkResult {
+            id: 2,
+            status: 404,
+            message: Some("Company not found".to_string()),
+        });
+        assert_eq!(results[2].status, 424);
+    }
+
+    #[test]
+    fn should_require_patch_for_update_only() {
+        assert!(require_patch::<()>(BulkAction::Update, &None).is_err());
+        assert!(require_patch(BulkAction::Update, &Some(())).is_ok());
+        assert!(require_patch::<()>(BulkAction::Trash, &None).is_ok());
+    }
+}
@@ -1,196 +1,555 @@
 use std::{
-    convert::Infallible,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
     vec::Vec,
 };
-use warp::http::StatusCode;
+use warp::{
+    http::StatusCode,
+    Reply,
+};
 
+use crate::audit::{self, diff, AuditAction};
+use crate::bulk::{self, BulkAction};
 use crate::company::{
+    BulkCompaniesParams,
     CompanyResponse,
     CreateCompanyParams,
     FindCompaniesRequest,
     UpdateCompanyParams,
+    SORT_KEYS,
+};
+use crate::error_handler::ApiError;
+use crate::helpers::{
+    DeleteParams,
+    fetch_one,
+    fetch_one_in,
+    parse_id,
+};
+use crate::membership::find_member_summaries;
+use crate::pagination::{Page, Pagination};
+use crate::precondition::{
+    not_modified,
+    with_etag,
+    Precondition,
+};
+use crate::policy::{
+    authorize_company,
+    find_company_role,
+    CompanyAction,
+    Role,
 };
-use crate::helpers::DeleteParams;
+use crate::query_builder::{QueryBuilder, Trashed};
+use crate::sorting::{parse_sort_by, SortKey};
+use crate::user::UserResponse;
 
+#[tracing::instrument(skip_all)]
 pub async fn find_companies(
     req: FindCompaniesRequest,
+    trashed: Trashed,
+    include: Vec<String>,
+    pagination: Pagination,
     graph: Arc<neo4rs::Graph>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut terms = vec!["MATCH (c:Company)"];
-    let search_term;
-    let sort_by_term;
-    let limit_term;
-
-    if req.search.is_some() {
-        let search: String = req.search.unwrap().trim().to_string().clone();
+    let mut builder = QueryBuilder::new("c", "Company")
+        .trashed(trashed);
+    if let Some(search) = req.search {
+        let search = search.trim();
         if !search.is_empty() {
-            search_term = format!("WHERE c.name CONTAINS '{}'", search);
-            terms.push(search_term.as_str());
+            builder = builder.where_contains(&["name"], search);
         }
     }
-    if req.sort_by.is_some() {
-        let sort_by: String = req.sort_by.unwrap();
-        sort_by_term = format!("SORT c.{} ASC", sort_by);
-        terms.push(sort_by_term.as_str());
+    builder = builder.returns();
+    if let Some(sort_by) = req.sort_by {
+        let keys: Vec<SortKey> = parse_sort_by(&sort_by, SORT_KEYS).map_err(|e| {
+            warp::reject::custom(
+                ApiError::ParsingError("sort_by".to_string(), e)
+            )
+        })?;
+        builder = builder.order_by(&keys, SORT_KEYS);
     }
+    builder = builder
+        .skip(pagination.offset)
+        .limit(pagination.per_page);
 
-    terms.push("RETURN c");
-    if req.limit.is_some() {
-        let limit: u32 = req.limit.unwrap();
-        limit_term = format!("SKIP 0 LIMIT {}", limit);
-        terms.push(limit_term.as_str());
-    }
-    let q = terms.join(" ");
+    let row: neo4rs::Row = fetch_one(&graph, builder.build_count(), "Company").await?;
+    let total: i64 = row.get("total").unwrap_or(0);
 
-    let mut result: neo4rs::RowStream = graph.execute(neo4rs::query(&q)).await.unwrap();
+    let mut result: neo4rs::RowStream = graph.execute(builder.build()).await.map_err(ApiError::from)?;
     let mut records: Vec<CompanyResponse> = vec![];
-    while let Ok(Some(row)) = result.next().await {
+    while let Some(row) = result.next().await.map_err(ApiError::from)? {
         records.push(CompanyResponse::from_row(row));
     }
-    Ok(warp::reply::json(&records))
+    if include.iter().any(|x| x == "members") {
+        let ids: Vec<i64> = records.iter().map(|x| x.id).collect();
+        let mut summaries = find_member_summaries(&graph, ids).await?;
+        for record in records.iter_mut() {
+            record.members = Some(summaries.remove(&record.id).unwrap_or_default());
+        }
+    }
+    Ok(Page::new(records, total, &pagination).into_reply(&pagination))
 }
 
+/// ETag only covers the company node, so it's left out when related records are embedded
+#[tracing::instrument(skip_all, fields(id = %id))]
 pub async fn show_company(
     id: String,
+    trashed: Trashed,
+    include: Vec<String>,
+    precondition: Option<Precondition>,
     graph: Arc<neo4rs::Graph>,
-) -> Result<impl warp::Reply, Infallible> {
-    let q: neo4rs::Query = neo4rs::query("
-        MATCH (c:Company)
-        WHERE id(c) = $id
-        RETURN c
-    ")
-    .param("id", id.parse::<i64>().unwrap());
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let q: neo4rs::Query = QueryBuilder::new("c", "Company")
+        .where_id(parse_id(&id)?)
+        .trashed(trashed)
+        .returns()
+        .build();
 
-    let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-    let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-    let record: CompanyResponse = CompanyResponse::from_row(row);
-    Ok(warp::reply::json(&record))
+    let row: neo4rs::Row = fetch_one(&graph, q, "Company").await?;
+    let mut record: CompanyResponse = CompanyResponse::from_row(row);
+    if include.is_empty() {
+        if precondition.is_some_and(|x| x.matches(record.version)) {
+            return Ok(not_modified(record.version));
+        }
+        return Ok(with_etag(warp::reply::json(&record), record.version));
+    }
+    if include.iter().any(|x| x == "members") {
+        let mut summaries = find_member_summaries(&graph, vec![record.id]).await?;
+        record.members = Some(summaries.remove(&record.id).unwrap_or_default());
+    }
+    Ok(warp::reply::json(&record).into_response())
 }
 
+#[tracing::instrument(skip_all, fields(actor = current_user.id))]
 pub async fn create_company(
+    current_user: UserResponse,
     params: CreateCompanyParams,
     graph: Arc<neo4rs::Graph>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let q: neo4rs::Query = neo4rs::query("
-        CREATE (c:Company {
-            name: $name,
-            since: date($since),
-            createdAt: datetime(),
-            updatedAt: datetime()
-        })
-        RETURN c
-    ")
-    .param("name", params.name.unwrap())
-    .param("since", params.since.unwrap());
-
-    let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-    let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-    let record: CompanyResponse = CompanyResponse::from_row(row);
+    let actor_id: i64 = current_user.id;
+    let record: CompanyResponse = graph.write_transaction(move |txn| {
+        let params = params.clone();
+        Box::pin(async move { insert_company(txn, actor_id, params).await })
+    }).await?;
     Ok(warp::reply::with_status(
         warp::reply::json(&record),
         StatusCode::CREATED,
     ))
 }
 
+#[tracing::instrument(skip_all, fields(id = %id, actor = current_user.id))]
 pub async fn update_company(
     id: String,
+    current_user: UserResponse,
+    precondition: Option<Precondition>,
     params: UpdateCompanyParams,
     graph: Arc<neo4rs::Graph>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut terms = vec!["MATCH (c:Company)"];
-    let w = format!("WHERE id(c) = {}", id);
-    terms.push(w.as_str());
-
-    let mut data = vec![];
-    match params.name {
-        Some(x) => {
-            data.push(format!("c.name = '{}'", x));
-        },
-        None => {},
-    }
-    match params.since {
-        Some(x) => {
-            data.push(format!("c.since = date('{}')", x.to_rfc3339()));
-        },
-        None => {},
-    }
-    let s = format!("SET {}", data.join(", "));
-    terms.push(s.as_str());
-    terms.push("RETURN c");
-
-    let t = terms.join(" ");
-    let q: neo4rs::Query = neo4rs::query(t.as_str());
-    let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-    let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-    let record: CompanyResponse = CompanyResponse::from_row(row);
-    Ok(warp::reply::with_status(
-        warp::reply::json(&record),
-        StatusCode::OK,
+    let id: i64 = parse_id(&id)?;
+    let actor_id: i64 = current_user.id;
+    let record: CompanyResponse = graph.write_transaction(move |txn| {
+        let params = params.clone();
+        let precondition = precondition.clone();
+        Box::pin(async move {
+            change_company(txn, actor_id, AuditAction::Update, id, Trashed::With, Some(params), &precondition).await
+        })
+    }).await?;
+    Ok(with_etag(
+        warp::reply::with_status(warp::reply::json(&record), StatusCode::OK),
+        record.version,
     ))
 }
 
+#[tracing::instrument(skip_all, fields(id = %id, actor = current_user.id))]
 pub async fn delete_company(
     id: String,
+    current_user: UserResponse,
+    precondition: Option<Precondition>,
     params: DeleteParams,
     graph: Arc<neo4rs::Graph>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let empty: Vec<u8> = vec![];
+    let id: i64 = parse_id(&id)?;
+    let action: AuditAction = match AuditAction::from_delete_mode(&params.mode) {
+        Some(x) => x,
+        None => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&empty),
+                StatusCode::BAD_REQUEST,
+            ));
+        },
+    };
+    let actor_id: i64 = current_user.id;
+    let record: CompanyResponse = graph.write_transaction(move |txn| {
+        let precondition = precondition.clone();
+        Box::pin(async move {
+            change_company(txn, actor_id, action, id, Trashed::With, None, &precondition).await
+        })
+    }).await?;
 
-    match params.mode.as_str() {
-        "erase" => {
-            let q: neo4rs::Query = neo4rs::query("
-                MATCH (c:Company)
-                WHERE id(c) = $id
-                DETACH DELETE c
-            ")
-            .param("id", id.parse::<i64>().unwrap());
-
-            graph.execute(q).await.unwrap();
+    match action {
+        AuditAction::Erase => {
             Ok(warp::reply::with_status(
                 warp::reply::json(&empty),
                 StatusCode::NO_CONTENT,
             ))
         },
-        "trash" => {
-            let q: neo4rs::Query = neo4rs::query("
-                MATCH (c:Company)
-                WHERE id(c) = $id
-                SET c.deletedAt = datetime()
-                RETURN c
-            ")
-            .param("id", id.parse::<i64>().unwrap());
-
-            let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-            let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-            let record: CompanyResponse = CompanyResponse::from_row(row);
+        _ => {
             Ok(warp::reply::with_status(
                 warp::reply::json(&record),
                 StatusCode::OK,
             ))
         },
-        "restore" => {
-            let q: neo4rs::Query = neo4rs::query("
+    }
+}
+
+/// Applies one action to many companies, either all of them are changed or none
+#[tracing::instrument(skip_all, fields(actor = current_user.id))]
+pub async fn bulk_companies(
+    current_user: UserResponse,
+    params: BulkCompaniesParams,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let action: BulkAction = params.action;
+    let (company_action, audit_action, trashed) = match action {
+        BulkAction::Update => (CompanyAction::Update, AuditAction::Update, Trashed::With),
+        BulkAction::Trash => (CompanyAction::Trash, AuditAction::Trash, Trashed::Without),
+        BulkAction::Restore => (CompanyAction::Restore, AuditAction::Restore, Trashed::Only),
+        BulkAction::Erase => (CompanyAction::Erase, AuditAction::Erase, Trashed::With),
+    };
+
+    // nothing is touched until every id is known to be allowed
+    let mut failed = bulk::find_duplicates(&params.ids);
+    if failed.is_empty() {
+        for id in params.ids.iter() {
+            let allowed = find_company_role(&graph, *id, current_user.id).await
+                .and_then(|role| authorize_company(role, company_action));
+            match allowed {
+                Ok(_) => {},
+                Err(e @ ApiError::NotFound(_)) | Err(e @ ApiError::Forbidden(_)) => failed.push((*id, e)),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    if !failed.is_empty() {
+        return Ok(bulk::into_reply(&params.ids, failed));
+    }
+
+    // the id being changed when the transaction failed, one that wasn't found rolls back the others
+    let current = Arc::new(AtomicI64::new(0));
+    let actor_id: i64 = current_user.id;
+    let ids: Vec<i64> = params.ids.clone();
+    let patch = params.patch;
+    let result = graph.write_transaction({
+        let current = current.clone();
+        move |txn| {
+            let current = current.clone();
+            let ids = ids.clone();
+            let patch = patch.clone();
+            Box::pin(async move {
+                for id in ids {
+                    current.store(id, Ordering::SeqCst);
+                    change_company(txn, actor_id, audit_action, id, trashed, patch.clone(), &None).await?;
+                }
+                Ok(())
+            })
+        }
+    }).await;
+    match result {
+        Ok(()) => {},
+        Err(e @ ApiError::NotFound(_)) => failed.push((current.load(Ordering::SeqCst), e)),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(bulk::into_reply(&params.ids, failed))
+}
+
+async fn insert_company(
+    txn: &neo4rs::Txn,
+    actor_id: i64,
+    params: CreateCompanyParams,
+) -> Result<CompanyResponse, ApiError> {
+    // creator becomes the owner
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)
+        WHERE id(u) = $user_id
+        CREATE (u)-[:MEMBER_OF {role: $role, since: date()}]->(c:Company {
+            name: $name,
+            since: date($since),
+            version: 1,
+            createdAt: datetime(),
+            updatedAt: datetime()
+        })
+        RETURN c
+    ")
+    .param("user_id", actor_id)
+    .param("role", Role::Owner.as_str())
+    .param("name", params.name.unwrap())
+    .param("since", params.since.unwrap());
+
+    let row: neo4rs::Row = fetch_one_in(txn, q, "Company").await?;
+    let record: CompanyResponse = CompanyResponse::from_row(row);
+    audit::record(txn, actor_id, AuditAction::Create, "Company", record.id, diff(None, Some(&record))).await?;
+    Ok(record)
+}
+
+/// Changes one company inside `txn` and records it in the audit log
+///
+/// `trashed` limits which companies can be found at all, `precondition` is the If-Match of the request.
+/// Returns the company after the change, or as it was before for erase.
+async fn change_company(
+    txn: &neo4rs::Txn,
+    actor_id: i64,
+    action: AuditAction,
+    id: i64,
+    trashed: Trashed,
+    patch: Option<UpdateCompanyParams>,
+    precondition: &Option<Precondition>,
+) -> Result<CompanyResponse, ApiError> {
+    let q: neo4rs::Query = QueryBuilder::new("c", "Company")
+        .where_id(id)
+        .trashed(trashed)
+        .returns()
+        .build();
+    let before: CompanyResponse = CompanyResponse::from_row(fetch_one_in(txn, q, "Company").await?);
+    if precondition.as_ref().is_some_and(|x| !x.matches(before.version)) {
+        return Err(ApiError::PreconditionFailed("Company".to_string()));
+    }
+
+    // the version is checked again by the write, it may have moved on since the read
+    let mut builder = QueryBuilder::new("c", "Company")
+        .where_id(id)
+        .where_precondition(precondition);
+    builder = match action {
+        AuditAction::Erase => {
+            audit::record(txn, actor_id, action, "Company", id, diff(Some(&before), None)).await?;
+            let versions: Option<Vec<i64>> = precondition.as_ref().and_then(Precondition::versions);
+            let condition: &str = if versions.is_some() { "AND coalesce(c.version, 0) IN $versions" } else { "" };
+            let q: neo4rs::Query = neo4rs::query(&format!("
                 MATCH (c:Company)
-                WHERE id(c) = $id
-                REMOVE c.deletedAt
-                RETURN c
-            ")
-            .param("id", id.parse::<i64>().unwrap());
-
-            let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-            let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-            let record: CompanyResponse = CompanyResponse::from_row(row);
-            Ok(warp::reply::with_status(
-                warp::reply::json(&record),
-                StatusCode::OK,
-            ))
+                WHERE id(c) = $id {}
+                OPTIONAL MATCH (d:Department)-[:PART_OF*1..]->(c)
+                DETACH DELETE d, c
+                RETURN count(DISTINCT c) AS count
+            ", condition))
+            .param("id", id)
+            .param("versions", versions.unwrap_or_default());
+
+            let row: neo4rs::Row = fetch_one_in(txn, q, "Company").await?;
+            if row.get::<i64>("count") != Some(1) {
+                return Err(ApiError::PreconditionFailed("Company".to_string()));
+            }
+            return Ok(before);
         },
-        &_ => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&empty),
-                StatusCode::BAD_REQUEST,
-            ))
+        AuditAction::Trash => builder.set_now("deletedAt"),
+        AuditAction::Restore => builder.remove("deletedAt"),
+        AuditAction::Create | AuditAction::Update => {
+            let params: UpdateCompanyParams = patch.unwrap_or_default();
+            if let Some(x) = params.name {
+                builder = builder.set("name", x);
+            }
+            if let Some(x) = params.since {
+                builder = builder.set_with("since", "date", x.date_naive());
+            }
+            builder.set_now("updatedAt")
         },
+    };
+    let q: neo4rs::Query = builder
+        .bump_version()
+        .returns()
+        .build();
+    let after: CompanyResponse = match fetch_one_in(txn, q, "Company").await {
+        Ok(row) => CompanyResponse::from_row(row),
+        Err(ApiError::NotFound(_)) => return Err(ApiError::PreconditionFailed("Company".to_string())),
+        Err(e) => return Err(e),
+    };
+    audit::record(txn, actor_id, action, "Company", id, diff(Some(&before), Some(&after))).await?;
+    Ok(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDate};
+    use neo4rs::testing::{node, BoltType, Expect, Reply, Script, StubServer};
+    use crate::test_support::{actor, connect};
+
+    const INSERT: &str = "
+        MATCH (u:User)
+        WHERE id(u) = $user_id
+        CREATE (u)-[:MEMBER_OF {role: $role, since: date()}]->(c:Company {
+            name: $name,
+            since: date($since),
+            version: 1,
+            createdAt: datetime(),
+            updatedAt: datetime()
+        })
+        RETURN c
+    ";
+
+    fn since() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, 1, 1).unwrap()
+    }
+
+    fn company(version: i64) -> Vec<BoltType> {
+        let now = DateTime::parse_from_rfc3339("2021-06-01T09:00:00+00:00").unwrap();
+        vec![node(42, &["Company"], vec![
+            ("name", "Acme".into()),
+            ("since", since().into()),
+            ("createdAt", now.into()),
+            ("updatedAt", now.into()),
+            ("version", version.into()),
+        ])]
+    }
+
+    fn insert(script: Script) -> Script {
+        script
+            .expect(Expect::begin())
+            .reply(Reply::success())
+            .expect(Expect::run(INSERT)
+                .param("user_id", 1i64)
+                .param("role", "owner")
+                .param("name", "Acme")
+                .param("since", since()))
+    }
+
+    fn params() -> CreateCompanyParams {
+        CreateCompanyParams {
+            name: Some("Acme".to_string()),
+            since: Some(since()),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_create_company_with_audit_event() {
+        let script = insert(Script::new())
+            .returns(&["c"], vec![company(1)])
+            .expect(Expect::run_containing("CREATE (e:AuditEvent")
+                .param("action", "create")
+                .param("diff", r#"{"name":{"from":null,"to":"Acme"},"since":{"from":null,"to":"2020-01-01"}}"#)
+                .param("actor_id", 1i64)
+                .param("label", "Company")
+                .param("target_id", 42i64))
+            .reply(Reply::success())
+            .expect(Expect::discard())
+            .reply(Reply::success())
+            .expect(Expect::commit())
+            .reply(Reply::success());
+        let server = StubServer::start(script).await;
+
+        let reply = create_company(actor(), params(), connect(&server).await).await.unwrap();
+        let response = warp::Reply::into_response(reply);
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], 42);
+        assert_eq!(body["name"], "Acme");
+        assert_eq!(body["since"], "2020-01-01");
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_retry_company_creation_after_deadlock() {
+        let script = insert(Script::new())
+            .reply(Reply::failure("Neo.TransientError.Transaction.DeadlockDetected", "ForsetiClient can't acquire ExclusiveLock"))
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            // recycled by the pool
+            .expect(Expect::reset())
+            .reply(Reply::success());
+        let script = insert(script)
+            .returns(&["c"], vec![company(1)])
+            .expect(Expect::run_containing("CREATE (e:AuditEvent"))
+            .reply(Reply::success())
+            .expect(Expect::discard())
+            .reply(Reply::success())
+            .expect(Expect::commit())
+            .reply(Reply::success());
+        let server = StubServer::start(script).await;
+
+        let reply = create_company(actor(), params(), connect(&server).await).await.unwrap();
+        let response = warp::Reply::into_response(reply);
+        assert_eq!(response.status(), StatusCode::CREATED);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_update_of_stale_version() {
+        let script = Script::new()
+            .expect(Expect::begin())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("MATCH (c:Company)").param("id", 42i64))
+            .returns(&["c"], vec![company(2)])
+            .expect(Expect::rollback())
+            .reply(Reply::success());
+        let server = StubServer::start(script).await;
+
+        let rejection = update_company(
+            "42".to_string(),
+            actor(),
+            Some(Precondition::Versions(vec![1])),
+            UpdateCompanyParams::default(),
+            connect(&server).await,
+        ).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::PreconditionFailed(_))));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_bulk_when_a_company_is_missing() {
+        let mut script = Script::new();
+        for id in [42i64, 43i64] {
+            script = script
+                .expect(Expect::run_containing("OPTIONAL MATCH (u:User)-[m:MEMBER_OF]->(c)").param("company_id", id).param("user_id", 1i64))
+                .returns(&["role"], vec![vec!["owner".into()]])
+                .expect(Expect::reset())
+                .reply(Reply::success());
+        }
+        let script = script
+            .expect(Expect::begin())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("MATCH (c:Company)").param("id", 42i64))
+            .returns(&["c"], vec![company(1)])
+            .expect(Expect::run_containing("c.deletedAt = datetime()"))
+            .returns(&["c"], vec![company(2)])
+            .expect(Expect::run_containing("CREATE (e:AuditEvent"))
+            .reply(Reply::success())
+            .expect(Expect::discard())
+            .reply(Reply::success())
+            // 43 is gone by now, 42 must not stay trashed
+            .expect(Expect::run_containing("MATCH (c:Company)").param("id", 43i64))
+            .returns(&["c"], vec![])
+            .expect(Expect::rollback())
+            .reply(Reply::success());
+        let server = StubServer::start(script).await;
+        let params = BulkCompaniesParams {
+            action: BulkAction::Trash,
+            ids: vec![42, 43],
+            patch: None,
+        };
+
+        let reply = bulk_companies(actor(), params, connect(&server).await).await.unwrap();
+        let response = warp::Reply::into_response(reply);
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["results"][0]["status"], 424);
+        assert_eq!(body["results"][1]["status"], 404);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_find_missing_company() {
+        let script = Script::new()
+            .expect(Expect::run_containing("MATCH (c:Company)").param("id", 7i64))
+            .returns(&["c"], vec![]);
+        let server = StubServer::start(script).await;
+
+        let rejection = show_company("7".to_string(), Trashed::Without, vec![], None, connect(&server).await)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::NotFound(_))));
+        server.finish();
     }
 }
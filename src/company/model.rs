@@ -4,17 +4,22 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
+use crate::bulk::BulkAction;
+use crate::membership::MembershipSummary;
+use crate::sorting::sort_by_regex;
+
 // find
 
 #[derive(Default, Deserialize)]
 pub struct FindCompaniesParams {
     pub search: Option<String>,
     pub sort_by: Option<String>,
-    pub limit: Option<String>,
 }
 
+pub const SORT_KEYS: &[&str] = &["name", "since", "createdAt"];
+
 lazy_static! {
-    static ref REGEX_SORT_BY: Regex = Regex::new(r"(name|capacity)").unwrap();
+    static ref REGEX_SORT_BY: Regex = sort_by_regex(SORT_KEYS);
 }
 
 #[derive(Default, Validate)]
@@ -22,10 +27,10 @@ pub struct FindCompaniesRequest {
     pub search: Option<String>,
     #[validate(regex = "REGEX_SORT_BY")]
     pub sort_by: Option<String>,
-    #[validate(range(min = 5, max = 100))]
-    pub limit: Option<u32>,
 }
 
+pub const INCLUDES: &[&str] = &["members"];
+
 // create
 
 #[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
@@ -44,6 +49,17 @@ pub struct UpdateCompanyParams {
     pub since: Option<DateTime<Utc>>,
 }
 
+// bulk
+
+#[derive(Clone, Debug, Validate, Serialize, Deserialize)]
+pub struct BulkCompaniesParams {
+    pub action: BulkAction,
+    #[validate(length(min = 1, max = 100))]
+    pub ids: Vec<i64>,
+    #[validate]
+    pub patch: Option<UpdateCompanyParams>,
+}
+
 // response
 
 #[derive(Clone, Debug, Serialize)]
@@ -54,8 +70,11 @@ pub struct CompanyResponse {
     pub since: NaiveDate,
     pub created_at: DateTime<FixedOffset>,
     pub updated_at: DateTime<FixedOffset>,
+    pub version: i64,
     #[serde(skip_serializing_if = "Option::is_none")] // if none, excluded from query
     pub deleted_at: Option<DateTime<FixedOffset>>,
+    #[serde(skip_serializing_if = "Option::is_none")] // only with ?include=members
+    pub members: Option<Vec<MembershipSummary>>,
 }
 
 impl CompanyResponse {
@@ -67,7 +86,9 @@ impl CompanyResponse {
             since: c.get("since").unwrap(),
             created_at: c.get("createdAt").unwrap(),
             updated_at: c.get("updatedAt").unwrap(),
+            version: c.get("version").unwrap_or(0),
             deleted_at: c.get("deletedAt"),
+            members: None,
         }
     }
 }
@@ -1,18 +1,31 @@
-use serde_json::Deserializer;
 use std::sync::Arc;
 use validator::Validate;
-use warp::{
-    http::HeaderValue,
-    Buf, Filter,
-};
+use warp::Filter;
 
+use crate::bulk::require_patch;
 use crate::helpers::{
+    with_json_body,
     DeleteParams,
+    parse_id,
     with_db,
+    with_include,
+    with_trashed,
 };
+use crate::auth::{require_auth, with_auth, JwtKeys};
 use crate::error_handler::ApiError;
+use crate::pagination::with_pagination;
+use crate::precondition::{with_if_match, with_if_none_match, Precondition};
+use crate::query_builder::Trashed;
+use crate::policy::{
+    authorize_company,
+    find_company_role,
+    CompanyAction,
+    Role,
+};
+use crate::user::UserResponse;
 use crate::company::{
     self,
+    BulkCompaniesParams,
     CreateCompanyParams,
     FindCompaniesParams,
     FindCompaniesRequest,
@@ -21,21 +34,46 @@ use crate::company::{
 
 pub fn init(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    find_companies(graph.clone())
-        .or(show_company(graph.clone()))
-        .or(create_company(graph.clone()))
-        .or(update_company(graph.clone()))
-        .or(delete_company(graph))
+    find_companies(graph.clone(), keys.clone())
+        .or(find_trashed_companies(graph.clone(), keys.clone()))
+        .or(show_company(graph.clone(), keys.clone()))
+        .or(create_company(graph.clone(), keys.clone()))
+        .or(bulk_companies(graph.clone(), keys.clone()))
+        .or(update_company(graph.clone(), keys.clone()))
+        .or(delete_company(graph, keys))
 }
 
 /// GET /companies
 fn find_companies(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("companies")
         .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_find_request())
+        .and(with_trashed())
+        .and(with_include(company::INCLUDES))
+        .and(with_pagination())
+        .and(with_db(graph))
+        .and_then(company::find_companies)
+}
+
+/// GET /companies/trash
+// must be tried before GET /companies/:id
+fn find_trashed_companies(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / "trash")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
         .and(with_find_request())
+        .and(warp::any().map(|| Trashed::Only))
+        .and(with_include(company::INCLUDES))
+        .and(with_pagination())
         .and(with_db(graph))
         .and_then(company::find_companies)
 }
@@ -43,9 +81,14 @@ fn find_companies(
 /// GET /companies/:id
 fn show_company(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("companies" / String)
         .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_trashed())
+        .and(with_include(company::INCLUDES))
+        .and(with_if_none_match())
         .and(with_db(graph))
         .and_then(company::show_company)
 }
@@ -53,20 +96,44 @@ fn show_company(
 /// POST /companies
 fn create_company(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("companies")
         .and(warp::post())
+        .and(with_auth(graph.clone(), keys))
         .and(with_create_params())
         .and(with_db(graph))
         .and_then(company::create_company)
 }
 
+/// POST /companies/bulk
+fn bulk_companies(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / "bulk")
+        .and(warp::post())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_bulk_params())
+        .and(with_db(graph))
+        .and_then(company::bulk_companies)
+}
+
 /// PATCH /companies/:id
 fn update_company(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("companies" / String)
         .and(warp::patch())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph, CompanyAction::Update).await?;
+            Ok::<(String, UserResponse), warp::Rejection>((id, current_user))
+        })
+        .untuple_one()
+        .and(with_if_match())
         .and(with_update_params())
         .and(with_db(graph))
         .and_then(company::update_company)
@@ -75,14 +142,34 @@ fn update_company(
 /// DELETE /companies/:id
 fn delete_company(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("companies" / String)
         .and(warp::delete())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_if_match())
         .and(with_delete_params())
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, current_user: UserResponse, precondition: Option<Precondition>, params: DeleteParams, graph: Arc<neo4rs::Graph>| async move {
+            let action = CompanyAction::from_delete_mode(&params.mode);
+            authorize(&id, &current_user, &graph, action).await?;
+            Ok::<(String, UserResponse, Option<Precondition>, DeleteParams), warp::Rejection>((id, current_user, precondition, params))
+        })
+        .untuple_one()
         .and(with_db(graph))
         .and_then(company::delete_company)
 }
 
+async fn authorize(
+    id: &str,
+    current_user: &UserResponse,
+    graph: &neo4rs::Graph,
+    action: CompanyAction,
+) -> Result<(), ApiError> {
+    let role: Option<Role> = find_company_role(graph, parse_id(id)?, current_user.id).await?;
+    authorize_company(role, action)
+}
+
 // warp::query::raw can't hook rejection of InvalidQuery for incorrect data type
 // so define FindCompaniesParams that contains string field
 // and define FindCompaniesRequest that contains number field
@@ -96,17 +183,6 @@ fn with_find_request() -> impl Filter<Extract = (FindCompaniesRequest, ), Error
         if params.sort_by.is_some() {
             req.sort_by = params.sort_by;
         }
-        if params.limit.is_some() {
-            let limit = match params.limit.unwrap().parse::<u32>() {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(warp::reject::custom(
-                        ApiError::ParsingError("limit".to_string(), e.to_string())
-                    ));
-                },
-            };
-            req.limit = Some(limit);
-        }
         match req.validate() {
             Ok(_) => Ok(req),
             Err(e) => Err(warp::reject::custom(
@@ -117,112 +193,22 @@ fn with_find_request() -> impl Filter<Extract = (FindCompaniesRequest, ), Error
 }
 
 fn with_create_params() -> impl Filter<Extract = (CreateCompanyParams, ), Error = warp::Rejection> + Clone {
-    warp::any()
-        .and(warp::header::value("content-type"))
-        .and(warp::body::aggregate())
-        .and_then(validate_create_params)
+    with_json_body()
 }
 
-async fn validate_create_params(
-    content_type: HeaderValue,
-    buf: impl Buf,
-) -> Result<CreateCompanyParams, warp::Rejection> {
-    if content_type.to_str().unwrap().starts_with("application/json") == false {
-        return Err(warp::reject::custom(
-            ApiError::ParsingError("content-type".to_string(), "Must be application/json".to_string())
-        ));
-    }
-    let deserializer = &mut Deserializer::from_reader(buf.reader());
-    let params: CreateCompanyParams = match serde_path_to_error::deserialize(deserializer) {
-        Ok(r) => r,
-        Err(e) => {
-            let pieces: Vec<String> = e.to_string().as_str().split(": ").map(String::from).collect();
-            return Err(warp::reject::custom(
-                ApiError::ParsingError(pieces[0].clone(), pieces[1].clone())
-            ));
-        },
-    };
-    match params.validate() {
-        Ok(_) => (),
-        Err(e) => {
-            return Err(warp::reject::custom(
-                ApiError::ValidationErrors(e)
-            ));
-        },
-    }
-    Ok(params)
+fn with_update_params() -> impl Filter<Extract = (UpdateCompanyParams, ), Error = warp::Rejection> + Clone {
+    with_json_body()
 }
 
-fn with_update_params() -> impl Filter<Extract = (UpdateCompanyParams, ), Error = warp::Rejection> + Clone {
-    warp::any()
-        .and(warp::header::value("content-type"))
-        .and(warp::body::aggregate())
-        .and_then(validate_update_params)
+fn with_bulk_params() -> impl Filter<Extract = (BulkCompaniesParams, ), Error = warp::Rejection> + Clone {
+    with_json_body().and_then(validate_bulk_params)
 }
 
-async fn validate_update_params(
-    content_type: HeaderValue,
-    buf: impl Buf,
-) -> Result<UpdateCompanyParams, warp::Rejection> {
-    if content_type.to_str().unwrap().starts_with("application/json") == false {
-        return Err(warp::reject::custom(
-            ApiError::ParsingError("content-type".to_string(), "Must be application/json".to_string())
-        ));
-    }
-    let deserializer = &mut Deserializer::from_reader(buf.reader());
-    let params: UpdateCompanyParams = match serde_path_to_error::deserialize(deserializer) {
-        Ok(r) => r,
-        Err(e) => {
-            let pieces: Vec<String> = e.to_string().as_str().split(": ").map(String::from).collect();
-            return Err(warp::reject::custom(
-                ApiError::ParsingError(pieces[0].clone(), pieces[1].clone())
-            ));
-        },
-    };
-    match params.validate() {
-        Ok(_) => (),
-        Err(e) => {
-            return Err(warp::reject::custom(
-                ApiError::ValidationErrors(e)
-            ));
-        },
-    }
+async fn validate_bulk_params(params: BulkCompaniesParams) -> Result<BulkCompaniesParams, warp::Rejection> {
+    require_patch(params.action, &params.patch)?;
     Ok(params)
 }
 
 fn with_delete_params() -> impl Filter<Extract = (DeleteParams, ), Error = warp::Rejection> + Clone {
-    warp::any()
-        .and(warp::header::value("content-type"))
-        .and(warp::body::aggregate())
-        .and_then(validate_delete_params)
-}
-
-async fn validate_delete_params(
-    content_type: HeaderValue,
-    buf: impl Buf,
-) -> Result<DeleteParams, warp::Rejection> {
-    if content_type.to_str().unwrap().starts_with("application/json") == false {
-        return Err(warp::reject::custom(
-            ApiError::ParsingError("content-type".to_string(), "Must be application/json".to_string())
-        ));
-    }
-    let deserializer = &mut Deserializer::from_reader(buf.reader());
-    let params: DeleteParams = match serde_path_to_error::deserialize(deserializer) {
-        Ok(r) => r,
-        Err(e) => {
-            let pieces: Vec<String> = e.to_string().as_str().split(": ").map(String::from).collect();
-            return Err(warp::reject::custom(
-                ApiError::ParsingError(pieces[0].clone(), pieces[1].clone())
-            ));
-        },
-    };
-    match params.validate() {
-        Ok(_) => (),
-        Err(e) => {
-            return Err(warp::reject::custom(
-                ApiError::ValidationErrors(e)
-            ));
-        },
-    }
-    Ok(params)
+    with_json_body()
 }
@@ -1,29 +1,538 @@
-use std::env;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
-pub fn host() -> String {
-    return env::var("HOST").expect("HOST must be set");
+// settings are layered, every layer overrides the one before it
+// 1. defaults below
+// 2. TOML file from --config or CONFIG_FILE, or config.toml in the working directory if there is one
+// 3. environment variables, including the ones in .env
+// 4. command line flags, like --port 8080
+// nothing panics half way, every problem is collected and reported at once
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub storage: StorageSettings,
+    pub auth: AuthSettings,
+    pub trash: TrashSettings,
+    pub log: LogSettings,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    pub cors_origins: Vec<String>, // "*" allows any origin
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    pub uri: String, // host:port of the bolt server, bolt+s://host:port for TLS, bolt+ssc://host:port to accept self-signed certificates
+    pub username: String,
+    pub password: String,
+    pub database: String,
+    pub max_connections: usize,
+    pub fetch_size: usize,
+    pub ca_certificates: String, // PEM file trusted instead of the public authorities, empty for the public ones
+    pub client_certificate: String, // PEM certificate chain for servers asking for one, with client_key
+    pub client_key: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    pub backend: String, // "local" or "s3"
+    pub root: PathBuf, // used by the local backend
+    pub public_url: String, // prefix of presigned links to local files
+    pub max_upload_bytes: u64, // the whole multipart form
+    pub max_avatar_bytes: u64,
+    pub max_field_bytes: u64, // every text field of a form
+    pub s3: S3Settings,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Settings {
+    pub endpoint: String, // like https://s3.eu-west-1.amazonaws.com or http://localhost:9000
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSettings {
+    pub jwt_secret: String,
+    pub admin_email: String, // first user, created at startup unless the email is taken, so that a fresh database can be signed into
+    pub admin_password: String,
 }
 
-pub fn port() -> String {
-    return env::var("PORT").expect("PORT must be set");
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrashSettings {
+    pub retention_days: i64, // trashed records are erased by the purge task after this
+    pub purge_interval_minutes: u64,
 }
 
-pub fn db_host() -> String {
-    return env::var("NEO4J_HOST").expect("NEO4J_HOST must be set");
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    pub level: String, // filter directives, like `info` or `info,neo4rs=debug`
 }
 
-pub fn db_port() -> String {
-    return env::var("NEO4J_PORT").expect("NEO4J_PORT must be set");
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            host: "127.0.0.1".to_string(),
+            port: 7070,
+            cors_origins: vec!["*".to_string()],
+        }
+    }
 }
 
-pub fn db_username() -> String {
-    return env::var("NEO4J_USERNAME").expect("NEO4J_USERNAME must be set");
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings {
+            uri: "localhost:7687".to_string(),
+            username: "neo4j".to_string(),
+            password: String::new(),
+            database: "neo4j".to_string(),
+            max_connections: 10,
+            fetch_size: 500,
+            ca_certificates: String::new(),
+            client_certificate: String::new(),
+            client_key: String::new(),
+        }
+    }
 }
 
-pub fn db_password() -> String {
-    return env::var("NEO4J_PASSWORD").expect("NEO4J_PASSWORD must be set");
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            backend: "local".to_string(),
+            root: PathBuf::from("storage"),
+            public_url: "/storage".to_string(),
+            max_upload_bytes: 5_000_000,
+            max_avatar_bytes: 5_000_000,
+            max_field_bytes: 10_000,
+            s3: S3Settings::default(),
+        }
+    }
 }
 
-pub fn db_database() -> String {
-    return env::var("NEO4J_DATABASE").expect("NEO4J_DATABASE must be set");
+impl Default for TrashSettings {
+    fn default() -> Self {
+        TrashSettings {
+            retention_days: 30,
+            purge_interval_minutes: 60,
+        }
+    }
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: "info".to_string(),
+        }
+    }
+}
+
+/// Where a setting can be overridden, besides the TOML file
+struct Override {
+    key: &'static str,
+    env: &'static str,
+    flag: &'static str,
+}
+
+const OVERRIDES: &[Override] = &[
+    Override { key: "server.host", env: "HOST", flag: "--host" },
+    Override { key: "server.port", env: "PORT", flag: "--port" },
+    Override { key: "server.cors_origins", env: "ORIGIN_ALLOWED", flag: "--cors-origins" },
+    Override { key: "database.uri", env: "NEO4J_URI", flag: "--db-uri" },
+    Override { key: "database.username", env: "NEO4J_USERNAME", flag: "--db-username" },
+    Override { key: "database.password", env: "NEO4J_PASSWORD", flag: "--db-password" },
+    Override { key: "database.database", env: "NEO4J_DATABASE", flag: "--db-database" },
+    Override { key: "database.max_connections", env: "NEO4J_MAX_CONNECTIONS", flag: "--db-max-connections" },
+    Override { key: "database.fetch_size", env: "NEO4J_FETCH_SIZE", flag: "--db-fetch-size" },
+    Override { key: "database.ca_certificates", env: "NEO4J_CA_CERTIFICATES", flag: "--db-ca-certificates" },
+    Override { key: "database.client_certificate", env: "NEO4J_CLIENT_CERTIFICATE", flag: "--db-client-certificate" },
+    Override { key: "database.client_key", env: "NEO4J_CLIENT_KEY", flag: "--db-client-key" },
+    Override { key: "storage.backend", env: "STORAGE_BACKEND", flag: "--storage-backend" },
+    Override { key: "storage.root", env: "STORAGE_ROOT", flag: "--storage-root" },
+    Override { key: "storage.public_url", env: "STORAGE_PUBLIC_URL", flag: "--storage-public-url" },
+    Override { key: "storage.max_upload_bytes", env: "MAX_UPLOAD_BYTES", flag: "--max-upload-bytes" },
+    Override { key: "storage.max_avatar_bytes", env: "MAX_AVATAR_BYTES", flag: "--max-avatar-bytes" },
+    Override { key: "storage.max_field_bytes", env: "MAX_FIELD_BYTES", flag: "--max-field-bytes" },
+    Override { key: "storage.s3.endpoint", env: "S3_ENDPOINT", flag: "--s3-endpoint" },
+    Override { key: "storage.s3.bucket", env: "S3_BUCKET", flag: "--s3-bucket" },
+    Override { key: "storage.s3.region", env: "S3_REGION", flag: "--s3-region" },
+    Override { key: "storage.s3.access_key", env: "S3_ACCESS_KEY", flag: "--s3-access-key" },
+    Override { key: "storage.s3.secret_key", env: "S3_SECRET_KEY", flag: "--s3-secret-key" },
+    Override { key: "auth.jwt_secret", env: "JWT_SECRET", flag: "--jwt-secret" },
+    Override { key: "auth.admin_email", env: "ADMIN_EMAIL", flag: "--admin-email" },
+    Override { key: "auth.admin_password", env: "ADMIN_PASSWORD", flag: "--admin-password" },
+    Override { key: "trash.retention_days", env: "TRASH_RETENTION_DAYS", flag: "--trash-retention-days" },
+    Override { key: "trash.purge_interval_minutes", env: "PURGE_INTERVAL_MINUTES", flag: "--purge-interval-minutes" },
+    Override { key: "log.level", env: "LOG_LEVEL", flag: "--log-level" },
+];
+
+const CONFIG_FLAG: &str = "--config";
+const CONFIG_ENV: &str = "CONFIG_FILE";
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+impl Settings {
+    /// Builds the settings from every layer, `args` without the program name
+    pub fn load(args: &[String], vars: &HashMap<String, String>) -> Result<Settings, Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+        let flags: Vec<(String, String)> = parse_flags(args, &mut errors);
+
+        let config_file: Option<PathBuf> = flags
+            .iter()
+            .find(|(flag, _)| flag == CONFIG_FLAG)
+            .map(|(_, value)| value.clone())
+            .or_else(|| vars.get(CONFIG_ENV).cloned())
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
+
+        let mut settings: Settings = match config_file {
+            Some(path) => match read_file(&path) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(e);
+                    Settings::default()
+                },
+            },
+            None => Settings::default(),
+        };
+        for o in OVERRIDES {
+            if let Some(value) = vars.get(o.env) {
+                if let Err(e) = settings.set(o.key, value) {
+                    errors.push(format!("{} ({}): {}", o.env, o.key, e));
+                }
+            }
+        }
+        for (flag, value) in flags.iter().filter(|(flag, _)| flag != CONFIG_FLAG) {
+            let o = OVERRIDES.iter().find(|o| o.flag == flag).unwrap();
+            if let Err(e) = settings.set(o.key, value) {
+                errors.push(format!("{} ({}): {}", o.flag, o.key, e));
+            }
+        }
+        errors.extend(settings.validate());
+
+        if errors.is_empty() {
+            Ok(settings)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+            value.trim().parse::<T>().map_err(|_| format!("{:?} is not a valid number", value))
+        }
+        match key {
+            "server.host" => self.server.host = value.to_string(),
+            "server.port" => self.server.port = number(value)?,
+            "server.cors_origins" => {
+                self.server.cors_origins = value
+                    .split(',')
+                    .map(|x| x.trim().to_string())
+                    .filter(|x| !x.is_empty())
+                    .collect();
+            },
+            "database.uri" => self.database.uri = value.to_string(),
+            "database.username" => self.database.username = value.to_string(),
+            "database.password" => self.database.password = value.to_string(),
+            "database.database" => self.database.database = value.to_string(),
+            "database.max_connections" => self.database.max_connections = number(value)?,
+            "database.fetch_size" => self.database.fetch_size = number(value)?,
+            "database.ca_certificates" => self.database.ca_certificates = value.to_string(),
+            "database.client_certificate" => self.database.client_certificate = value.to_string(),
+            "database.client_key" => self.database.client_key = value.to_string(),
+            "storage.backend" => self.storage.backend = value.to_string(),
+            "storage.root" => self.storage.root = PathBuf::from(value),
+            "storage.public_url" => self.storage.public_url = value.to_string(),
+            "storage.max_upload_bytes" => self.storage.max_upload_bytes = number(value)?,
+            "storage.max_avatar_bytes" => self.storage.max_avatar_bytes = number(value)?,
+            "storage.max_field_bytes" => self.storage.max_field_bytes = number(value)?,
+            "storage.s3.endpoint" => self.storage.s3.endpoint = value.to_string(),
+            "storage.s3.bucket" => self.storage.s3.bucket = value.to_string(),
+            "storage.s3.region" => self.storage.s3.region = value.to_string(),
+            "storage.s3.access_key" => self.storage.s3.access_key = value.to_string(),
+            "storage.s3.secret_key" => self.storage.s3.secret_key = value.to_string(),
+            "auth.jwt_secret" => self.auth.jwt_secret = value.to_string(),
+            "auth.admin_email" => self.auth.admin_email = value.to_string(),
+            "auth.admin_password" => self.auth.admin_password = value.to_string(),
+            "trash.retention_days" => self.trash.retention_days = number(value)?,
+            "trash.purge_interval_minutes" => self.trash.purge_interval_minutes = number(value)?,
+            "log.level" => self.log.level = value.to_string(),
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Every problem found, empty when the settings are usable
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors: Vec<String> = vec![];
+        if self.bind_address().is_none() {
+            errors.push(format!(
+                "server.host: {:?} with port {} is not a valid bind address",
+                self.server.host, self.server.port,
+            ));
+        }
+        if self.server.cors_origins.is_empty() {
+            errors.push("server.cors_origins: must not be empty, use \"*\" to allow any origin".to_string());
+        }
+        for origin in self.server.cors_origins.iter() {
+            if origin != "*" && !origin.starts_with("http://") && !origin.starts_with("https://") {
+                errors.push(format!("server.cors_origins: {:?} must be \"*\" or start with http:// or https://", origin));
+            }
+        }
+        if self.database.uri.trim().is_empty() {
+            errors.push("database.uri: must be set".to_string());
+        }
+        if let Some((scheme, _)) = self.database.uri.split_once("://") {
+            if !["bolt", "bolt+s", "bolt+ssc"].contains(&scheme) {
+                errors.push(format!("database.uri: {:?} must be one of bolt, bolt+s, bolt+ssc", scheme));
+            }
+        }
+        if self.database.client_certificate.is_empty() != self.database.client_key.is_empty() {
+            errors.push("database.client_certificate: must be set together with database.client_key".to_string());
+        }
+        if self.database.username.trim().is_empty() {
+            errors.push("database.username: must be set".to_string());
+        }
+        if self.database.database.trim().is_empty() {
+            errors.push("database.database: must be set".to_string());
+        }
+        if self.database.max_connections == 0 {
+            errors.push("database.max_connections: must be at least 1".to_string());
+        }
+        if self.database.fetch_size == 0 {
+            errors.push("database.fetch_size: must be at least 1".to_string());
+        }
+        match self.storage.backend.as_str() {
+            "local" => {
+                if self.storage.root.as_os_str().is_empty() {
+                    errors.push("storage.root: must be set".to_string());
+                }
+            },
+            "s3" => {
+                let s3 = &self.storage.s3;
+                if !s3.endpoint.starts_with("http://") && !s3.endpoint.starts_with("https://") {
+                    errors.push(format!("storage.s3.endpoint: {:?} must start with http:// or https://", s3.endpoint));
+                }
+                for (key, value) in [
+                    ("bucket", &s3.bucket),
+                    ("region", &s3.region),
+                    ("access_key", &s3.access_key),
+                    ("secret_key", &s3.secret_key),
+                ] {
+                    if value.trim().is_empty() {
+                        errors.push(format!("storage.s3.{}: must be set for the s3 backend", key));
+                    }
+                }
+            },
+            x => errors.push(format!("storage.backend: {:?} must be one of local, s3", x)),
+        }
+        for (key, value) in [
+            ("max_upload_bytes", self.storage.max_upload_bytes),
+            ("max_avatar_bytes", self.storage.max_avatar_bytes),
+            ("max_field_bytes", self.storage.max_field_bytes),
+        ] {
+            if value == 0 {
+                errors.push(format!("storage.{}: must be at least 1", key));
+            }
+        }
+        if self.auth.jwt_secret.is_empty() {
+            errors.push("auth.jwt_secret: must be set".to_string());
+        }
+        if !self.auth.admin_email.is_empty() {
+            if !validator::validate_email(&self.auth.admin_email) {
+                errors.push(format!("auth.admin_email: {:?} is not a valid email", self.auth.admin_email));
+            }
+            if self.auth.admin_password.len() < 6 {
+                errors.push("auth.admin_password: must be at least 6 characters with auth.admin_email".to_string());
+            }
+        }
+        if self.trash.retention_days < 0 {
+            errors.push("trash.retention_days: must not be negative".to_string());
+        }
+        if self.trash.purge_interval_minutes == 0 {
+            errors.push("trash.purge_interval_minutes: must be at least 1".to_string());
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.level) {
+            errors.push(format!("log.level: {:?} is not a valid filter, {}", self.log.level, e));
+        }
+        errors
+    }
+
+    pub fn bind_address(&self) -> Option<SocketAddr> {
+        format!("{}:{}", self.server.host, self.server.port).parse().ok()
+    }
+}
+
+// accepts both `--port 8080` and `--port=8080`
+fn parse_flags(args: &[String], errors: &mut Vec<String>) -> Vec<(String, String)> {
+    let mut flags: Vec<(String, String)> = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let value = inline.or_else(|| iter.next().cloned());
+        if flag != CONFIG_FLAG && !OVERRIDES.iter().any(|o| o.flag == flag) {
+            errors.push(format!("{}: unknown flag", flag));
+            continue;
+        }
+        match value {
+            Some(value) => flags.push((flag, value)),
+            None => errors.push(format!("{}: missing value", flag)),
+        }
+    }
+    flags
+}
+
+fn read_file(path: &Path) -> Result<Settings, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Loads the settings of this process, or prints every error and exits
+pub fn init() -> &'static Settings {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let vars: HashMap<String, String> = std::env::vars().collect();
+    match Settings::load(&args, &vars) {
+        Ok(settings) => SETTINGS.get_or_init(|| settings),
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for e in errors {
+                eprintln!("  {}", e);
+            }
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Settings loaded by `init`, defaults if it hasn't run, like in tests
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn vars(list: &[(&str, &str)]) -> HashMap<String, String> {
+        list.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn should_let_flags_override_env() {
+        let settings = Settings::load(
+            &args(&["--port", "9090", "--db-fetch-size=100"]),
+            &vars(&[("PORT", "8080"), ("HOST", "0.0.0.0"), ("JWT_SECRET", "secret")]),
+        ).unwrap();
+        assert_eq!(settings.server.port, 9090);
+        assert_eq!(settings.server.host, "0.0.0.0");
+        assert_eq!(settings.database.fetch_size, 100);
+        assert_eq!(settings.bind_address(), Some("0.0.0.0:9090".parse().unwrap()));
+    }
+
+    #[test]
+    fn should_read_toml_below_env() {
+        let path = std::env::temp_dir().join(format!("groupware-settings-{}.toml", std::process::id()));
+        fs::write(&path, "
+            [server]
+            port = 8000
+            cors_origins = [\"https://example.com\"]
+
+            [auth]
+            jwt_secret = \"from-file\"
+        ").unwrap();
+        let settings = Settings::load(
+            &args(&["--config", path.to_str().unwrap()]),
+            &vars(&[("JWT_SECRET", "from-env")]),
+        ).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.server.port, 8000);
+        assert_eq!(settings.server.cors_origins, vec!["https://example.com".to_string()]);
+        assert_eq!(settings.auth.jwt_secret, "from-env");
+        assert_eq!(settings.database.max_connections, 10);
+    }
+
+    #[test]
+    fn should_report_every_error_at_once() {
+        let errors = Settings::load(
+            &args(&["--bogus", "1", "--max-upload-bytes", "0"]),
+            &vars(&[("PORT", "http"), ("ORIGIN_ALLOWED", "example.com")]),
+        ).unwrap_err();
+        assert_eq!(errors, vec![
+            "--bogus: unknown flag".to_string(),
+            "PORT (server.port): \"http\" is not a valid number".to_string(),
+            "server.cors_origins: \"example.com\" must be \"*\" or start with http:// or https://".to_string(),
+            "storage.max_upload_bytes: must be at least 1".to_string(),
+            "auth.jwt_secret: must be set".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn should_require_password_of_first_user() {
+        let errors = Settings::load(
+            &args(&["--admin-email", "admin"]),
+            &vars(&[("JWT_SECRET", "secret")]),
+        ).unwrap_err();
+        assert_eq!(errors, vec![
+            "auth.admin_email: \"admin\" is not a valid email".to_string(),
+            "auth.admin_password: must be at least 6 characters with auth.admin_email".to_string(),
+        ]);
+
+        let settings = Settings::load(
+            &args(&[]),
+            &vars(&[("JWT_SECRET", "secret"), ("ADMIN_EMAIL", "admin@example.com"), ("ADMIN_PASSWORD", "secret")]),
+        ).unwrap();
+        assert_eq!(settings.auth.admin_email, "admin@example.com");
+    }
+
+    #[test]
+    fn should_check_database_tls_settings() {
+        let errors = Settings::load(
+            &args(&["--db-uri", "neo4j+s://db.example.com", "--db-client-certificate", "client.pem"]),
+            &vars(&[("JWT_SECRET", "secret")]),
+        ).unwrap_err();
+        assert_eq!(errors, vec![
+            "database.uri: \"neo4j+s\" must be one of bolt, bolt+s, bolt+ssc".to_string(),
+            "database.client_certificate: must be set together with database.client_key".to_string(),
+        ]);
+
+        let settings = Settings::load(
+            &args(&["--db-uri", "bolt+s://db.example.com:7687"]),
+            &vars(&[
+                ("JWT_SECRET", "secret"),
+                ("NEO4J_CA_CERTIFICATES", "ca.pem"),
+                ("NEO4J_CLIENT_CERTIFICATE", "client.pem"),
+                ("NEO4J_CLIENT_KEY", "client.key"),
+            ]),
+        ).unwrap();
+        assert_eq!(settings.database.ca_certificates, "ca.pem");
+        assert_eq!(settings.database.client_key, "client.key");
+    }
 }
@@ -1,17 +1,21 @@
 use std::sync::Arc;
-use crate::config::{db_host, db_port, db_username, db_password, db_database};
+use crate::config::DatabaseSettings;
 
-pub async fn init_pool() -> Arc<neo4rs::Graph> {
-    let addr = format!("{}:{}", db_host(), db_port());
-    let config = neo4rs::config()
-        .uri(&addr)
-        .user(&db_username())
-        .password(&db_password())
-        .db(&db_database())
-        .fetch_size(500)
-        .max_connections(10)
-        .build()
-        .unwrap();
+pub async fn init_pool(settings: &DatabaseSettings) -> Arc<neo4rs::Graph> {
+    let mut config = neo4rs::config()
+        .uri(&settings.uri)
+        .user(&settings.username)
+        .password(&settings.password)
+        .db(&settings.database)
+        .fetch_size(settings.fetch_size)
+        .max_connections(settings.max_connections);
+    if !settings.ca_certificates.is_empty() {
+        config = config.ca_certificates(&settings.ca_certificates);
+    }
+    if !settings.client_certificate.is_empty() {
+        config = config.client_certificate(&settings.client_certificate, &settings.client_key);
+    }
+    let config = config.build().unwrap();
     let graph = neo4rs::Graph::connect(config).await.unwrap();
     Arc::new(graph)
 }
@@ -0,0 +1,538 @@
+use std::{
+    sync::Arc,
+    vec::Vec,
+};
+use warp::http::StatusCode;
+
+use crate::company::CompanyResponse;
+use crate::department::{
+    build_org_chart,
+    CreateDepartmentParams,
+    DepartmentResponse,
+    FindDepartmentsParams,
+    MoveDepartmentParams,
+    OrgChartResponse,
+    OrgUnit,
+    UpdateDepartmentParams,
+};
+use crate::error_handler::ApiError;
+use crate::helpers::{
+    fetch_one,
+    parse_id,
+};
+use crate::pagination::{Page, Pagination};
+
+// a department hangs off its company through one or more PART_OF edges
+// (:Department)-[:PART_OF]->(:Department)-[:PART_OF]->(:Company)
+// every query returning a department ends with this so that DepartmentResponse finds its columns
+const RETURN_DEPARTMENT: &str = "
+    WITH d
+    MATCH (d)-[:PART_OF]->(p)
+    MATCH (d)-[:PART_OF*1..]->(c:Company)
+    RETURN d, id(p) AS parent_id, id(c) AS company_id
+";
+
+#[tracing::instrument(skip_all, fields(company_id = %company_id))]
+pub async fn find_departments(
+    company_id: String,
+    params: FindDepartmentsParams,
+    pagination: Pagination,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let company_id: i64 = parse_id(&company_id)?;
+    let search: String = params.search.unwrap_or_default().trim().to_string();
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (c:Company)
+        WHERE id(c) = $company_id AND c.deletedAt IS NULL
+        OPTIONAL MATCH (d:Department)-[:PART_OF*1..]->(c)
+        WHERE d.name CONTAINS $search
+        RETURN count(d) AS total
+    ")
+    .param("company_id", company_id)
+    .param("search", search.clone());
+    let row: neo4rs::Row = fetch_one(&graph, q, "Company").await?;
+    let total: i64 = row.get("total").unwrap_or(0);
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (d:Department)-[:PART_OF*1..]->(c:Company)
+        WHERE id(c) = $company_id AND c.deletedAt IS NULL AND d.name CONTAINS $search
+        MATCH (d)-[:PART_OF]->(p)
+        RETURN d, id(p) AS parent_id, id(c) AS company_id
+        ORDER BY d.name
+        SKIP $skip
+        LIMIT $limit
+    ")
+    .param("company_id", company_id)
+    .param("search", search)
+    .param("skip", pagination.offset)
+    .param("limit", pagination.per_page);
+
+    let mut result: neo4rs::RowStream = graph.execute(q).await.map_err(ApiError::from)?;
+    let mut records: Vec<DepartmentResponse> = vec![];
+    while let Some(row) = result.next().await.map_err(ApiError::from)? {
+        records.push(DepartmentResponse::from_row(row));
+    }
+    Ok(Page::new(records, total, &pagination).into_reply(&pagination))
+}
+
+#[tracing::instrument(skip_all, fields(id = %id))]
+pub async fn show_department(
+    id: String,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let q: neo4rs::Query = neo4rs::query(&format!("
+        MATCH (d:Department)
+        WHERE id(d) = $id
+        {}
+    ", RETURN_DEPARTMENT))
+    .param("id", parse_id(&id)?);
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "Department").await?;
+    let record: DepartmentResponse = DepartmentResponse::from_row(row);
+    Ok(warp::reply::json(&record))
+}
+
+#[tracing::instrument(skip_all, fields(company_id = %company_id))]
+pub async fn create_department(
+    company_id: String,
+    params: CreateDepartmentParams,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let company_id: i64 = parse_id(&company_id)?;
+    // parent is the company itself or one of its departments
+    let q: neo4rs::Query = neo4rs::query(&format!("
+        MATCH (c:Company)
+        WHERE id(c) = $company_id
+        MATCH (p)
+        WHERE id(p) = $parent_id AND (p = c OR (p:Department AND (p)-[:PART_OF*1..]->(c)))
+        CREATE (d:Department {{
+            name: $name,
+            createdAt: datetime(),
+            updatedAt: datetime()
+        }})-[:PART_OF]->(p)
+        {}
+    ", RETURN_DEPARTMENT))
+    .param("company_id", company_id)
+    .param("parent_id", params.parent_id.unwrap_or(company_id))
+    .param("name", params.name.unwrap());
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "Parent").await?;
+    let record: DepartmentResponse = DepartmentResponse::from_row(row);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&record),
+        StatusCode::CREATED,
+    ))
+}
+
+#[tracing::instrument(skip_all, fields(id = %id))]
+pub async fn update_department(
+    id: String,
+    params: UpdateDepartmentParams,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut assignments: Vec<&str> = vec![];
+    if params.name.is_some() {
+        assignments.push("d.name = $name");
+    }
+    assignments.push("d.updatedAt = datetime()");
+    let mut q: neo4rs::Query = neo4rs::query(&format!("
+        MATCH (d:Department)
+        WHERE id(d) = $id
+        SET {}
+        {}
+    ", assignments.join(", "), RETURN_DEPARTMENT))
+    .param("id", parse_id(&id)?);
+    if let Some(x) = params.name {
+        q = q.param("name", x);
+    }
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "Department").await?;
+    let record: DepartmentResponse = DepartmentResponse::from_row(row);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&record),
+        StatusCode::OK,
+    ))
+}
+
+/// Reparents the department under the company or another department of the same company
+#[tracing::instrument(skip_all, fields(id = %id))]
+pub async fn move_department(
+    id: String,
+    params: MoveDepartmentParams,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id: i64 = parse_id(&id)?;
+    let parent_id: i64 = params.parent_id.unwrap();
+
+    // a path of zero or more PART_OF edges from the new parent to the department means
+    // the parent is the department itself or one of its descendants,
+    // existential subqueries are only allowed in WHERE before Neo4j 5 so the paths are counted here
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (d:Department)-[:PART_OF*1..]->(c:Company)
+        WHERE id(d) = $id
+        MATCH (p)
+        WHERE id(p) = $parent_id AND (p:Company OR p:Department)
+        OPTIONAL MATCH (p)-[:PART_OF*0..]->(pc:Company)
+        RETURN id(c) = id(pc) AS same_company, size([(p)-[:PART_OF*0..]->(d) | p]) > 0 AS cycle
+    ")
+    .param("id", id)
+    .param("parent_id", parent_id);
+    let row: neo4rs::Row = fetch_one(&graph, q, "Department").await?;
+    if row.get::<bool>("same_company") != Some(true) {
+        return Err(ApiError::Conflict("Parent must belong to the same company".to_string()).into());
+    }
+    if row.get::<bool>("cycle") != Some(false) {
+        return Err(ApiError::Conflict("Department can't be moved under itself or its descendants".to_string()).into());
+    }
+
+    // the cycle check is repeated so that a concurrent move can't sneak one in
+    let q: neo4rs::Query = neo4rs::query(&format!("
+        MATCH (d:Department)-[r:PART_OF]->()
+        WHERE id(d) = $id
+        MATCH (p)
+        WHERE id(p) = $parent_id AND NOT EXISTS {{ MATCH (p)-[:PART_OF*0..]->(d) }}
+        DELETE r
+        CREATE (d)-[:PART_OF]->(p)
+        SET d.updatedAt = datetime()
+        {}
+    ", RETURN_DEPARTMENT))
+    .param("id", id)
+    .param("parent_id", parent_id);
+
+    let row: neo4rs::Row = match fetch_one(&graph, q, "Department").await {
+        Ok(row) => row,
+        // both were found above, so a missing row means a concurrent move made this one a cycle
+        Err(ApiError::NotFound(_)) => {
+            return Err(ApiError::Conflict("Department can't be moved under itself or its descendants".to_string()).into());
+        },
+        Err(e) => return Err(e.into()),
+    };
+    let record: DepartmentResponse = DepartmentResponse::from_row(row);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&record),
+        StatusCode::OK,
+    ))
+}
+
+#[tracing::instrument(skip_all, fields(id = %id))]
+pub async fn delete_department(
+    id: String,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id: i64 = parse_id(&id)?;
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (d:Department)
+        WHERE id(d) = $id
+        OPTIONAL MATCH (child:Department)-[:PART_OF]->(d)
+        RETURN count(child) AS children
+    ")
+    .param("id", id);
+    let row: neo4rs::Row = fetch_one(&graph, q, "Department").await?;
+    if row.get::<i64>("children").unwrap_or(0) > 0 {
+        return Err(ApiError::Conflict("Department still has sub-departments".to_string()).into());
+    }
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (d:Department)
+        WHERE id(d) = $id AND NOT ()-[:PART_OF]->(d)
+        DETACH DELETE d
+        RETURN count(*) AS count
+    ")
+    .param("id", id);
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "Department").await?;
+    if row.get::<i64>("count") != Some(1) {
+        return Err(ApiError::Conflict("Department still has sub-departments".to_string()).into());
+    }
+    let empty: Vec<u8> = vec![];
+    Ok(warp::reply::with_status(
+        warp::reply::json(&empty),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+/// Only members of the company can be assigned to its departments
+#[tracing::instrument(skip_all, fields(id = %id, user_id = %user_id))]
+pub async fn add_department_member(
+    id: String,
+    user_id: String,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (d:Department)-[:PART_OF*1..]->(c:Company)<-[:MEMBER_OF]-(u:User)
+        WHERE id(d) = $id AND id(u) = $user_id
+        MERGE (u)-[m:MEMBER_OF]->(d)
+        ON CREATE SET m.since = date()
+        RETURN count(*) AS count
+    ")
+    .param("id", parse_id(&id)?)
+    .param("user_id", parse_id(&user_id)?);
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "Department").await?;
+    if row.get::<i64>("count") != Some(1) {
+        return Err(ApiError::Conflict("User must be a member of the company first".to_string()).into());
+    }
+    let empty: Vec<u8> = vec![];
+    Ok(warp::reply::with_status(
+        warp::reply::json(&empty),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+#[tracing::instrument(skip_all, fields(id = %id, user_id = %user_id))]
+pub async fn remove_department_member(
+    id: String,
+    user_id: String,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[m:MEMBER_OF]->(d:Department)
+        WHERE id(d) = $id AND id(u) = $user_id
+        DELETE m
+        RETURN count(*) AS count
+    ")
+    .param("id", parse_id(&id)?)
+    .param("user_id", parse_id(&user_id)?);
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "Membership").await?;
+    if row.get::<i64>("count") != Some(1) {
+        return Err(ApiError::NotFound("Membership".to_string()).into());
+    }
+    let empty: Vec<u8> = vec![];
+    Ok(warp::reply::with_status(
+        warp::reply::json(&empty),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+/// Whole department tree of the company in one round trip, nested afterwards
+#[tracing::instrument(skip_all, fields(company_id = %company_id))]
+pub async fn show_org_chart(
+    company_id: String,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (c:Company)
+        WHERE id(c) = $id AND c.deletedAt IS NULL
+        OPTIONAL MATCH (cm:User)-[:MEMBER_OF]->(c)
+        WITH c, count(cm) AS company_members
+        OPTIONAL MATCH path = (d:Department)-[:PART_OF*1..]->(c)
+        OPTIONAL MATCH (u:User)-[:MEMBER_OF]->(d)
+        WITH c, company_members, d, path, count(u) AS members
+        RETURN c, company_members, id(d) AS id, id(nodes(path)[1]) AS parent_id, d.name AS name, members
+        ORDER BY length(path), name
+    ")
+    .param("id", parse_id(&company_id)?);
+
+    let mut result: neo4rs::RowStream = graph.execute(q).await.map_err(ApiError::from)?;
+    let mut company: Option<(CompanyResponse, i64)> = None;
+    let mut units: Vec<OrgUnit> = vec![];
+    while let Some(row) = result.next().await.map_err(ApiError::from)? {
+        // a company without departments still yields one row, with null department columns
+        if let (Some(id), Some(parent_id)) = (row.get::<i64>("id"), row.get::<i64>("parent_id")) {
+            units.push(OrgUnit {
+                id,
+                parent_id,
+                name: row.get("name").unwrap(),
+                member_count: row.get("members").unwrap_or(0),
+            });
+        }
+        if company.is_none() {
+            let member_count: i64 = row.get("company_members").unwrap_or(0);
+            company = Some((CompanyResponse::from_row(row), member_count));
+        }
+    }
+    let (company, member_count) = company.ok_or_else(|| ApiError::NotFound("Company".to_string()))?;
+    let record = OrgChartResponse {
+        departments: build_org_chart(company.id, units),
+        company,
+        member_count,
+    };
+    Ok(warp::reply::json(&record))
+}
+
+/// Id of the company the department belongs to, for authorization
+pub async fn find_department_company(
+    graph: &neo4rs::Graph,
+    id: i64,
+) -> Result<i64, ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (d:Department)-[:PART_OF*1..]->(c:Company)
+        WHERE id(d) = $id
+        RETURN id(c) AS company_id
+    ")
+    .param("id", id);
+
+    let row: neo4rs::Row = fetch_one(graph, q, "Department").await?;
+    Ok(row.get("company_id").unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDate};
+    use neo4rs::testing::{node, BoltType, Expect, Reply, Script, StubServer};
+    use crate::test_support::connect;
+
+    const CHECK_MOVE: &str = "RETURN id(c) = id(pc) AS same_company, size([(p)-[:PART_OF*0..]->(d) | p]) > 0 AS cycle";
+
+    /// Moving department 11 under 10 finds them in the same company or not, and a cycle or not
+    fn check_move(same_company: bool, cycle: bool) -> Script {
+        Script::new()
+            .expect(Expect::run_containing(CHECK_MOVE).param("id", 11i64).param("parent_id", 10i64))
+            .returns(&["same_company", "cycle"], vec![vec![same_company.into(), cycle.into()]])
+    }
+
+    fn department(id: i64, parent_id: i64) -> Vec<BoltType> {
+        let now = DateTime::parse_from_rfc3339("2021-06-01T09:00:00+00:00").unwrap();
+        vec![
+            node(id, &["Department"], vec![
+                ("name", "Backend".into()),
+                ("createdAt", now.into()),
+                ("updatedAt", now.into()),
+            ]),
+            parent_id.into(),
+            42i64.into(),
+        ]
+    }
+
+    fn move_params() -> MoveDepartmentParams {
+        MoveDepartmentParams {
+            parent_id: Some(10),
+        }
+    }
+
+    fn company() -> BoltType {
+        let now = DateTime::parse_from_rfc3339("2021-06-01T09:00:00+00:00").unwrap();
+        node(42, &["Company"], vec![
+            ("name", "Acme".into()),
+            ("since", NaiveDate::from_ymd_opt(2020, 1, 1).unwrap().into()),
+            ("createdAt", now.into()),
+            ("updatedAt", now.into()),
+            ("version", 1i64.into()),
+        ])
+    }
+
+    async fn json(reply: impl warp::Reply) -> (StatusCode, serde_json::Value) {
+        let response = warp::Reply::into_response(reply);
+        let status: StatusCode = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn should_nest_org_chart_from_one_query() {
+        let script = Script::new()
+            .expect(Expect::run_containing("OPTIONAL MATCH path = (d:Department)-[:PART_OF*1..]->(c)").param("id", 42i64))
+            .returns(&["c", "company_members", "id", "parent_id", "name", "members"], vec![
+                vec![company(), 2i64.into(), 10i64.into(), 42i64.into(), "Engineering".into(), 3i64.into()],
+                vec![company(), 2i64.into(), 12i64.into(), 42i64.into(), "Sales".into(), 0i64.into()],
+                vec![company(), 2i64.into(), 11i64.into(), 10i64.into(), "Backend".into(), 1i64.into()],
+            ]);
+        let server = StubServer::start(script).await;
+
+        let (status, body) = json(show_org_chart("42".to_string(), connect(&server).await).await.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["company"]["id"], 42);
+        assert_eq!(body["memberCount"], 2);
+        assert_eq!(body["departments"][0]["name"], "Engineering");
+        assert_eq!(body["departments"][0]["memberCount"], 3);
+        assert_eq!(body["departments"][0]["children"][0]["name"], "Backend");
+        assert_eq!(body["departments"][0]["children"][0]["memberCount"], 1);
+        assert_eq!(body["departments"][1]["name"], "Sales");
+        assert_eq!(body["departments"][1]["children"], serde_json::json!([]));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_chart_missing_or_trashed_company() {
+        let script = Script::new()
+            .expect(Expect::run_containing("WHERE id(c) = $id AND c.deletedAt IS NULL").param("id", 7i64))
+            .returns(&["c", "company_members", "id", "parent_id", "name", "members"], vec![]);
+        let server = StubServer::start(script).await;
+
+        let rejection = show_org_chart("7".to_string(), connect(&server).await).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::NotFound(x)) if x == "Company"));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_move_department_under_another_one() {
+        let script = check_move(true, false)
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("AND NOT EXISTS { MATCH (p)-[:PART_OF*0..]->(d) } DELETE r CREATE (d)-[:PART_OF]->(p)")
+                .param("id", 11i64)
+                .param("parent_id", 10i64))
+            .returns(&["d", "parent_id", "company_id"], vec![department(11, 10)]);
+        let server = StubServer::start(script).await;
+
+        let (status, body) = json(move_department("11".to_string(), move_params(), connect(&server).await).await.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], 11);
+        assert_eq!(body["parentId"], 10);
+        assert_eq!(body["companyId"], 42);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_move_department_under_its_descendant() {
+        let server = StubServer::start(check_move(true, true)).await;
+
+        let rejection = move_department("11".to_string(), move_params(), connect(&server).await).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Conflict(x)) if x.contains("under itself")));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_move_department_into_cycle_made_since_the_check() {
+        let script = check_move(true, false)
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("AND NOT EXISTS { MATCH (p)-[:PART_OF*0..]->(d) }"))
+            .returns(&["d", "parent_id", "company_id"], vec![]);
+        let server = StubServer::start(script).await;
+
+        let rejection = move_department("11".to_string(), move_params(), connect(&server).await).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Conflict(x)) if x.contains("under itself")));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_move_department_to_another_company() {
+        let server = StubServer::start(check_move(false, false)).await;
+
+        let rejection = move_department("11".to_string(), move_params(), connect(&server).await).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Conflict(x)) if x.contains("same company")));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_delete_department_without_children() {
+        let script = Script::new()
+            .expect(Expect::run_containing("RETURN count(child) AS children").param("id", 11i64))
+            .returns(&["children"], vec![vec![0i64.into()]])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("WHERE id(d) = $id AND NOT ()-[:PART_OF]->(d) DETACH DELETE d").param("id", 11i64))
+            .returns(&["count"], vec![vec![1i64.into()]]);
+        let server = StubServer::start(script).await;
+
+        let reply = delete_department("11".to_string(), connect(&server).await).await.unwrap();
+        assert_eq!(warp::Reply::into_response(reply).status(), StatusCode::NO_CONTENT);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_keep_department_with_children() {
+        let script = Script::new()
+            .expect(Expect::run_containing("RETURN count(child) AS children").param("id", 10i64))
+            .returns(&["children"], vec![vec![1i64.into()]]);
+        let server = StubServer::start(script).await;
+
+        let rejection = delete_department("10".to_string(), connect(&server).await).await.err().unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Conflict(_))));
+        server.finish();
+    }
+}
@@ -0,0 +1,151 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use validator::Validate;
+
+use crate::company::CompanyResponse;
+
+// find
+
+#[derive(Default, Deserialize)]
+pub struct FindDepartmentsParams {
+    pub search: Option<String>,
+}
+
+// create
+
+#[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
+pub struct CreateDepartmentParams {
+    #[validate(required, length(min = 1))]
+    pub name: Option<String>,
+    pub parent_id: Option<i64>, // company itself if omitted
+}
+
+// update
+
+#[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
+pub struct UpdateDepartmentParams {
+    #[validate(length(min = 1))]
+    pub name: Option<String>,
+}
+
+// move
+
+#[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
+pub struct MoveDepartmentParams {
+    #[validate(required)]
+    pub parent_id: Option<i64>, // id of the company or of another department in it
+}
+
+// response
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DepartmentResponse {
+    pub id: i64,
+    pub name: String,
+    pub company_id: i64,
+    pub parent_id: i64,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+}
+
+impl DepartmentResponse {
+    pub fn from_row(row: neo4rs::Row) -> DepartmentResponse {
+        let d: neo4rs::Node = row.get("d").unwrap();
+        DepartmentResponse {
+            id: d.id(),
+            name: d.get("name").unwrap(),
+            company_id: row.get("company_id").unwrap(),
+            parent_id: row.get("parent_id").unwrap(),
+            created_at: d.get("createdAt").unwrap(),
+            updated_at: d.get("updatedAt").unwrap(),
+        }
+    }
+}
+
+// org chart
+
+/// One department of the org chart as returned by cypher, before nesting
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrgUnit {
+    pub id: i64,
+    pub parent_id: i64,
+    pub name: String,
+    pub member_count: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgChartNode {
+    pub id: i64,
+    pub name: String,
+    pub member_count: i64,
+    pub children: Vec<OrgChartNode>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgChartResponse {
+    pub company: CompanyResponse,
+    pub member_count: i64,
+    pub departments: Vec<OrgChartNode>,
+}
+
+/// Nests the units under `root_id`, keeping their order among siblings
+pub fn build_org_chart(root_id: i64, units: Vec<OrgUnit>) -> Vec<OrgChartNode> {
+    let mut children: HashMap<i64, Vec<OrgUnit>> = HashMap::new();
+    for unit in units {
+        children.entry(unit.parent_id).or_default().push(unit);
+    }
+    nest(root_id, &mut children)
+}
+
+fn nest(parent_id: i64, children: &mut HashMap<i64, Vec<OrgUnit>>) -> Vec<OrgChartNode> {
+    children
+        .remove(&parent_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|unit| OrgChartNode {
+            id: unit.id,
+            name: unit.name,
+            member_count: unit.member_count,
+            children: nest(unit.id, children),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(id: i64, parent_id: i64, name: &str) -> OrgUnit {
+        OrgUnit {
+            id,
+            parent_id,
+            name: name.to_string(),
+            member_count: id,
+        }
+    }
+
+    #[test]
+    fn should_nest_units_under_their_parents() {
+        let chart = build_org_chart(1, vec![
+            unit(2, 1, "Engineering"),
+            unit(3, 1, "Sales"),
+            unit(4, 2, "Backend"),
+            unit(5, 4, "Storage"),
+        ]);
+        assert_eq!(chart.len(), 2);
+        assert_eq!(chart[0].name, "Engineering");
+        assert_eq!(chart[0].children[0].name, "Backend");
+        assert_eq!(chart[0].children[0].children[0].member_count, 5);
+        assert!(chart[1].children.is_empty());
+    }
+
+    #[test]
+    fn should_drop_units_outside_of_root() {
+        let chart = build_org_chart(1, vec![unit(2, 9, "Detached")]);
+        assert!(chart.is_empty());
+    }
+}
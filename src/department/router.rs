@@ -0,0 +1,217 @@
+use std::sync::Arc;
+use warp::Filter;
+
+use crate::helpers::{
+    with_json_body,
+    parse_id,
+    with_db,
+};
+use crate::auth::{require_auth, with_auth, JwtKeys};
+use crate::error_handler::ApiError;
+use crate::pagination::with_pagination;
+use crate::policy::{
+    authorize_company,
+    find_company_role,
+    CompanyAction,
+    Role,
+};
+use crate::user::UserResponse;
+use crate::department::{
+    self,
+    find_department_company,
+    CreateDepartmentParams,
+    FindDepartmentsParams,
+    MoveDepartmentParams,
+    UpdateDepartmentParams,
+};
+
+pub fn init(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    find_departments(graph.clone(), keys.clone())
+        .or(create_department(graph.clone(), keys.clone()))
+        .or(show_org_chart(graph.clone(), keys.clone()))
+        .or(show_department(graph.clone(), keys.clone()))
+        .or(update_department(graph.clone(), keys.clone()))
+        .or(move_department(graph.clone(), keys.clone()))
+        .or(delete_department(graph.clone(), keys.clone()))
+        .or(add_department_member(graph.clone(), keys.clone()))
+        .or(remove_department_member(graph, keys))
+}
+
+/// GET /companies/:id/departments
+fn find_departments(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / String / "departments")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(warp::query::<FindDepartmentsParams>())
+        .and(with_pagination())
+        .and(with_db(graph))
+        .and_then(department::find_departments)
+}
+
+/// POST /companies/:id/departments
+fn create_department(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / String / "departments")
+        .and(warp::post())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|company_id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize_company_id(parse_id(&company_id)?, &current_user, &graph).await?;
+            Ok::<String, warp::Rejection>(company_id)
+        })
+        .and(with_create_params())
+        .and(with_db(graph))
+        .and_then(department::create_department)
+}
+
+/// GET /companies/:id/org-chart
+fn show_org_chart(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / String / "org-chart")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_db(graph))
+        .and_then(department::show_org_chart)
+}
+
+/// GET /departments/:id
+fn show_department(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("departments" / String)
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_db(graph))
+        .and_then(department::show_department)
+}
+
+/// PATCH /departments/:id
+fn update_department(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("departments" / String)
+        .and(warp::patch())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph).await?;
+            Ok::<String, warp::Rejection>(id)
+        })
+        .and(with_update_params())
+        .and(with_db(graph))
+        .and_then(department::update_department)
+}
+
+/// POST /departments/:id/move
+fn move_department(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("departments" / String / "move")
+        .and(warp::post())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph).await?;
+            Ok::<String, warp::Rejection>(id)
+        })
+        .and(with_move_params())
+        .and(with_db(graph))
+        .and_then(department::move_department)
+}
+
+/// DELETE /departments/:id
+fn delete_department(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("departments" / String)
+        .and(warp::delete())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph).await?;
+            Ok::<String, warp::Rejection>(id)
+        })
+        .and(with_db(graph))
+        .and_then(department::delete_department)
+}
+
+/// POST /departments/:id/members/:user_id
+fn add_department_member(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("departments" / String / "members" / String)
+        .and(warp::post())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, user_id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph).await?;
+            Ok::<(String, String), warp::Rejection>((id, user_id))
+        })
+        .untuple_one()
+        .and(with_db(graph))
+        .and_then(department::add_department_member)
+}
+
+/// DELETE /departments/:id/members/:user_id
+fn remove_department_member(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("departments" / String / "members" / String)
+        .and(warp::delete())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, user_id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph).await?;
+            Ok::<(String, String), warp::Rejection>((id, user_id))
+        })
+        .untuple_one()
+        .and(with_db(graph))
+        .and_then(department::remove_department_member)
+}
+
+// departments are managed by the admins of the company they belong to
+async fn authorize(
+    id: &str,
+    current_user: &UserResponse,
+    graph: &neo4rs::Graph,
+) -> Result<(), ApiError> {
+    let company_id: i64 = find_department_company(graph, parse_id(id)?).await?;
+    authorize_company_id(company_id, current_user, graph).await
+}
+
+async fn authorize_company_id(
+    company_id: i64,
+    current_user: &UserResponse,
+    graph: &neo4rs::Graph,
+) -> Result<(), ApiError> {
+    let role: Option<Role> = find_company_role(graph, company_id, current_user.id).await?;
+    authorize_company(role, CompanyAction::ManageDepartments)
+}
+
+fn with_create_params() -> impl Filter<Extract = (CreateDepartmentParams, ), Error = warp::Rejection> + Clone {
+    with_json_body()
+}
+
+fn with_update_params() -> impl Filter<Extract = (UpdateDepartmentParams, ), Error = warp::Rejection> + Clone {
+    with_json_body()
+}
+
+fn with_move_params() -> impl Filter<Extract = (MoveDepartmentParams, ), Error = warp::Rejection> + Clone {
+    with_json_body()
+}
@@ -10,16 +10,100 @@ use warp::{
     reject::InvalidHeader,
 };
 
+use crate::metrics;
+
 #[derive(Error, Debug)]
 pub enum ApiError {
     #[error("{0}")]
     ParsingError(String, String),
     #[error("validation error: {0}")]
     ValidationErrors(validator::ValidationErrors),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("database error: {0:?}")]
+    Database(neo4rs::Error),
+    #[error("storage error: {0}")]
+    Storage(#[from] std::io::Error),
+    #[error("invalid id: {0}")]
+    InvalidId(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0} has been modified since it was fetched")]
+    PreconditionFailed(String),
+    #[error("{0}: {1}")]
+    PayloadTooLarge(String, String),
+    #[error("{0}: {1}")]
+    UnsupportedMediaType(String, String),
 }
 
 impl warp::reject::Reject for ApiError {}
 
+impl ApiError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ParsingError(_, _) | ApiError::ValidationErrors(_) | ApiError::InvalidId(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::PayloadTooLarge(_, _) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_, _) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        }
+    }
+
+    /// Name of the variant, used as a metric label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::ParsingError(_, _) => "ParsingError",
+            ApiError::ValidationErrors(_) => "ValidationErrors",
+            ApiError::NotFound(_) => "NotFound",
+            ApiError::Conflict(_) => "Conflict",
+            ApiError::Database(_) => "Database",
+            ApiError::Storage(_) => "Storage",
+            ApiError::InvalidId(_) => "InvalidId",
+            ApiError::Unauthorized(_) => "Unauthorized",
+            ApiError::Forbidden(_) => "Forbidden",
+            ApiError::PreconditionFailed(_) => "PreconditionFailed",
+            ApiError::PayloadTooLarge(_, _) => "PayloadTooLarge",
+            ApiError::UnsupportedMediaType(_, _) => "UnsupportedMediaType",
+        }
+    }
+
+    /// Message safe to show to clients, driver and filesystem details are not leaked
+    pub fn public_message(&self) -> String {
+        match self {
+            ApiError::Database(_) => "Database unavailable".to_string(),
+            ApiError::Storage(_) => "Storage error".to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+// neo4rs::Error doesn't implement std::error::Error, so thiserror can't derive this
+// a constraint catches what the checks before a write can miss, like two requests taking the same email at once
+impl From<neo4rs::Error> for ApiError {
+    fn from(e: neo4rs::Error) -> Self {
+        if e.is_constraint_violation() {
+            return ApiError::Conflict("record already exists".to_string());
+        }
+        ApiError::Database(e)
+    }
+}
+
+// lets `Graph::write_transaction` retry a deadlock or a lost connection, whatever the work returned
+impl neo4rs::TransactionError for ApiError {
+    fn is_retryable(&self) -> bool {
+        matches!(self, ApiError::Database(e) if e.is_retryable())
+    }
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
     success: bool,
@@ -39,18 +123,20 @@ pub async fn handle_rejection(
 ) -> Result<impl warp::Reply, Infallible> {
     let (
         code,
+        kind,
         message,
         errors,
     ): (
         StatusCode,
+        &'static str,
         String,
         Option<Vec<FieldError>>,
     ) = if r.is_not_found() {
-        (StatusCode::NOT_FOUND, "Not found".to_string(), None)
+        (StatusCode::NOT_FOUND, "RouteNotFound", "Not found".to_string(), None)
     } else if let Some(e) = r.find::<CorsForbidden>() {
-        (StatusCode::FORBIDDEN, e.to_string(), None)
+        (StatusCode::FORBIDDEN, "CorsForbidden", e.to_string(), None)
     } else if let Some(e) = r.find::<InvalidHeader>() {
-        (StatusCode::BAD_REQUEST, e.to_string(), None)
+        (StatusCode::BAD_REQUEST, "InvalidHeader", e.to_string(), None)
     } else if let Some(e) = r.find::<ApiError>() {
         match e {
             ApiError::ParsingError(field, msg) => {
@@ -58,62 +144,193 @@ pub async fn handle_rejection(
                     field: field.clone(),
                     messages: vec![msg.clone()],
                 }];
-                (StatusCode::BAD_REQUEST, "Parsing errors".to_string(), Some(errors))
+                (StatusCode::BAD_REQUEST, e.kind(), "Parsing errors".to_string(), Some(errors))
+            },
+            ApiError::PayloadTooLarge(field, msg) | ApiError::UnsupportedMediaType(field, msg) => {
+                let errors: Vec<FieldError> = vec![FieldError {
+                    field: field.clone(),
+                    messages: vec![msg.clone()],
+                }];
+                let message: &str = if e.status_code() == StatusCode::PAYLOAD_TOO_LARGE { "Payload too large" } else { "Unsupported media type" };
+                (e.status_code(), e.kind(), message.to_string(), Some(errors))
             },
             ApiError::ValidationErrors(val_errs) => {
-                let errors: Vec<FieldError> = val_errs
-                    .errors()
-                    .iter()
-                    .map(|error_kind| FieldError {
-                        field: error_kind.0.to_string(),
-                        messages: match error_kind.1 {
-                            validator::ValidationErrorsKind::Struct(struct_err) => {
-                                validation_errs_to_str_vec(struct_err)
-                            },
-                            validator::ValidationErrorsKind::Field(field_errs) => field_errs
-                                .iter()
-                                .map(|fe| format!("{}", fe.code))
-                                .collect(),
-                            validator::ValidationErrorsKind::List(vec_errs) => vec_errs
-                                .iter()
-                                .map(|ve| {
-                                    let err_text = validation_errs_to_str_vec(ve.1).join(" | ");
-                                    format!("{}: {:?}", ve.0, err_text)
-                                })
-                                .collect(),
-                        },
-                    })
-                    .collect();
-                (StatusCode::BAD_REQUEST, "Validation errors".to_string(), Some(errors))
+                let mut errors: Vec<FieldError> = vec![];
+                flatten_validation_errors("", val_errs, &mut errors);
+                errors.sort_by(|a, b| a.field.cmp(&b.field));
+                (StatusCode::BAD_REQUEST, e.kind(), "Validation errors".to_string(), Some(errors))
+            },
+            _ => {
+                if e.status_code().is_server_error() {
+                    // the response only carries a generic message, the details go to the log
+                    tracing::error!(error = %e, "Request failed");
+                }
+                (e.status_code(), e.kind(), e.public_message(), None)
             },
         }
     } else if let Some(e) = r.find::<warp::body::BodyDeserializeError>() {
-        (StatusCode::BAD_REQUEST, e.to_string(), None)
+        (StatusCode::BAD_REQUEST, "BodyDeserializeError", e.to_string(), None)
     } else {
-        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string(), None)
+        tracing::error!(rejection = ?r, "Unhandled rejection");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Unhandled", "Internal server error".to_string(), None)
     };
+    metrics::record_rejection(kind);
 
     let json = warp::reply::json(&ErrorResponse {
         success: false,
-        message: message.into(),
+        message,
         errors,
     });
 
     Ok(warp::reply::with_status(json, code))
 }
 
-fn validation_errs_to_str_vec(ve: &validator::ValidationErrors) -> Vec<String> {
-    ve.field_errors()
-        .iter()
-        .map(|fe| {
-            format!(
-                "{}: errors: {}",
-                fe.0,
-                fe.1.iter()
-                    .map(|ve| format!("{}: {:?}", ve.code, ve.params))
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            )
-        })
-        .collect()
+/// Nested structs and lists become paths like `patch.email` or `items[2].name`
+fn flatten_validation_errors(
+    prefix: &str,
+    errs: &validator::ValidationErrors,
+    out: &mut Vec<FieldError>,
+) {
+    for (field, kind) in errs.errors() {
+        let path = format!("{}{}", prefix, field);
+        match kind {
+            validator::ValidationErrorsKind::Field(field_errs) => out.push(FieldError {
+                field: path,
+                messages: field_errs.iter().map(|fe| format!("{}", fe.code)).collect(),
+            }),
+            validator::ValidationErrorsKind::Struct(struct_errs) => {
+                flatten_validation_errors(&format!("{}.", path), struct_errs, out);
+            },
+            validator::ValidationErrorsKind::List(list_errs) => {
+                for (index, item_errs) in list_errs {
+                    flatten_validation_errors(&format!("{}[{}].", path, index), item_errs, out);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::Validate;
+    use warp::Reply;
+
+    async fn status_of(e: ApiError) -> StatusCode {
+        let reply = handle_rejection(warp::reject::custom(e)).await.unwrap();
+        reply.into_response().status()
+    }
+
+    #[tokio::test]
+    async fn should_map_not_found() {
+        assert_eq!(status_of(ApiError::NotFound("Company".to_string())).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn should_map_conflict() {
+        assert_eq!(status_of(ApiError::Conflict("email has already been taken".to_string())).await, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn should_map_database() {
+        assert_eq!(status_of(ApiError::Database(neo4rs::Error::ConnectionError)).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn should_map_constraint_violation_to_conflict() {
+        let e = neo4rs::Error::neo4j(
+            "Neo.ClientError.Schema.ConstraintValidationFailed".to_string(),
+            "Node(0) already exists with label `User` and property `email` = 'a@b.c'".to_string(),
+        );
+        assert_eq!(status_of(ApiError::from(e)).await, StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn should_retry_only_transient_database_errors() {
+        use neo4rs::TransactionError;
+        let deadlock = neo4rs::Error::neo4j(
+            "Neo.TransientError.Transaction.DeadlockDetected".to_string(),
+            "ForsetiClient can't acquire ExclusiveLock".to_string(),
+        );
+        assert!(ApiError::from(deadlock).is_retryable());
+        assert!(ApiError::from(neo4rs::Error::ConnectionError).is_retryable());
+        assert!(!ApiError::NotFound("Company".to_string()).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn should_map_storage() {
+        let e = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        assert_eq!(status_of(ApiError::Storage(e)).await, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn should_map_invalid_id() {
+        assert_eq!(status_of(ApiError::InvalidId("abc".to_string())).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn should_map_unauthorized() {
+        assert_eq!(status_of(ApiError::Unauthorized("Missing bearer token".to_string())).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn should_map_forbidden() {
+        assert_eq!(status_of(ApiError::Forbidden("Not allowed to update this user".to_string())).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn should_map_precondition_failed() {
+        assert_eq!(status_of(ApiError::PreconditionFailed("Company".to_string())).await, StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn should_map_unsupported_media_type() {
+        let e = ApiError::UnsupportedMediaType("avatar".to_string(), "Must be a PNG, JPEG, GIF or WebP image".to_string());
+        assert_eq!(status_of(e).await, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[derive(Validate)]
+    struct Patch {
+        #[validate(email)]
+        email: Option<String>,
+    }
+
+    #[derive(Validate)]
+    struct Bulk {
+        #[validate]
+        patch: Option<Patch>,
+        #[validate]
+        items: Vec<Patch>,
+    }
+
+    #[tokio::test]
+    async fn should_flatten_nested_validation_errors() {
+        let bulk = Bulk {
+            patch: Some(Patch { email: Some("nope".to_string()) }),
+            items: vec![
+                Patch { email: None },
+                Patch { email: Some("nope".to_string()) },
+            ],
+        };
+        let reply = handle_rejection(warp::reject::custom(
+            ApiError::ValidationErrors(bulk.validate().unwrap_err())
+        )).await.unwrap();
+        let body = warp::hyper::body::to_bytes(reply.into_response().into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["errors"], serde_json::json!([
+            {"field": "items[1].email", "messages": ["email"]},
+            {"field": "patch.email", "messages": ["email"]},
+        ]));
+    }
+
+    #[tokio::test]
+    async fn should_keep_error_response_shape() {
+        let reply = handle_rejection(warp::reject::custom(ApiError::NotFound("User".to_string()))).await.unwrap();
+        let body = warp::hyper::body::to_bytes(reply.into_response().into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "success": false,
+            "message": "User not found",
+        }));
+    }
 }
@@ -0,0 +1,202 @@
+use serde::Serialize;
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::Duration,
+};
+use warp::{
+    http::StatusCode,
+    Filter, Reply,
+};
+
+use crate::helpers::{fetch_one, with_db};
+use crate::storage::{with_storage, Storage};
+
+// probes for orchestrators, they live outside of /api/v1 and don't need a token
+// liveness only tells that the process serves requests, readiness also checks its dependencies
+
+const READY_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn init(
+    graph: Arc<neo4rs::Graph>,
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    live().or(ready(graph, storage))
+}
+
+/// GET /health/live
+fn live() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("health" / "live")
+        .and(warp::get())
+        .map(|| warp::reply::json(&Status { status: "ok" }))
+}
+
+/// GET /health/ready
+fn ready(
+    graph: Arc<neo4rs::Graph>,
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("health" / "ready")
+        .and(warp::get())
+        .and(with_db(graph))
+        .and(with_storage(storage))
+        .and_then(check_ready)
+}
+
+#[derive(Serialize)]
+struct Status {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct ReadyResponse {
+    status: &'static str,
+    database: DatabaseStatus,
+    storage: StorageStatus,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseStatus {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    latency_ms: u128,
+    pool: PoolStatus,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PoolStatus {
+    max_size: usize,
+    size: usize,
+    available: isize,
+}
+
+#[derive(Serialize)]
+struct StorageStatus {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    location: String,
+}
+
+async fn check_ready(
+    graph: Arc<neo4rs::Graph>,
+    storage: Arc<dyn Storage>,
+) -> Result<warp::reply::Response, Infallible> {
+    let database = check_database(&graph).await;
+    let storage = check_storage(storage.as_ref()).await;
+    let (status, code) = if database.ok && storage.ok {
+        ("ok", StatusCode::OK)
+    } else {
+        ("unavailable", StatusCode::SERVICE_UNAVAILABLE)
+    };
+    let json = warp::reply::json(&ReadyResponse {
+        status,
+        database,
+        storage,
+    });
+    Ok(warp::reply::with_status(json, code).into_response())
+}
+
+/// Runs a trivial query through the pool, a database that doesn't answer in time counts as down
+async fn check_database(graph: &neo4rs::Graph) -> DatabaseStatus {
+    let started = std::time::Instant::now();
+    let q: neo4rs::Query = neo4rs::query("RETURN 1 AS ok");
+    let error = match tokio::time::timeout(READY_TIMEOUT, fetch_one(graph, q, "Row")).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("No answer within {} seconds", READY_TIMEOUT.as_secs())),
+    };
+    let pool = graph.pool_status();
+    DatabaseStatus {
+        ok: error.is_none(),
+        error,
+        latency_ms: started.elapsed().as_millis(),
+        pool: PoolStatus {
+            max_size: pool.max_size,
+            size: pool.size,
+            available: pool.available,
+        },
+    }
+}
+
+/// Uploads can't be accepted if the storage can't take files, with the same timeout as the database
+async fn check_storage(storage: &dyn Storage) -> StorageStatus {
+    let error = match tokio::time::timeout(READY_TIMEOUT, storage.check()).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("No answer within {} seconds", READY_TIMEOUT.as_secs())),
+    };
+    StorageStatus {
+        ok: error.is_none(),
+        error,
+        location: storage.location(),
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM, so that the server stops accepting connections and drains the open ones
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for SIGINT");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Shutting down, waiting for in-flight requests to finish");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    async fn unreachable_graph() -> Arc<neo4rs::Graph> {
+        Arc::new(neo4rs::Graph::new("127.0.0.1:1", "neo4j", "neo4j").await.unwrap())
+    }
+
+    fn missing_storage() -> Arc<dyn Storage> {
+        Arc::new(LocalStorage::new("/nonexistent/storage".into(), "/storage", b"secret"))
+    }
+
+    #[tokio::test]
+    async fn should_answer_liveness_without_database() {
+        let response = warp::test::request()
+            .path("/health/live")
+            .reply(&init(unreachable_graph().await, missing_storage()))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), r#"{"status":"ok"}"#);
+    }
+
+    #[tokio::test]
+    async fn should_not_be_ready_without_database() {
+        let response = warp::test::request()
+            .path("/health/ready")
+            .reply(&init(unreachable_graph().await, missing_storage()))
+            .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["database"]["ok"], false);
+        assert_eq!(body["database"]["pool"]["maxSize"], 16);
+    }
+
+    #[tokio::test]
+    async fn should_report_missing_storage_directory() {
+        let storage = check_storage(missing_storage().as_ref()).await;
+        assert!(!storage.ok);
+        assert_eq!(storage.location, "/nonexistent/storage");
+    }
+}
@@ -1,12 +1,19 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Deserializer;
 use std::{
     convert::Infallible,
     sync::Arc,
 };
 use validator::Validate;
-use warp::Filter;
+use warp::{
+    http::HeaderValue,
+    Buf, Filter,
+};
+
+use crate::error_handler::ApiError;
+use crate::query_builder::Trashed;
 
 pub fn with_db(
     graph: Arc<neo4rs::Graph>,
@@ -16,6 +23,119 @@ pub fn with_db(
     })
 }
 
+pub fn parse_id(id: &str) -> Result<i64, ApiError> {
+    id.parse::<i64>().map_err(|_| ApiError::InvalidId(id.to_string()))
+}
+
+/// Executes a query that is expected to match a single node, like `show` or `update`
+pub async fn fetch_one(
+    graph: &neo4rs::Graph,
+    q: neo4rs::Query,
+    label: &str,
+) -> Result<neo4rs::Row, ApiError> {
+    let mut result: neo4rs::RowStream = graph.execute(q).await?;
+    match result.next().await? {
+        Some(row) => Ok(row),
+        None => Err(ApiError::NotFound(label.to_string())),
+    }
+}
+
+/// Same as `fetch_one` inside a transaction, the rest of the stream is drained
+/// so that the connection is ready for the next query
+pub async fn fetch_one_in(
+    txn: &neo4rs::Txn,
+    q: neo4rs::Query,
+    label: &str,
+) -> Result<neo4rs::Row, ApiError> {
+    let mut result: neo4rs::RowStream = txn.execute(q).await?;
+    let row = result.next().await?;
+    while result.next().await?.is_some() {}
+    row.ok_or_else(|| ApiError::NotFound(label.to_string()))
+}
+
+// body
+
+/// JSON body deserialized into `T` and validated
+pub fn with_json_body<T>() -> impl Filter<Extract = (T, ), Error = warp::Rejection> + Clone
+where
+    T: DeserializeOwned + Validate + Send,
+{
+    warp::any()
+        .and(warp::header::value("content-type"))
+        .and(warp::body::aggregate())
+        .and_then(|content_type: HeaderValue, buf| async move {
+            parse_json_body(&content_type, buf).map_err(warp::reject::custom)
+        })
+}
+
+/// Errors name the field at fault, the path is `.` when the body itself is wrong, like a missing field
+fn parse_json_body<T>(content_type: &HeaderValue, buf: impl Buf) -> Result<T, ApiError>
+where
+    T: DeserializeOwned + Validate,
+{
+    if !content_type.to_str().is_ok_and(|x| x.starts_with("application/json")) {
+        return Err(ApiError::ParsingError("content-type".to_string(), "Must be application/json".to_string()));
+    }
+    let deserializer = &mut Deserializer::from_reader(buf.reader());
+    let params: T = serde_path_to_error::deserialize(deserializer)
+        .map_err(|e| ApiError::ParsingError(e.path().to_string(), e.inner().to_string()))?;
+    params.validate().map_err(ApiError::ValidationErrors)?;
+    Ok(params)
+}
+
+// include
+
+#[derive(Default, Deserialize)]
+pub struct IncludeParams {
+    pub include: Option<String>,
+}
+
+/// Related records to embed in a response, like `?include=members`
+pub fn with_include(
+    allowed: &'static [&'static str],
+) -> impl Filter<Extract = (Vec<String>, ), Error = warp::Rejection> + Clone {
+    warp::query::<IncludeParams>().and_then(move |params: IncludeParams| async move {
+        parse_include(params.include.as_deref().unwrap_or_default(), allowed)
+            .map_err(warp::reject::custom)
+    })
+}
+
+fn parse_include(value: &str, allowed: &[&str]) -> Result<Vec<String>, ApiError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if allowed.contains(&name) {
+                Ok(name.to_string())
+            } else {
+                Err(ApiError::ParsingError("include".to_string(), format!("Must be one of {}", allowed.join(", "))))
+            }
+        })
+        .collect()
+}
+
+// trashed
+
+#[derive(Default, Deserialize)]
+pub struct TrashedParams {
+    pub trashed: Option<String>,
+}
+
+/// `?trashed=only|with`, soft deleted records are hidden without it
+pub fn with_trashed() -> impl Filter<Extract = (Trashed, ), Error = warp::Rejection> + Clone {
+    warp::query::<TrashedParams>().and_then(|params: TrashedParams| async move {
+        match params.trashed {
+            None => Ok(Trashed::Without),
+            Some(value) => Trashed::parse(&value).ok_or_else(|| {
+                warp::reject::custom(
+                    ApiError::ParsingError("trashed".to_string(), "Must be one of only, with".to_string())
+                )
+            }),
+        }
+    })
+}
+
 // delete
 
 lazy_static! {
@@ -27,3 +147,71 @@ pub struct DeleteParams {
     #[validate(regex = "REGEX_THREE_MODES")]
     pub mode: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Login {
+        #[validate(length(min = 1))]
+        email: String,
+    }
+
+    #[tokio::test]
+    async fn should_name_the_field_of_a_bad_body() {
+        let filter = with_json_body::<Login>();
+        let rejection = warp::test::request()
+            .header("content-type", "application/json")
+            .body("{}")
+            .filter(&filter)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            rejection.find::<ApiError>(),
+            Some(ApiError::ParsingError(path, message)) if path == "." && message.starts_with("missing field `email`")
+        ));
+
+        let rejection = warp::test::request()
+            .header("content-type", "application/json")
+            .body(r#"{"email":42}"#)
+            .filter(&filter)
+            .await
+            .unwrap_err();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::ParsingError(path, _)) if path == "email"));
+
+        let rejection = warp::test::request()
+            .header("content-type", "text/plain")
+            .body(r#"{"email":"a@example.com"}"#)
+            .filter(&filter)
+            .await
+            .unwrap_err();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::ParsingError(path, _)) if path == "content-type"));
+
+        let login = warp::test::request()
+            .header("content-type", "application/json")
+            .body(r#"{"email":"a@example.com"}"#)
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(login.email, "a@example.com");
+    }
+
+    #[tokio::test]
+    async fn should_hide_trashed_by_default() {
+        let filter = with_trashed();
+        assert_eq!(warp::test::request().path("/").filter(&filter).await.unwrap(), Trashed::Without);
+        assert_eq!(warp::test::request().path("/?trashed=only").filter(&filter).await.unwrap(), Trashed::Only);
+        assert!(warp::test::request().path("/?trashed=all").filter(&filter).await.is_err());
+    }
+
+    #[test]
+    fn should_parse_include() {
+        assert_eq!(parse_include("", &["members"]).unwrap(), Vec::<String>::new());
+        assert_eq!(parse_include("members", &["members"]).unwrap(), vec!["members"]);
+        assert!(matches!(
+            parse_include("members,secrets", &["members"]),
+            Err(ApiError::ParsingError(_, _))
+        ));
+    }
+}
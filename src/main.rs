@@ -1,39 +1,94 @@
 use dotenv::dotenv;
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::Duration,
+};
 use warp::{http::Method, Filter};
 
+mod audit;
+mod avatar;
+mod auth;
+mod bulk;
 mod config;
 mod database;
 mod error_handler;
+mod health;
 mod helpers;
+mod membership;
+mod metrics;
+mod pagination;
+mod policy;
+mod precondition;
+mod purge;
+mod query_builder;
+mod sorting;
+mod storage;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
 mod company;
+mod department;
 mod user;
 
 #[tokio::main]
 async fn main() {
-    println!("Hello, world!");
     dotenv().ok();
+    let settings: &config::Settings = config::init();
+    telemetry::init(&settings.log.level);
+    let handle = metrics::install();
 
     let cors = warp::cors()
-        .allow_any_origin()
-        .allow_header("Content-Type")
-        .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE]);
+        .allow_headers(vec!["Authorization", "Content-Type", "If-Match", "If-None-Match", "X-Request-Id"])
+        .expose_headers(vec!["ETag", "X-Request-Id"])
+        .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE]);
+    let cors = if settings.server.cors_origins.iter().any(|x| x == "*") {
+        cors.allow_any_origin()
+    } else {
+        cors.allow_origins(settings.server.cors_origins.iter().map(String::as_str))
+    };
 
-    let graph: Arc<neo4rs::Graph> = database::init_pool().await;
-    let routes = api_filters(graph).with(cors);
+    let graph: Arc<neo4rs::Graph> = database::init_pool(&settings.database).await;
+    match auth::seed_admin(&graph, &settings.auth).await {
+        Ok(Some(id)) => tracing::info!(user_id = id, email = %settings.auth.admin_email, "Created the first user"),
+        Ok(None) => {},
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to create the first user");
+            std::process::exit(1);
+        },
+    }
+    let storage: Arc<dyn storage::Storage> = storage::init(settings);
+    purge::spawn(
+        graph.clone(),
+        storage.clone(),
+        settings.trash.retention_days,
+        Duration::from_secs(settings.trash.purge_interval_minutes * 60),
+    );
+    let keys: Arc<auth::JwtKeys> = Arc::new(auth::JwtKeys::new(settings.auth.jwt_secret.as_bytes()));
+    let routes = health::init(graph.clone(), storage.clone())
+        .or(metrics::init(graph.clone(), handle))
+        .or(avatar::init(storage.clone()))
+        .or(api_filters(graph, keys, storage))
+        .with(cors);
 
-    warp::serve(routes)
-        .run(([127, 0, 0, 1], 7070))
-        .await;
+    if let Err(e) = telemetry::serve(routes, settings.bind_address().unwrap(), health::shutdown_signal()).await {
+        tracing::error!(error = %e, "Server failed");
+        std::process::exit(1);
+    }
 }
 
 fn api_filters(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<auth::JwtKeys>,
+    storage: Arc<dyn storage::Storage>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("api" / "v1" / ..) // Add path prefix /api/v1 to all our routes
         .and(
-            company::init(graph.clone())
-                .or(user::init(graph))
+            auth::init(graph.clone(), keys.clone())
+                .or(company::init(graph.clone(), keys.clone()))
+                .or(membership::init(graph.clone(), keys.clone()))
+                .or(department::init(graph.clone(), keys.clone()))
+                .or(audit::init(graph.clone(), keys.clone()))
+                .or(user::init(graph, keys, storage))
                 .recover(error_handler::handle_rejection)
         )
 }
@@ -0,0 +1,382 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    vec::Vec,
+};
+use warp::http::StatusCode;
+
+use crate::error_handler::ApiError;
+use crate::helpers::{
+    fetch_one,
+    parse_id,
+};
+use crate::membership::{
+    AddMemberParams,
+    MemberResponse,
+    MembershipResponse,
+    MembershipSummary,
+};
+use crate::pagination::{Page, Pagination};
+use crate::policy::{find_company_role, Role};
+
+// memberships created before `since` was recorded fall back to the company creation date
+
+#[tracing::instrument(skip_all, fields(company_id = %company_id))]
+pub async fn find_members(
+    company_id: String,
+    pagination: Pagination,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let company_id: i64 = parse_id(&company_id)?;
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (c:Company)
+        WHERE id(c) = $id
+        OPTIONAL MATCH (u:User)-[:MEMBER_OF]->(c)
+        RETURN count(u) AS total
+    ")
+    .param("id", company_id);
+    let row: neo4rs::Row = fetch_one(&graph, q, "Company").await?;
+    let total: i64 = row.get("total").unwrap_or(0);
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[m:MEMBER_OF]->(c:Company)
+        WHERE id(c) = $id
+        RETURN u, m.role AS role, coalesce(m.since, date(c.createdAt)) AS since
+        ORDER BY u.name
+        SKIP $skip
+        LIMIT $limit
+    ")
+    .param("id", company_id)
+    .param("skip", pagination.offset)
+    .param("limit", pagination.per_page);
+
+    let mut result: neo4rs::RowStream = graph.execute(q).await.map_err(ApiError::from)?;
+    let mut records: Vec<MemberResponse> = vec![];
+    while let Some(row) = result.next().await.map_err(ApiError::from)? {
+        records.push(MemberResponse::from_row(row));
+    }
+    Ok(Page::new(records, total, &pagination).into_reply(&pagination))
+}
+
+#[tracing::instrument(skip_all, fields(user_id = %user_id))]
+pub async fn find_user_companies(
+    user_id: String,
+    pagination: Pagination,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user_id: i64 = parse_id(&user_id)?;
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)
+        WHERE id(u) = $id
+        OPTIONAL MATCH (u)-[:MEMBER_OF]->(c:Company)
+        RETURN count(c) AS total
+    ")
+    .param("id", user_id);
+    let row: neo4rs::Row = fetch_one(&graph, q, "User").await?;
+    let total: i64 = row.get("total").unwrap_or(0);
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[m:MEMBER_OF]->(c:Company)
+        WHERE id(u) = $id
+        RETURN c, m.role AS role, coalesce(m.since, date(c.createdAt)) AS since
+        ORDER BY c.name
+        SKIP $skip
+        LIMIT $limit
+    ")
+    .param("id", user_id)
+    .param("skip", pagination.offset)
+    .param("limit", pagination.per_page);
+
+    let mut result: neo4rs::RowStream = graph.execute(q).await.map_err(ApiError::from)?;
+    let mut records: Vec<MembershipResponse> = vec![];
+    while let Some(row) = result.next().await.map_err(ApiError::from)? {
+        records.push(MembershipResponse::from_row(row));
+    }
+    Ok(Page::new(records, total, &pagination).into_reply(&pagination))
+}
+
+/// Adds the user to the company, or changes the role if already a member
+#[tracing::instrument(skip_all, fields(company_id = %company_id, user_id = %user_id))]
+pub async fn add_member(
+    company_id: String,
+    user_id: String,
+    params: AddMemberParams,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let company_id: i64 = parse_id(&company_id)?;
+    let user_id: i64 = parse_id(&user_id)?;
+    let role: String = params.role.unwrap_or_else(|| Role::Member.as_str().to_string());
+    if role != Role::Owner.as_str() {
+        ensure_other_owner(&graph, company_id, user_id).await?;
+    }
+
+    // the merge below finds nothing when either node is missing, so the company is looked up on its own
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (c:Company)
+        WHERE id(c) = $id
+        RETURN id(c) AS id
+    ")
+    .param("id", company_id);
+    fetch_one(&graph, q, "Company").await?;
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (c:Company), (u:User)
+        WHERE id(c) = $company_id AND id(u) = $user_id
+        MERGE (u)-[m:MEMBER_OF]->(c)
+        ON CREATE SET m.since = date()
+        SET m.role = $role
+        RETURN u, m.role AS role, coalesce(m.since, date(c.createdAt)) AS since
+    ")
+    .param("company_id", company_id)
+    .param("user_id", user_id)
+    .param("role", role);
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "User").await?;
+    let record: MemberResponse = MemberResponse::from_row(row);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&record),
+        StatusCode::CREATED,
+    ))
+}
+
+#[tracing::instrument(skip_all, fields(company_id = %company_id, user_id = %user_id))]
+pub async fn remove_member(
+    company_id: String,
+    user_id: String,
+    graph: Arc<neo4rs::Graph>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let company_id: i64 = parse_id(&company_id)?;
+    let user_id: i64 = parse_id(&user_id)?;
+    ensure_other_owner(&graph, company_id, user_id).await?;
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[m:MEMBER_OF]->(c:Company)
+        WHERE id(c) = $company_id AND id(u) = $user_id
+        OPTIONAL MATCH (u)-[dm:MEMBER_OF]->(:Department)-[:PART_OF*1..]->(c)
+        DELETE dm, m
+        RETURN count(DISTINCT m) AS count
+    ")
+    .param("company_id", company_id)
+    .param("user_id", user_id);
+
+    let row: neo4rs::Row = fetch_one(&graph, q, "Membership").await?;
+    if row.get::<i64>("count") != Some(1) {
+        return Err(ApiError::NotFound("Membership".to_string()).into());
+    }
+    let empty: Vec<u8> = vec![];
+    Ok(warp::reply::with_status(
+        warp::reply::json(&empty),
+        StatusCode::NO_CONTENT,
+    ))
+}
+
+/// Members of each company, keyed by company id
+pub async fn find_member_summaries(
+    graph: &neo4rs::Graph,
+    company_ids: Vec<i64>,
+) -> Result<HashMap<i64, Vec<MembershipSummary>>, ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[m:MEMBER_OF]->(c:Company)
+        WHERE id(c) IN $ids
+        RETURN id(c) AS key, id(u) AS id, u.name AS name, m.role AS role, coalesce(m.since, date(c.createdAt)) AS since
+        ORDER BY name
+    ")
+    .param("ids", company_ids);
+    find_summaries(graph, q).await
+}
+
+/// Companies of each user, keyed by user id
+pub async fn find_company_summaries(
+    graph: &neo4rs::Graph,
+    user_ids: Vec<i64>,
+) -> Result<HashMap<i64, Vec<MembershipSummary>>, ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[m:MEMBER_OF]->(c:Company)
+        WHERE id(u) IN $ids
+        RETURN id(u) AS key, id(c) AS id, c.name AS name, m.role AS role, coalesce(m.since, date(c.createdAt)) AS since
+        ORDER BY name
+    ")
+    .param("ids", user_ids);
+    find_summaries(graph, q).await
+}
+
+async fn find_summaries(
+    graph: &neo4rs::Graph,
+    q: neo4rs::Query,
+) -> Result<HashMap<i64, Vec<MembershipSummary>>, ApiError> {
+    let mut result: neo4rs::RowStream = graph.execute(q).await?;
+    let mut summaries: HashMap<i64, Vec<MembershipSummary>> = HashMap::new();
+    while let Some(row) = result.next().await? {
+        let key: i64 = row.get("key").unwrap();
+        summaries.entry(key).or_default().push(MembershipSummary::from_row(&row));
+    }
+    Ok(summaries)
+}
+
+// a company must never be left without an owner
+async fn ensure_other_owner(
+    graph: &neo4rs::Graph,
+    company_id: i64,
+    user_id: i64,
+) -> Result<(), ApiError> {
+    if find_company_role(graph, company_id, user_id).await? != Some(Role::Owner) {
+        return Ok(());
+    }
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[:MEMBER_OF {role: $role}]->(c:Company)
+        WHERE id(c) = $company_id AND id(u) <> $user_id
+        RETURN count(u) AS count
+    ")
+    .param("role", Role::Owner.as_str())
+    .param("company_id", company_id)
+    .param("user_id", user_id);
+
+    let row: neo4rs::Row = fetch_one(graph, q, "Company").await?;
+    if row.get::<i64>("count").unwrap_or(0) == 0 {
+        return Err(ApiError::Conflict("Company must keep at least one owner".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDate};
+    use neo4rs::testing::{node, BoltType, Expect, Reply, Script, StubServer};
+    use crate::test_support::connect;
+
+    const ROLE: &str = "
+        MATCH (c:Company)
+        WHERE id(c) = $company_id
+        OPTIONAL MATCH (u:User)-[m:MEMBER_OF]->(c)
+        WHERE id(u) = $user_id
+        RETURN m.role AS role
+    ";
+
+    /// The role user 7 has in company 42
+    fn role(script: Script, role: &str) -> Script {
+        script
+            .expect(Expect::run(ROLE).param("company_id", 42i64).param("user_id", 7i64))
+            .returns(&["role"], vec![vec![role.into()]])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+    }
+
+    fn member(role: &str) -> Vec<BoltType> {
+        let now = DateTime::parse_from_rfc3339("2021-06-01T09:00:00+00:00").unwrap();
+        vec![
+            node(7, &["User"], vec![
+                ("name", "John".into()),
+                ("email", "john@example.com".into()),
+                ("avatar", "".into()),
+                ("createdAt", now.into()),
+                ("updatedAt", now.into()),
+            ]),
+            role.into(),
+            NaiveDate::from_ymd_opt(2021, 6, 1).unwrap().into(),
+        ]
+    }
+
+    fn owner() -> AddMemberParams {
+        AddMemberParams {
+            role: Some("owner".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_name_missing_company() {
+        let script = Script::new()
+            .expect(Expect::run("MATCH (c:Company) WHERE id(c) = $id RETURN id(c) AS id").param("id", 42i64))
+            .returns(&["id"], vec![]);
+        let server = StubServer::start(script).await;
+
+        let rejection = add_member("42".to_string(), "7".to_string(), owner(), connect(&server).await)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::NotFound(x)) if x == "Company"));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_name_missing_user() {
+        let script = Script::new()
+            .expect(Expect::run("MATCH (c:Company) WHERE id(c) = $id RETURN id(c) AS id").param("id", 42i64))
+            .returns(&["id"], vec![vec![42i64.into()]])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("MERGE (u)-[m:MEMBER_OF]->(c)")
+                .param("company_id", 42i64)
+                .param("user_id", 7i64)
+                .param("role", "owner"))
+            .returns(&["u", "role", "since"], vec![]);
+        let server = StubServer::start(script).await;
+
+        let rejection = add_member("42".to_string(), "7".to_string(), owner(), connect(&server).await)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::NotFound(x)) if x == "User"));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_change_role_of_member() {
+        let script = role(Script::new(), "guest")
+            .expect(Expect::run("MATCH (c:Company) WHERE id(c) = $id RETURN id(c) AS id").param("id", 42i64))
+            .returns(&["id"], vec![vec![42i64.into()]])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("MERGE (u)-[m:MEMBER_OF]->(c)")
+                .param("company_id", 42i64)
+                .param("user_id", 7i64)
+                .param("role", "member"))
+            .returns(&["u", "role", "since"], vec![member("member")]);
+        let server = StubServer::start(script).await;
+        let params = AddMemberParams {
+            role: None,
+        };
+
+        let reply = add_member("42".to_string(), "7".to_string(), params, connect(&server).await).await.unwrap();
+        let response = warp::Reply::into_response(reply);
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["user"]["id"], 7);
+        assert_eq!(body["role"], "member");
+        assert_eq!(body["since"], "2021-06-01");
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_keep_last_owner() {
+        let script = role(Script::new(), "owner")
+            .expect(Expect::run_containing("WHERE id(c) = $company_id AND id(u) <> $user_id")
+                .param("role", "owner")
+                .param("company_id", 42i64)
+                .param("user_id", 7i64))
+            .returns(&["count"], vec![vec![0i64.into()]]);
+        let server = StubServer::start(script).await;
+
+        let rejection = remove_member("42".to_string(), "7".to_string(), connect(&server).await)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Conflict(_))));
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_remove_member_from_company_and_its_departments() {
+        let script = role(Script::new(), "member")
+            .expect(Expect::run_containing("DELETE dm, m")
+                .param("company_id", 42i64)
+                .param("user_id", 7i64))
+            .returns(&["count"], vec![vec![1i64.into()]]);
+        let server = StubServer::start(script).await;
+
+        let reply = remove_member("42".to_string(), "7".to_string(), connect(&server).await).await.unwrap();
+        assert_eq!(warp::Reply::into_response(reply).status(), StatusCode::NO_CONTENT);
+        server.finish();
+    }
+}
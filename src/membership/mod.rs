@@ -0,0 +1,7 @@
+mod model;
+mod controller;
+mod router;
+
+pub use model::*;
+pub use controller::*;
+pub use router::init;
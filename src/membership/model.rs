@@ -0,0 +1,84 @@
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::company::CompanyResponse;
+use crate::user::UserResponse;
+
+// add
+
+lazy_static! {
+    static ref REGEX_ROLE: Regex = Regex::new(r"^(guest|member|admin|owner)$").unwrap();
+}
+
+#[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
+pub struct AddMemberParams {
+    #[validate(regex = "REGEX_ROLE")]
+    pub role: Option<String>, // member by default
+}
+
+// response
+
+/// Compact form embedded in company and user responses by `?include=`
+/// `id` and `name` belong to the other end of the relationship
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipSummary {
+    pub id: i64,
+    pub name: String,
+    pub role: String,
+    pub since: NaiveDate,
+}
+
+impl MembershipSummary {
+    pub fn from_row(row: &neo4rs::Row) -> MembershipSummary {
+        MembershipSummary {
+            id: row.get("id").unwrap(),
+            name: row.get("name").unwrap(),
+            role: row.get("role").unwrap(),
+            since: row.get("since").unwrap(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberResponse {
+    pub user: UserResponse,
+    pub role: String,
+    pub since: NaiveDate,
+}
+
+impl MemberResponse {
+    pub fn from_row(row: neo4rs::Row) -> MemberResponse {
+        let role: String = row.get("role").unwrap();
+        let since: NaiveDate = row.get("since").unwrap();
+        MemberResponse {
+            user: UserResponse::from_row(row),
+            role,
+            since,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipResponse {
+    pub company: CompanyResponse,
+    pub role: String,
+    pub since: NaiveDate,
+}
+
+impl MembershipResponse {
+    pub fn from_row(row: neo4rs::Row) -> MembershipResponse {
+        let role: String = row.get("role").unwrap();
+        let since: NaiveDate = row.get("since").unwrap();
+        MembershipResponse {
+            company: CompanyResponse::from_row(row),
+            role,
+            since,
+        }
+    }
+}
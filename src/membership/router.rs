@@ -0,0 +1,147 @@
+use std::sync::Arc;
+use warp::Filter;
+
+use crate::helpers::{
+    with_json_body,
+    parse_id,
+    with_db,
+};
+use crate::auth::{require_auth, with_auth, JwtKeys};
+use crate::error_handler::ApiError;
+use crate::pagination::with_pagination;
+use crate::policy::{
+    authorize_grant,
+    find_company_role,
+    Role,
+};
+use crate::user::UserResponse;
+use crate::membership::{
+    self,
+    AddMemberParams,
+};
+
+pub fn init(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    find_members(graph.clone(), keys.clone())
+        .or(add_member(graph.clone(), keys.clone()))
+        .or(remove_member(graph.clone(), keys.clone()))
+        .or(find_user_companies(graph, keys))
+}
+
+/// GET /companies/:id/members
+fn find_members(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / String / "members")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_pagination())
+        .and(with_db(graph))
+        .and_then(membership::find_members)
+}
+
+/// POST /companies/:id/members/:user_id
+fn add_member(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / String / "members" / String)
+        .and(warp::post())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_add_params())
+        .and(with_db(graph.clone()))
+        .and_then(|company_id: String, user_id: String, current_user: UserResponse, params: AddMemberParams, graph: Arc<neo4rs::Graph>| async move {
+            let role: Role = params.role.as_deref().and_then(Role::parse).unwrap_or(Role::Member);
+            authorize(&company_id, &user_id, &current_user, &graph, Some(role)).await?;
+            Ok::<(String, String, AddMemberParams), warp::Rejection>((company_id, user_id, params))
+        })
+        .untuple_one()
+        .and(with_db(graph))
+        .and_then(membership::add_member)
+}
+
+/// DELETE /companies/:id/members/:user_id
+fn remove_member(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("companies" / String / "members" / String)
+        .and(warp::delete())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|company_id: String, user_id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&company_id, &user_id, &current_user, &graph, None).await?;
+            Ok::<(String, String), warp::Rejection>((company_id, user_id))
+        })
+        .untuple_one()
+        .and(with_db(graph))
+        .and_then(membership::remove_member)
+}
+
+/// GET /users/:id/companies
+fn find_user_companies(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("users" / String / "companies")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_pagination())
+        .and(with_db(graph))
+        .and_then(membership::find_user_companies)
+}
+
+// granting a role, or revoking the current one, is checked against the role of the caller
+// anybody may leave a company on their own
+async fn authorize(
+    company_id: &str,
+    user_id: &str,
+    current_user: &UserResponse,
+    graph: &neo4rs::Graph,
+    new_role: Option<Role>,
+) -> Result<(), ApiError> {
+    let company_id: i64 = parse_id(company_id)?;
+    let user_id: i64 = parse_id(user_id)?;
+    if new_role.is_none() && user_id == current_user.id {
+        return Ok(());
+    }
+    let role: Option<Role> = find_company_role(graph, company_id, current_user.id).await?;
+    if let Some(new_role) = new_role {
+        authorize_grant(role, new_role)?;
+    }
+    let old_role: Option<Role> = find_company_role(graph, company_id, user_id).await?;
+    authorize_grant(role, old_role.unwrap_or(Role::Guest))
+}
+
+fn with_add_params() -> impl Filter<Extract = (AddMemberParams, ), Error = warp::Rejection> + Clone {
+    with_json_body()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_reject_unknown_role_before_touching_database() {
+        let filter = with_add_params();
+        let rejection = warp::test::request()
+            .header("content-type", "application/json")
+            .body(r#"{"role":"root"}"#)
+            .filter(&filter)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::ValidationErrors(_))));
+
+        let params = warp::test::request()
+            .header("content-type", "application/json")
+            .body(r#"{"role":"admin"}"#)
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(params.role.as_deref(), Some("admin"));
+    }
+}
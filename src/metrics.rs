@@ -0,0 +1,138 @@
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::Duration,
+};
+use warp::{
+    http::{header::CONTENT_TYPE, Method},
+    Filter,
+};
+
+use crate::helpers::with_db;
+
+// metrics are exposed in the Prometheus text format at /metrics, next to the health probes
+// http metrics are recorded by `telemetry::serve` for every response, including the ones made from rejections
+// the driver records `neo4rs_query_duration_seconds` and `neo4rs_rows_streamed_total` by itself
+// pool gauges are read from the driver when the endpoint is scraped
+
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// every path segment that isn't one of these is an id, so that routes don't explode into one series per record
+const STATIC_SEGMENTS: &[&str] = &[
+    "api", "v1", "health", "live", "ready", "metrics", "storage",
+    "auth", "login", "refresh", "logout",
+    "companies", "users", "departments",
+    "trash", "bulk", "members", "history", "org-chart", "move",
+];
+
+/// Installs the global recorder, the handle renders what has been recorded
+pub fn install() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets(LATENCY_BUCKETS)
+        .unwrap()
+        .install_recorder()
+        .expect("Failed to install the metrics recorder")
+}
+
+/// GET /metrics
+pub fn init(
+    graph: Arc<neo4rs::Graph>,
+    handle: PrometheusHandle,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and(with_db(graph))
+        .and(warp::any().map(move || handle.clone()))
+        .and_then(render)
+}
+
+async fn render(
+    graph: Arc<neo4rs::Graph>,
+    handle: PrometheusHandle,
+) -> Result<impl warp::Reply, Infallible> {
+    record_pool(graph.pool_status());
+    handle.run_upkeep();
+    Ok(warp::reply::with_header(
+        handle.render(),
+        CONTENT_TYPE,
+        "text/plain; version=0.0.4",
+    ))
+}
+
+fn record_pool(pool: neo4rs::PoolStatus) {
+    let idle = pool.available.max(0) as usize;
+    metrics::gauge!("neo4rs_pool_max_connections").set(pool.max_size as f64);
+    metrics::gauge!("neo4rs_pool_connections").set(pool.size as f64);
+    metrics::gauge!("neo4rs_pool_idle_connections").set(idle as f64);
+    metrics::gauge!("neo4rs_pool_in_use_connections").set(pool.size.saturating_sub(idle) as f64);
+    metrics::gauge!("neo4rs_pool_waiting_requests").set((-pool.available).max(0) as f64);
+}
+
+pub fn record_request(method: &Method, path: &str, status: u16, elapsed: Duration) {
+    let route = route_label(path);
+    metrics::counter!(
+        "http_requests_total",
+        "method" => method.to_string(),
+        "route" => route.clone(),
+        "status" => status.to_string()
+    )
+    .increment(1);
+    metrics::histogram!(
+        "http_request_duration_seconds",
+        "method" => method.to_string(),
+        "route" => route
+    )
+    .record(elapsed.as_secs_f64());
+}
+
+/// Counted by `handle_rejection`, `kind` is the `ApiError` variant or the warp rejection
+pub fn record_rejection(kind: &'static str) {
+    metrics::counter!("http_rejections_total", "kind" => kind).increment(1);
+}
+
+/// `/api/v1/companies/42/members/7` becomes `/api/v1/companies/:id/members/:id`
+fn route_label(path: &str) -> String {
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            if STATIC_SEGMENTS.contains(&segment) {
+                segment
+            } else {
+                ":id"
+            }
+        })
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_replace_ids_in_route() {
+        assert_eq!(route_label("/api/v1/companies/42/members/7"), "/api/v1/companies/:id/members/:id");
+        assert_eq!(route_label("/api/v1/users/trash"), "/api/v1/users/trash");
+        assert_eq!(route_label("/wp-admin/setup.php"), "/:id/:id");
+        assert_eq!(route_label("/"), "/");
+    }
+
+    #[test]
+    fn should_render_pool_gauges() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            record_pool(neo4rs::PoolStatus {
+                max_size: 10,
+                size: 4,
+                available: 1,
+            });
+        });
+        let text = handle.render();
+        assert!(text.contains("neo4rs_pool_connections 4"));
+        assert!(text.contains("neo4rs_pool_in_use_connections 3"));
+        assert!(text.contains("neo4rs_pool_waiting_requests 0"));
+    }
+}
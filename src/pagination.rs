@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use warp::{
+    http::{header::LINK, HeaderValue},
+    path::FullPath,
+    Filter, Reply,
+};
+
+use crate::error_handler::ApiError;
+
+const DEFAULT_PER_PAGE: u32 = 20;
+
+// same approach as find requests, numbers are accepted as string at first
+// then parsed and validated by PaginationRequest
+
+#[derive(Default, Deserialize)]
+pub struct PaginationParams {
+    pub page: Option<String>,
+    pub per_page: Option<String>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Default, Validate)]
+pub struct PaginationRequest {
+    #[validate(range(min = 1))]
+    pub page: Option<u32>,
+    #[validate(range(min = 5, max = 100))]
+    pub per_page: Option<u32>,
+    pub cursor: Option<String>,
+}
+
+/// Resolved window of a list endpoint, shared by every resource
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pagination {
+    pub offset: i64,
+    pub per_page: i64,
+    path: String,
+    query: Vec<String>, // other query pairs kept as is, to be repeated in Link header
+}
+
+impl Pagination {
+    fn from_request(
+        req: PaginationRequest,
+        path: String,
+        raw_query: &str,
+    ) -> Result<Pagination, ApiError> {
+        let (offset, per_page) = match req.cursor {
+            Some(cursor) => decode_cursor(&cursor)?,
+            None => {
+                let per_page = req.per_page.unwrap_or(DEFAULT_PER_PAGE) as i64;
+                let page = req.page.unwrap_or(1) as i64;
+                ((page - 1) * per_page, per_page)
+            },
+        };
+        let query: Vec<String> = raw_query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                let key = pair.split('=').next().unwrap_or_default();
+                !matches!(key, "page" | "per_page" | "cursor")
+            })
+            .map(String::from)
+            .collect();
+        Ok(Pagination {
+            offset,
+            per_page,
+            path,
+            query,
+        })
+    }
+
+    pub fn next_cursor(&self, total: i64) -> Option<String> {
+        if self.offset + self.per_page < total {
+            Some(encode_cursor(self.offset + self.per_page, self.per_page))
+        } else {
+            None
+        }
+    }
+
+    pub fn prev_cursor(&self) -> Option<String> {
+        if self.offset > 0 {
+            Some(encode_cursor((self.offset - self.per_page).max(0), self.per_page))
+        } else {
+            None
+        }
+    }
+
+    fn link(&self, cursor: &str, rel: &str) -> String {
+        let mut query = self.query.clone();
+        query.push(format!("cursor={}", cursor));
+        format!("<{}?{}>; rel=\"{}\"", self.path, query.join("&"), rel)
+    }
+}
+
+fn encode_cursor(offset: i64, per_page: i64) -> String {
+    base64::encode_config(format!("{}:{}", offset, per_page), base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor(cursor: &str) -> Result<(i64, i64), ApiError> {
+    let invalid = || ApiError::ParsingError("cursor".to_string(), "Invalid cursor".to_string());
+    let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+    let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+    let (offset, per_page) = text.split_once(':').ok_or_else(invalid)?;
+    let offset = offset.parse::<i64>().map_err(|_| invalid())?;
+    let per_page = per_page.parse::<i64>().map_err(|_| invalid())?;
+    if offset < 0 || !(5..=100).contains(&per_page) {
+        return Err(invalid());
+    }
+    Ok((offset, per_page))
+}
+
+/// Envelope of every list response
+#[derive(Serialize)]
+pub struct Page<T: Serialize> {
+    pub data: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+impl<T: Serialize> Page<T> {
+    pub fn new(data: Vec<T>, total: i64, pagination: &Pagination) -> Self {
+        Page {
+            data,
+            total,
+            next_cursor: pagination.next_cursor(total),
+            prev_cursor: pagination.prev_cursor(),
+        }
+    }
+
+    pub fn into_reply(self, pagination: &Pagination) -> warp::reply::Response {
+        let mut links = vec![];
+        if let Some(cursor) = &self.next_cursor {
+            links.push(pagination.link(cursor, "next"));
+        }
+        if let Some(cursor) = &self.prev_cursor {
+            links.push(pagination.link(cursor, "prev"));
+        }
+        let mut res = warp::reply::json(&self).into_response();
+        if !links.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&links.join(", ")) {
+                res.headers_mut().insert(LINK, value);
+            }
+        }
+        res
+    }
+}
+
+pub fn with_pagination() -> impl Filter<Extract = (Pagination, ), Error = warp::Rejection> + Clone {
+    warp::query::<PaginationParams>()
+        .and(warp::path::full())
+        .and(
+            warp::query::raw()
+                .or(warp::any().map(String::new))
+                .unify()
+        )
+        .and_then(|params: PaginationParams, path: FullPath, raw_query: String| async move {
+            let mut req: PaginationRequest = PaginationRequest::default();
+            if let Some(page) = params.page {
+                let page = page.parse::<u32>().map_err(|e| {
+                    warp::reject::custom(
+                        ApiError::ParsingError("page".to_string(), e.to_string())
+                    )
+                })?;
+                req.page = Some(page);
+            }
+            if let Some(per_page) = params.per_page {
+                let per_page = per_page.parse::<u32>().map_err(|e| {
+                    warp::reject::custom(
+                        ApiError::ParsingError("per_page".to_string(), e.to_string())
+                    )
+                })?;
+                req.per_page = Some(per_page);
+            }
+            req.cursor = params.cursor;
+            if let Err(e) = req.validate() {
+                return Err(warp::reject::custom(
+                    ApiError::ValidationErrors(e)
+                ));
+            }
+            Pagination::from_request(req, path.as_str().to_string(), &raw_query)
+                .map_err(warp::reject::custom)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pagination(req: PaginationRequest, raw_query: &str) -> Pagination {
+        Pagination::from_request(req, "/api/v1/companies".to_string(), raw_query).unwrap()
+    }
+
+    #[test]
+    fn should_compute_offset_from_page() {
+        let p = pagination(PaginationRequest {
+            page: Some(3),
+            per_page: Some(10),
+            cursor: None,
+        }, "page=3&per_page=10");
+        assert_eq!(p.offset, 20);
+        assert_eq!(p.per_page, 10);
+    }
+
+    #[test]
+    fn should_default_to_first_page() {
+        let p = pagination(PaginationRequest::default(), "");
+        assert_eq!(p.offset, 0);
+        assert_eq!(p.per_page, DEFAULT_PER_PAGE as i64);
+        assert_eq!(p.prev_cursor(), None);
+    }
+
+    #[test]
+    fn should_round_trip_cursor() {
+        let p = pagination(PaginationRequest {
+            page: Some(2),
+            per_page: Some(10),
+            cursor: None,
+        }, "");
+        let next = pagination(PaginationRequest {
+            page: None,
+            per_page: None,
+            cursor: p.next_cursor(100),
+        }, "");
+        assert_eq!(next.offset, 20);
+        assert_eq!(next.per_page, 10);
+
+        let prev = pagination(PaginationRequest {
+            page: None,
+            per_page: None,
+            cursor: p.prev_cursor(),
+        }, "");
+        assert_eq!(prev.offset, 0);
+    }
+
+    #[test]
+    fn should_stop_at_last_page() {
+        let p = pagination(PaginationRequest {
+            page: Some(2),
+            per_page: Some(10),
+            cursor: None,
+        }, "");
+        assert_eq!(p.next_cursor(20), None);
+        assert!(p.next_cursor(21).is_some());
+    }
+
+    #[test]
+    fn should_reject_invalid_cursor() {
+        assert!(decode_cursor("not a cursor").is_err());
+        assert!(decode_cursor(&base64::encode_config("-5:10", base64::URL_SAFE_NO_PAD)).is_err());
+        assert!(decode_cursor(&base64::encode_config("0:1000", base64::URL_SAFE_NO_PAD)).is_err());
+    }
+
+    #[test]
+    fn should_keep_filters_in_link() {
+        let p = pagination(PaginationRequest {
+            page: Some(1),
+            per_page: Some(10),
+            cursor: None,
+        }, "search=acme&page=1&per_page=10");
+        let cursor = p.next_cursor(30).unwrap();
+        assert_eq!(
+            p.link(&cursor, "next"),
+            format!("</api/v1/companies?search=acme&cursor={}>; rel=\"next\"", cursor)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_extract_pagination_from_query() {
+        let p = warp::test::request()
+            .path("/api/v1/users?search=bob&page=2&per_page=5")
+            .filter(&with_pagination())
+            .await
+            .unwrap();
+        assert_eq!(p.offset, 5);
+        assert_eq!(p.per_page, 5);
+        assert_eq!(p.query, vec!["search=bob".to_string()]);
+
+        let rejected = warp::test::request()
+            .path("/api/v1/users?per_page=1000")
+            .filter(&with_pagination())
+            .await;
+        assert!(rejected.is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use crate::error_handler::ApiError;
+use crate::helpers::fetch_one;
+
+// every authorization rule lives here, routers only look up the facts
+// (current user, role in company) and ask this module
+// listing and showing are open to any signed in user, so they have no action
+
+/// Role of a user in a company, stored on `(:User)-[:MEMBER_OF {role}]->(:Company)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Guest,
+    Member,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Role> {
+        match value {
+            "guest" => Some(Role::Guest),
+            "member" => Some(Role::Member),
+            "admin" => Some(Role::Admin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompanyAction {
+    Update,
+    Trash,
+    Restore,
+    Erase,
+    ManageMembers,
+    ManageDepartments,
+}
+
+impl CompanyAction {
+    /// Maps `mode` of DeleteParams
+    pub fn from_delete_mode(mode: &str) -> CompanyAction {
+        match mode {
+            "trash" => CompanyAction::Trash,
+            "restore" => CompanyAction::Restore,
+            _ => CompanyAction::Erase,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            CompanyAction::Update => "update",
+            CompanyAction::Trash => "trash",
+            CompanyAction::Restore => "restore",
+            CompanyAction::Erase => "erase",
+            CompanyAction::ManageMembers => "manage members of",
+            CompanyAction::ManageDepartments => "manage departments of",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UserAction {
+    Update,
+    Delete,
+}
+
+/// Role of a user in one of their companies, next to the role of the current user in it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ManagedRole {
+    pub role: Role,
+    pub manager: Option<Role>,
+}
+
+pub fn can_company(role: Option<Role>, action: CompanyAction) -> bool {
+    match action {
+        CompanyAction::Update
+        | CompanyAction::Trash
+        | CompanyAction::Restore
+        | CompanyAction::ManageMembers
+        | CompanyAction::ManageDepartments => role >= Some(Role::Admin),
+        CompanyAction::Erase => role == Some(Role::Owner),
+    }
+}
+
+/// Granting or revoking a role needs at least that role, so only owners can touch owners
+pub fn can_grant(role: Option<Role>, target: Role) -> bool {
+    can_company(role, CompanyAction::ManageMembers) && role >= Some(target)
+}
+
+/// Users can change themselves, admins and owners can change members they outrank in every
+/// company the member belongs to, so that one company can't touch a user another company relies on
+pub fn can_user(current_user_id: i64, target_id: i64, roles: &[ManagedRole], action: UserAction) -> bool {
+    match action {
+        UserAction::Update | UserAction::Delete => {
+            current_user_id == target_id
+                || (!roles.is_empty() && roles.iter().all(|x| {
+                    can_company(x.manager, CompanyAction::ManageMembers) && x.manager > Some(x.role)
+                }))
+        },
+    }
+}
+
+pub fn authorize_company(role: Option<Role>, action: CompanyAction) -> Result<(), ApiError> {
+    if can_company(role, action) {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(format!("Not allowed to {} this company", action.describe())))
+    }
+}
+
+pub fn authorize_grant(role: Option<Role>, target: Role) -> Result<(), ApiError> {
+    if can_grant(role, target) {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(format!("Not allowed to manage {} members of this company", target.as_str())))
+    }
+}
+
+pub fn authorize_user(
+    current_user_id: i64,
+    target_id: i64,
+    roles: &[ManagedRole],
+    action: UserAction,
+) -> Result<(), ApiError> {
+    if can_user(current_user_id, target_id, roles, action) {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(format!("Not allowed to {:?} this user", action).to_lowercase()))
+    }
+}
+
+/// Role of the user in the company, or None if not a member at all
+pub async fn find_company_role(
+    graph: &neo4rs::Graph,
+    company_id: i64,
+    user_id: i64,
+) -> Result<Option<Role>, ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (c:Company)
+        WHERE id(c) = $company_id
+        OPTIONAL MATCH (u:User)-[m:MEMBER_OF]->(c)
+        WHERE id(u) = $user_id
+        RETURN m.role AS role
+    ")
+    .param("company_id", company_id)
+    .param("user_id", user_id);
+
+    let row: neo4rs::Row = fetch_one(graph, q, "Company").await?;
+    Ok(row.get::<String>("role").and_then(|role| Role::parse(&role)))
+}
+
+/// Companies of each target user with the role of the current user in them, keyed by target id
+/// users without any company are missing
+pub async fn find_managed_roles(
+    graph: &neo4rs::Graph,
+    current_user_id: i64,
+    target_ids: Vec<i64>,
+) -> Result<HashMap<i64, Vec<ManagedRole>>, ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)-[m:MEMBER_OF]->(c:Company)
+        WHERE id(u) IN $ids
+        OPTIONAL MATCH (me:User)-[n:MEMBER_OF]->(c)
+        WHERE id(me) = $user_id
+        RETURN id(u) AS id, m.role AS role, n.role AS manager
+    ")
+    .param("ids", target_ids)
+    .param("user_id", current_user_id);
+
+    let mut result: neo4rs::RowStream = graph.execute(q).await?;
+    let mut roles: HashMap<i64, Vec<ManagedRole>> = HashMap::new();
+    while let Some(row) = result.next().await? {
+        let id: i64 = row.get("id").unwrap();
+        roles.entry(id).or_default().push(ManagedRole {
+            // an unknown role is never outranked
+            role: row.get::<String>("role").and_then(|x| Role::parse(&x)).unwrap_or(Role::Owner),
+            manager: row.get::<String>("manager").and_then(|x| Role::parse(&x)),
+        });
+    }
+    Ok(roles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_let_only_admins_update_company() {
+        for action in [CompanyAction::Update, CompanyAction::Trash, CompanyAction::Restore, CompanyAction::ManageMembers, CompanyAction::ManageDepartments] {
+            assert!(!can_company(None, action));
+            assert!(!can_company(Some(Role::Guest), action));
+            assert!(!can_company(Some(Role::Member), action));
+            assert!(can_company(Some(Role::Admin), action));
+            assert!(can_company(Some(Role::Owner), action));
+        }
+    }
+
+    #[test]
+    fn should_let_only_owner_erase_company() {
+        assert!(!can_company(Some(Role::Admin), CompanyAction::Erase));
+        assert!(can_company(Some(Role::Owner), CompanyAction::Erase));
+    }
+
+    #[test]
+    fn should_grant_up_to_own_role() {
+        assert!(!can_grant(Some(Role::Member), Role::Guest));
+        assert!(can_grant(Some(Role::Admin), Role::Member));
+        assert!(can_grant(Some(Role::Admin), Role::Admin));
+        assert!(!can_grant(Some(Role::Admin), Role::Owner));
+        assert!(can_grant(Some(Role::Owner), Role::Owner));
+    }
+
+    #[test]
+    fn should_let_users_change_themselves() {
+        assert!(can_user(1, 1, &[], UserAction::Update));
+        assert!(!can_user(1, 2, &[], UserAction::Update));
+        assert!(can_user(1, 1, &[], UserAction::Delete));
+        assert!(!can_user(1, 2, &[], UserAction::Delete));
+    }
+
+    #[test]
+    fn should_let_admins_change_members_they_outrank_everywhere() {
+        let role = |role, manager| ManagedRole { role, manager };
+        for action in [UserAction::Update, UserAction::Delete] {
+            assert!(can_user(1, 2, &[role(Role::Member, Some(Role::Admin))], action));
+            assert!(can_user(1, 2, &[role(Role::Admin, Some(Role::Owner))], action));
+            assert!(!can_user(1, 2, &[role(Role::Admin, Some(Role::Admin))], action));
+            assert!(!can_user(1, 2, &[role(Role::Guest, Some(Role::Member))], action));
+            // the member also belongs to a company the current user doesn't run
+            assert!(!can_user(1, 2, &[role(Role::Member, Some(Role::Owner)), role(Role::Member, None)], action));
+        }
+    }
+
+    #[test]
+    fn should_deny_with_forbidden() {
+        assert!(matches!(
+            authorize_company(Some(Role::Member), CompanyAction::Update),
+            Err(ApiError::Forbidden(_))
+        ));
+        assert!(matches!(
+            authorize_user(1, 2, &[], UserAction::Update),
+            Err(ApiError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn should_parse_roles() {
+        for role in [Role::Guest, Role::Member, Role::Admin, Role::Owner] {
+            assert_eq!(Role::parse(role.as_str()), Some(role));
+        }
+        assert_eq!(Role::parse("root"), None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::convert::Infallible;
+use warp::{
+    http::{header::ETAG, StatusCode},
+    Filter, Reply,
+};
+
+// every company and user node carries a `version` counter that is bumped on each change
+// the counter is sent as a strong ETag, like `"3"`, and compared against If-Match and If-None-Match
+// If-Match uses the strong comparison, so a weak tag like `W/"3"` never matches there (RFC 9110 13.1.1)
+
+/// Entity tags listed in an If-Match or If-None-Match header
+#[derive(Clone, Debug, PartialEq)]
+pub enum Precondition {
+    Any,
+    Versions(Vec<i64>),
+}
+
+impl Precondition {
+    /// Tags that aren't one of our versions are dropped, they can never match anyway
+    /// weak tags are dropped too unless `weak` is set, which is only right for If-None-Match
+    pub fn parse(value: &str, weak: bool) -> Precondition {
+        if value.trim() == "*" {
+            return Precondition::Any;
+        }
+        let versions: Vec<i64> = value
+            .split(',')
+            .filter_map(|tag| {
+                let tag = tag.trim();
+                let tag = match tag.strip_prefix("W/") {
+                    Some(_) if !weak => return None,
+                    Some(x) => x,
+                    None => tag,
+                };
+                tag.strip_prefix('"')?.strip_suffix('"')?.parse::<i64>().ok()
+            })
+            .collect();
+        Precondition::Versions(versions)
+    }
+
+    pub fn matches(&self, version: i64) -> bool {
+        match self {
+            Precondition::Any => true,
+            Precondition::Versions(versions) => versions.contains(&version),
+        }
+    }
+
+    /// Versions a conditional write has to match, None if any version will do
+    pub fn versions(&self) -> Option<Vec<i64>> {
+        match self {
+            Precondition::Any => None,
+            Precondition::Versions(versions) => Some(versions.clone()),
+        }
+    }
+}
+
+pub fn with_if_match() -> impl Filter<Extract = (Option<Precondition>, ), Error = Infallible> + Clone {
+    with_precondition("if-match", false)
+}
+
+pub fn with_if_none_match() -> impl Filter<Extract = (Option<Precondition>, ), Error = Infallible> + Clone {
+    with_precondition("if-none-match", true)
+}
+
+fn with_precondition(
+    name: &'static str,
+    weak: bool,
+) -> impl Filter<Extract = (Option<Precondition>, ), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(move |headers: warp::http::HeaderMap| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| Precondition::parse(value, weak))
+    })
+}
+
+pub fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+pub fn with_etag(reply: impl Reply, version: i64) -> warp::reply::Response {
+    warp::reply::with_header(reply, ETAG, etag(version)).into_response()
+}
+
+pub fn not_modified(version: i64) -> warp::reply::Response {
+    with_etag(StatusCode::NOT_MODIFIED, version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_entity_tags() {
+        assert_eq!(Precondition::parse("*", false), Precondition::Any);
+        assert_eq!(Precondition::parse("\"3\"", false), Precondition::Versions(vec![3]));
+        assert_eq!(Precondition::parse("W/\"3\", \"5\", \"abc\", 7", true), Precondition::Versions(vec![3, 5]));
+    }
+
+    #[test]
+    fn should_ignore_weak_tags_for_strong_comparison() {
+        assert_eq!(Precondition::parse("W/\"3\", \"5\"", false), Precondition::Versions(vec![5]));
+        assert!(!Precondition::parse("W/\"3\"", false).matches(3));
+        assert!(Precondition::parse("W/\"3\"", true).matches(3));
+    }
+
+    #[test]
+    fn should_match_versions() {
+        assert!(Precondition::Any.matches(9));
+        assert!(Precondition::parse("\"1\", \"2\"", false).matches(2));
+        assert!(!Precondition::parse("\"1\"", false).matches(2));
+        assert!(!Precondition::parse("garbage", false).matches(0));
+    }
+
+    #[tokio::test]
+    async fn should_read_if_match_header() {
+        let precondition = warp::test::request()
+            .header("if-match", "\"4\"")
+            .filter(&with_if_match())
+            .await
+            .unwrap();
+        assert_eq!(precondition, Some(Precondition::Versions(vec![4])));
+
+        let precondition = warp::test::request()
+            .filter(&with_if_match())
+            .await
+            .unwrap();
+        assert_eq!(precondition, None);
+
+        let precondition = warp::test::request()
+            .header("if-match", "W/\"4\"")
+            .filter(&with_if_match())
+            .await
+            .unwrap();
+        assert_eq!(precondition, Some(Precondition::Versions(vec![])));
+
+        let precondition = warp::test::request()
+            .header("if-none-match", "W/\"4\"")
+            .filter(&with_if_none_match())
+            .await
+            .unwrap();
+        assert_eq!(precondition, Some(Precondition::Versions(vec![4])));
+    }
+
+    #[test]
+    fn should_reply_not_modified_with_etag() {
+        let response = not_modified(4);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[ETAG], "\"4\"");
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::error_handler::ApiError;
+use crate::helpers::fetch_one;
+use crate::storage::Storage;
+use crate::user::remove_user_files;
+
+// trashed records are erased for good once they have stayed in the trash for the retention period
+// same as `erase` mode of delete, departments go with their company and users take their files
+
+/// Runs the purge every `interval` for as long as the server is up
+pub fn spawn(graph: Arc<neo4rs::Graph>, storage: Arc<dyn Storage>, retention_days: i64, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match purge(&graph, storage.as_ref(), cutoff(Utc::now(), retention_days)).await {
+                Ok((0, 0)) => {},
+                Ok((companies, users)) => {
+                    tracing::info!(companies, users, "Purged the trash");
+                },
+                Err(e) => tracing::error!(error = %e, "Failed to purge the trash"),
+            }
+        }
+    });
+}
+
+/// Records trashed before this have stayed in the trash for the whole retention period
+fn cutoff(now: DateTime<Utc>, retention_days: i64) -> DateTime<FixedOffset> {
+    (now - chrono::Duration::days(retention_days)).into()
+}
+
+/// Erases what was trashed before `cutoff`, returns number of erased companies and users
+pub async fn purge(
+    graph: &neo4rs::Graph,
+    storage: &dyn Storage,
+    cutoff: DateTime<FixedOffset>,
+) -> Result<(i64, i64), ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (c:Company)
+        WHERE c.deletedAt < $cutoff
+        OPTIONAL MATCH (d:Department)-[:PART_OF*1..]->(c)
+        DETACH DELETE d, c
+        RETURN count(DISTINCT c) AS count
+    ")
+    .param("cutoff", cutoff);
+    let row: neo4rs::Row = fetch_one(graph, q, "Company").await?;
+    let companies: i64 = row.get("count").unwrap_or(0);
+
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)
+        WHERE u.deletedAt < $cutoff
+        OPTIONAL MATCH (t:RefreshToken)-[:ISSUED_TO]->(u)
+        WITH u, id(u) AS id, collect(t) AS tokens
+        FOREACH (t IN tokens | DETACH DELETE t)
+        DETACH DELETE u
+        RETURN id
+    ")
+    .param("cutoff", cutoff);
+    let mut result: neo4rs::RowStream = graph.execute(q).await?;
+    let mut ids: Vec<i64> = vec![];
+    while let Some(row) = result.next().await? {
+        ids.push(row.get("id").unwrap());
+    }
+    // nodes are gone already, so files that can't be removed are only logged
+    for id in ids.iter() {
+        if let Err(e) = remove_user_files(storage, *id).await {
+            tracing::error!(user_id = id, error = %e, "Failed to remove storage");
+        }
+    }
+    Ok((companies, ids.len() as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::LocalStorage;
+    use bytes::Bytes;
+    use chrono::TimeZone;
+    use neo4rs::testing::{Expect, Reply, Script, StubServer};
+    use crate::test_support::connect;
+
+    const PURGE_COMPANIES: &str = "
+        MATCH (c:Company)
+        WHERE c.deletedAt < $cutoff
+        OPTIONAL MATCH (d:Department)-[:PART_OF*1..]->(c)
+        DETACH DELETE d, c
+        RETURN count(DISTINCT c) AS count
+    ";
+
+    const PURGE_USERS: &str = "
+        MATCH (u:User)
+        WHERE u.deletedAt < $cutoff
+        OPTIONAL MATCH (t:RefreshToken)-[:ISSUED_TO]->(u)
+        WITH u, id(u) AS id, collect(t) AS tokens
+        FOREACH (t IN tokens | DETACH DELETE t)
+        DETACH DELETE u
+        RETURN id
+    ";
+
+    fn at(value: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
+
+    #[test]
+    fn should_keep_records_trashed_within_retention() {
+        let now = Utc.with_ymd_and_hms(2021, 6, 30, 12, 0, 0).unwrap();
+        let cutoff = cutoff(now, 30);
+        assert_eq!(cutoff, at("2021-05-31T12:00:00+00:00"));
+        // queries only take what was trashed before the cutoff
+        assert!(at("2021-06-01T12:00:00+00:00") >= cutoff);
+        assert!(at("2021-05-31T12:00:00+00:00") >= cutoff);
+        assert!(at("2021-05-31T11:59:59+00:00") < cutoff);
+    }
+
+    #[tokio::test]
+    async fn should_remove_files_of_purged_users_only() {
+        let cutoff = at("2021-05-31T12:00:00+00:00");
+        let script = Script::new()
+            .expect(Expect::run(PURGE_COMPANIES).param("cutoff", cutoff))
+            .returns(&["count"], vec![vec![2i64.into()]])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            // user 8 was trashed after the cutoff, so the database doesn't return it
+            .expect(Expect::run(PURGE_USERS).param("cutoff", cutoff))
+            .returns(&["id"], vec![vec![7i64.into()]]);
+        let server = StubServer::start(script).await;
+        let graph = connect(&server).await;
+        let root = std::env::temp_dir().join(format!("groupware-purge-{}", std::process::id()));
+        let storage = LocalStorage::new(root.clone(), "/storage", b"secret");
+        for key in ["7/avatar.png", "7/avatar_64.png", "8/avatar.png"] {
+            storage.put(key, Bytes::from_static(b"png"), "image/png").await.unwrap();
+        }
+
+        assert_eq!(purge(&graph, &storage, cutoff).await.unwrap(), (2, 1));
+        assert_eq!(storage.list("").await.unwrap(), vec!["8/avatar.png".to_string()]);
+        server.finish();
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+}
@@ -0,0 +1,402 @@
+use chrono::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::precondition::Precondition;
+use crate::sorting::SortKey;
+
+// every value supplied by a client is kept here and bound through neo4rs::Query::param
+// so that it never becomes part of the cypher text
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryValue {
+    Integer(i64),
+    String(String),
+    Date(NaiveDate),
+    Integers(Vec<i64>),
+}
+
+impl From<i64> for QueryValue {
+    fn from(value: i64) -> Self {
+        QueryValue::Integer(value)
+    }
+}
+
+impl From<String> for QueryValue {
+    fn from(value: String) -> Self {
+        QueryValue::String(value)
+    }
+}
+
+impl From<&str> for QueryValue {
+    fn from(value: &str) -> Self {
+        QueryValue::String(value.to_string())
+    }
+}
+
+impl From<NaiveDate> for QueryValue {
+    fn from(value: NaiveDate) -> Self {
+        QueryValue::Date(value)
+    }
+}
+
+impl From<Vec<i64>> for QueryValue {
+    fn from(value: Vec<i64>) -> Self {
+        QueryValue::Integers(value)
+    }
+}
+
+/// Which soft deleted nodes a query should see, by `deletedAt`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trashed {
+    Without,
+    Only,
+    With,
+}
+
+impl Trashed {
+    pub fn parse(value: &str) -> Option<Trashed> {
+        match value {
+            "only" => Some(Trashed::Only),
+            "with" => Some(Trashed::With),
+            _ => None,
+        }
+    }
+}
+
+/// Assembles a cypher query for a single node variable, like `(c:Company)`.
+///
+/// Clauses are emitted in the fixed order MATCH, WHERE, SET, REMOVE, RETURN, ORDER BY, SKIP, LIMIT
+/// regardless of the order the builder methods were called in.
+#[derive(Clone, Debug)]
+pub struct QueryBuilder {
+    var: String,
+    label: String,
+    conditions: Vec<String>,
+    assignments: Vec<String>,
+    removals: Vec<String>,
+    returns: bool,
+    order_by: Vec<String>,
+    skip: Option<i64>,
+    limit: Option<i64>,
+    params: BTreeMap<String, QueryValue>,
+}
+
+impl QueryBuilder {
+    pub fn new(var: &str, label: &str) -> Self {
+        QueryBuilder {
+            var: var.to_string(),
+            label: label.to_string(),
+            conditions: vec![],
+            assignments: vec![],
+            removals: vec![],
+            returns: false,
+            order_by: vec![],
+            skip: None,
+            limit: None,
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// WHERE id(c) = $id
+    pub fn where_id(mut self, id: i64) -> Self {
+        self.conditions.push(format!("id({}) = $id", self.var));
+        self.params.insert("id".to_string(), id.into());
+        self
+    }
+
+    /// WHERE (c.name CONTAINS $search OR c.email CONTAINS $search)
+    pub fn where_contains(mut self, props: &[&str], search: &str) -> Self {
+        let terms: Vec<String> = props
+            .iter()
+            .map(|prop| format!("{}.{} CONTAINS $search", self.var, prop))
+            .collect();
+        self.conditions.push(format!("({})", terms.join(" OR ")));
+        self.params.insert("search".to_string(), search.into());
+        self
+    }
+
+    /// WHERE c.deletedAt IS NULL, or IS NOT NULL for the trash
+    pub fn trashed(mut self, trashed: Trashed) -> Self {
+        match trashed {
+            Trashed::Without => self.conditions.push(format!("{}.deletedAt IS NULL", self.var)),
+            Trashed::Only => self.conditions.push(format!("{}.deletedAt IS NOT NULL", self.var)),
+            Trashed::With => {},
+        }
+        self
+    }
+
+    /// WHERE coalesce(c.version, 0) IN $versions, nodes created before versioning count as 0
+    pub fn where_version(mut self, versions: Vec<i64>) -> Self {
+        self.conditions.push(format!("coalesce({}.version, 0) IN $versions", self.var));
+        self.params.insert("versions".to_string(), versions.into());
+        self
+    }
+
+    /// where_version for the tags of If-Match, nothing for `*` or a missing header
+    pub fn where_precondition(self, precondition: &Option<Precondition>) -> Self {
+        match precondition.as_ref().and_then(Precondition::versions) {
+            Some(versions) => self.where_version(versions),
+            None => self,
+        }
+    }
+
+    /// SET c.name = $set_name, prefixed so it can't clash with $id, $search or $versions
+    pub fn set<T: Into<QueryValue>>(mut self, prop: &str, value: T) -> Self {
+        self.assignments.push(format!("{}.{} = $set_{}", self.var, prop, prop));
+        self.params.insert(format!("set_{}", prop), value.into());
+        self
+    }
+
+    /// SET c.since = date($set_since)
+    pub fn set_with<T: Into<QueryValue>>(mut self, prop: &str, function: &str, value: T) -> Self {
+        self.assignments.push(format!("{}.{} = {}($set_{})", self.var, prop, function, prop));
+        self.params.insert(format!("set_{}", prop), value.into());
+        self
+    }
+
+    /// SET c.updatedAt = datetime()
+    pub fn set_now(mut self, prop: &str) -> Self {
+        self.assignments.push(format!("{}.{} = datetime()", self.var, prop));
+        self
+    }
+
+    /// SET c.version = coalesce(c.version, 0) + 1
+    pub fn bump_version(mut self) -> Self {
+        self.assignments.push(format!("{0}.version = coalesce({0}.version, 0) + 1", self.var));
+        self
+    }
+
+    /// REMOVE c.deletedAt
+    pub fn remove(mut self, prop: &str) -> Self {
+        self.removals.push(format!("{}.{}", self.var, prop));
+        self
+    }
+
+    pub fn returns(mut self) -> Self {
+        self.returns = true;
+        self
+    }
+
+    /// Sort keys can't be bound as parameters, so anything not in `allowed` is dropped
+    pub fn order_by(mut self, keys: &[SortKey], allowed: &[&str]) -> Self {
+        for key in keys {
+            if allowed.contains(&key.prop.as_str()) {
+                let direction = if key.descending { "DESC" } else { "ASC" };
+                self.order_by.push(format!("{}.{} {}", self.var, key.prop, direction));
+            }
+        }
+        self
+    }
+
+    pub fn skip(mut self, skip: i64) -> Self {
+        self.skip = Some(skip);
+        self.params.insert("skip".to_string(), skip.into());
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self.params.insert("limit".to_string(), limit.into());
+        self
+    }
+
+    fn match_terms(&self) -> Vec<String> {
+        let mut terms = vec![format!("MATCH ({}:{})", self.var, self.label)];
+        if !self.conditions.is_empty() {
+            terms.push(format!("WHERE {}", self.conditions.join(" AND ")));
+        }
+        terms
+    }
+
+    pub fn text(&self) -> String {
+        let mut terms = self.match_terms();
+        if !self.assignments.is_empty() {
+            terms.push(format!("SET {}", self.assignments.join(", ")));
+        }
+        if !self.removals.is_empty() {
+            terms.push(format!("REMOVE {}", self.removals.join(", ")));
+        }
+        if self.returns {
+            terms.push(format!("RETURN {}", self.var));
+        }
+        if !self.order_by.is_empty() {
+            terms.push(format!("ORDER BY {}", self.order_by.join(", ")));
+        }
+        if self.skip.is_some() {
+            terms.push("SKIP $skip".to_string());
+        }
+        if self.limit.is_some() {
+            terms.push("LIMIT $limit".to_string());
+        }
+        terms.join(" ")
+    }
+
+    /// MATCH and WHERE of this query followed by `RETURN count(c) AS total`
+    pub fn count_text(&self) -> String {
+        let mut terms = self.match_terms();
+        terms.push(format!("RETURN count({}) AS total", self.var));
+        terms.join(" ")
+    }
+
+    pub fn params(&self) -> &BTreeMap<String, QueryValue> {
+        &self.params
+    }
+
+    pub fn build(&self) -> neo4rs::Query {
+        bind(neo4rs::query(&self.text()), self.params().iter())
+    }
+
+    /// Counts every node matching the conditions, regardless of SKIP and LIMIT
+    pub fn build_count(&self) -> neo4rs::Query {
+        let params = self.params()
+            .iter()
+            .filter(|(key, _)| key.as_str() != "skip" && key.as_str() != "limit");
+        bind(neo4rs::query(&self.count_text()), params)
+    }
+}
+
+fn bind<'a>(
+    mut q: neo4rs::Query,
+    params: impl Iterator<Item = (&'a String, &'a QueryValue)>,
+) -> neo4rs::Query {
+    for (key, value) in params {
+        q = match value.clone() {
+            QueryValue::Integer(x) => q.param(key, x),
+            QueryValue::String(x) => q.param(key, x),
+            QueryValue::Date(x) => q.param(key, x),
+            QueryValue::Integers(x) => q.param(key, x),
+        };
+    }
+    q
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE: &str = "' OR 1=1 //";
+
+    #[test]
+    fn should_bind_search_as_param() {
+        let builder = QueryBuilder::new("u", "User")
+            .where_contains(&["name", "email"], HOSTILE)
+            .returns()
+            .limit(10);
+
+        assert_eq!(
+            builder.text(),
+            "MATCH (u:User) WHERE (u.name CONTAINS $search OR u.email CONTAINS $search) RETURN u LIMIT $limit"
+        );
+        assert_eq!(builder.params().len(), 2);
+        assert_eq!(builder.params()["search"], QueryValue::String(HOSTILE.to_string()));
+        assert_eq!(builder.params()["limit"], QueryValue::Integer(10));
+    }
+
+    #[test]
+    fn should_bind_assignments_as_params() {
+        let since = NaiveDate::from_ymd_opt(2020, 2, 29).unwrap();
+        let builder = QueryBuilder::new("c", "Company")
+            .where_id(7)
+            .set("name", HOSTILE)
+            .set_with("since", "date", since)
+            .set_now("updatedAt")
+            .returns();
+
+        assert_eq!(
+            builder.text(),
+            "MATCH (c:Company) WHERE id(c) = $id SET c.name = $set_name, c.since = date($set_since), c.updatedAt = datetime() RETURN c"
+        );
+        assert_eq!(builder.params().len(), 3);
+        assert_eq!(builder.params()["id"], QueryValue::Integer(7));
+        assert_eq!(builder.params()["set_name"], QueryValue::String(HOSTILE.to_string()));
+        assert_eq!(builder.params()["set_since"], QueryValue::Date(since));
+    }
+
+    #[test]
+    fn should_keep_assignment_apart_from_search() {
+        let builder = QueryBuilder::new("u", "User")
+            .where_contains(&["name"], "jane")
+            .set("search", "john")
+            .returns();
+
+        assert_eq!(
+            builder.text(),
+            "MATCH (u:User) WHERE (u.name CONTAINS $search) SET u.search = $set_search RETURN u"
+        );
+        assert_eq!(builder.params()["search"], QueryValue::String("jane".to_string()));
+        assert_eq!(builder.params()["set_search"], QueryValue::String("john".to_string()));
+    }
+
+    fn sort_key(prop: &str, descending: bool) -> SortKey {
+        SortKey {
+            prop: prop.to_string(),
+            descending,
+        }
+    }
+
+    #[test]
+    fn should_drop_sort_key_not_in_whitelist() {
+        let builder = QueryBuilder::new("c", "Company")
+            .returns()
+            .order_by(&[sort_key(HOSTILE, false), sort_key("name", false)], &["name"])
+            .skip(0)
+            .limit(5);
+
+        assert_eq!(
+            builder.text(),
+            "MATCH (c:Company) RETURN c ORDER BY c.name ASC SKIP $skip LIMIT $limit"
+        );
+        assert!(!builder.text().contains(HOSTILE));
+    }
+
+    #[test]
+    fn should_order_by_multiple_keys() {
+        let builder = QueryBuilder::new("u", "User")
+            .returns()
+            .order_by(&[sort_key("createdAt", true), sort_key("name", false)], &["name", "email", "createdAt"]);
+
+        assert_eq!(
+            builder.text(),
+            "MATCH (u:User) RETURN u ORDER BY u.createdAt DESC, u.name ASC"
+        );
+    }
+
+    #[test]
+    fn should_filter_trashed_nodes() {
+        let text = |trashed| QueryBuilder::new("c", "Company").where_id(1).trashed(trashed).returns().text();
+
+        assert_eq!(text(Trashed::Without), "MATCH (c:Company) WHERE id(c) = $id AND c.deletedAt IS NULL RETURN c");
+        assert_eq!(text(Trashed::Only), "MATCH (c:Company) WHERE id(c) = $id AND c.deletedAt IS NOT NULL RETURN c");
+        assert_eq!(text(Trashed::With), "MATCH (c:Company) WHERE id(c) = $id RETURN c");
+    }
+
+    #[test]
+    fn should_match_and_bump_version() {
+        let builder = QueryBuilder::new("c", "Company")
+            .where_id(1)
+            .where_version(vec![3])
+            .bump_version()
+            .returns();
+
+        assert_eq!(
+            builder.text(),
+            "MATCH (c:Company) WHERE id(c) = $id AND coalesce(c.version, 0) IN $versions SET c.version = coalesce(c.version, 0) + 1 RETURN c"
+        );
+        assert_eq!(builder.params()["versions"], QueryValue::Integers(vec![3]));
+    }
+
+    #[test]
+    fn should_count_without_window() {
+        let builder = QueryBuilder::new("u", "User")
+            .where_contains(&["name", "email"], HOSTILE)
+            .returns()
+            .order_by(&[sort_key("name", false)], &["name"])
+            .skip(20)
+            .limit(10);
+
+        assert_eq!(
+            builder.count_text(),
+            "MATCH (u:User) WHERE (u.name CONTAINS $search OR u.email CONTAINS $search) RETURN count(u) AS total"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use regex::Regex;
+
+/// One key of `sort_by`, like `-createdAt`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SortKey {
+    pub prop: String,
+    pub descending: bool,
+}
+
+/// Matches a comma-separated list of the allowed keys, each optionally prefixed with `-`
+pub fn sort_by_regex(allowed: &[&str]) -> Regex {
+    let keys: Vec<String> = allowed.iter().map(|key| regex::escape(key)).collect();
+    Regex::new(&format!(r"^-?({0})(,-?({0}))*$", keys.join("|"))).unwrap()
+}
+
+/// Parses `-createdAt,name` into sort keys, a `-` prefix meaning descending order
+pub fn parse_sort_by(value: &str, allowed: &[&str]) -> Result<Vec<SortKey>, String> {
+    value
+        .split(',')
+        .map(|term| {
+            let (prop, descending) = match term.strip_prefix('-') {
+                Some(prop) => (prop, true),
+                None => (term, false),
+            };
+            if allowed.contains(&prop) {
+                Ok(SortKey {
+                    prop: prop.to_string(),
+                    descending,
+                })
+            } else {
+                Err(format!("Must be one of {}", allowed.join(", ")))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALLOWED: &[&str] = &["name", "since", "createdAt"];
+
+    #[test]
+    fn should_parse_keys_with_direction() {
+        assert_eq!(
+            parse_sort_by("-createdAt,name", ALLOWED).unwrap(),
+            vec![
+                SortKey {
+                    prop: "createdAt".to_string(),
+                    descending: true,
+                },
+                SortKey {
+                    prop: "name".to_string(),
+                    descending: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reject_unknown_key() {
+        assert!(parse_sort_by("namexyz", ALLOWED).is_err());
+        assert!(parse_sort_by("name,", ALLOWED).is_err());
+        assert!(parse_sort_by("--name", ALLOWED).is_err());
+        assert!(parse_sort_by("name DESC", ALLOWED).is_err());
+    }
+
+    #[test]
+    fn should_match_exact_keys_only() {
+        let regex = sort_by_regex(ALLOWED);
+        assert!(regex.is_match("name"));
+        assert!(regex.is_match("-createdAt,name"));
+        assert!(regex.is_match("since,-name,createdAt"));
+        assert!(!regex.is_match("namexyz"));
+        assert!(!regex.is_match("xname"));
+        assert!(!regex.is_match("name,"));
+        assert!(!regex.is_match("email"));
+        assert!(!regex.is_match("name ASC"));
+    }
+}
@@ -0,0 +1,196 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use path_slash::{PathBufExt, PathExt};
+use sha2::Sha256;
+use std::{
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::error_handler::ApiError;
+use crate::storage::{check_key, Storage};
+
+/// Files under a directory of this machine, keys are relative paths
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+    secret: Vec<u8>,
+}
+
+impl LocalStorage {
+    /// Presigned URLs start with `public_url` and are signed with `secret`
+    pub fn new(root: PathBuf, public_url: &str, secret: &[u8]) -> LocalStorage {
+        LocalStorage {
+            root,
+            public_url: public_url.trim_end_matches('/').to_string(),
+            secret: secret.to_vec(),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, ApiError> {
+        check_key(key)?;
+        Ok(self.root.join(PathBuf::from_slash(key)))
+    }
+
+    /// HMAC of the key and the expiry, hex encoded
+    fn signature(&self, key: &str, expires: u64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(format!("{}\n{}", key, expires).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Directories left empty by a delete or a move are removed, up to the root
+    async fn remove_empty_parents(&self, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(x) = dir {
+            if x == self.root || !x.starts_with(&self.root) || tokio::fs::remove_dir(x).await.is_err() {
+                break;
+            }
+            dir = x.parent();
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Bytes, _content_type: &str) -> Result<(), ApiError> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Bytes, ApiError> {
+        let data = tokio::fs::read(self.path(key)?).await?;
+        Ok(Bytes::from(data))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), ApiError> {
+        let path = self.path(key)?;
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(ApiError::Storage(e)),
+            _ => {
+                self.remove_empty_parents(&path).await;
+                Ok(())
+            },
+        }
+    }
+
+    async fn move_to(&self, from: &str, to: &str) -> Result<(), ApiError> {
+        let from = self.path(from)?;
+        let to = self.path(to)?;
+        if let Some(dir) = to.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::rename(&from, &to).await?;
+        self.remove_empty_parents(&from).await;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, ApiError> {
+        // only the directory the prefix points into has to be walked
+        let dir: &str = prefix.rsplit_once('/').map(|(dir, _)| dir).unwrap_or_default();
+        let start: PathBuf = if dir.is_empty() { self.root.clone() } else { self.path(dir)? };
+
+        let mut keys: Vec<String> = vec![];
+        let mut pending: Vec<PathBuf> = vec![start];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(x) => x,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(ApiError::Storage(e)),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let key = path.strip_prefix(&self.root).unwrap().to_slash_lossy();
+                if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn presign(&self, key: &str, expires_in: Duration) -> Result<String, ApiError> {
+        check_key(key)?;
+        let expires: u64 = (SystemTime::now() + expires_in)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        Ok(format!(
+            "{}/{}?expires={}&signature={}",
+            self.public_url,
+            key,
+            expires,
+            self.signature(key, expires),
+        ))
+    }
+
+    async fn check(&self) -> Result<(), ApiError> {
+        let metadata = tokio::fs::metadata(&self.root).await?;
+        if !metadata.is_dir() {
+            return Err(ApiError::Storage(Error::other("Not a directory")));
+        }
+        if metadata.permissions().readonly() {
+            return Err(ApiError::Storage(Error::new(ErrorKind::PermissionDenied, "Not writable")));
+        }
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        self.root.display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_storage(name: &str) -> LocalStorage {
+        let root = std::env::temp_dir().join(format!("groupware-storage-{}-{}", name, std::process::id()));
+        LocalStorage::new(root, "/storage/", b"secret")
+    }
+
+    #[tokio::test]
+    async fn should_put_move_list_and_delete() {
+        let storage = temp_storage("roundtrip");
+        storage.put("tmp/a.png", Bytes::from_static(b"png"), "image/png").await.unwrap();
+        storage.move_to("tmp/a.png", "42/a.png").await.unwrap();
+
+        assert_eq!(storage.get("42/a.png").await.unwrap(), Bytes::from_static(b"png"));
+        assert_eq!(storage.list("42/").await.unwrap(), vec!["42/a.png".to_string()]);
+        assert!(storage.list("tmp/").await.unwrap().is_empty());
+
+        storage.delete("42/a.png").await.unwrap();
+        storage.delete("42/a.png").await.unwrap();
+        assert!(!storage.root.join("42").exists());
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_report_missing_file_as_not_found() {
+        let storage = temp_storage("missing");
+        match storage.get("42/none.png").await {
+            Err(ApiError::Storage(e)) => assert_eq!(e.kind(), ErrorKind::NotFound),
+            _ => panic!("expected a storage error"),
+        }
+        assert!(storage.check().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_sign_presigned_url() {
+        let storage = temp_storage("presign");
+        let url = storage.presign("42/a.png", Duration::from_secs(60)).await.unwrap();
+        assert!(url.starts_with("/storage/42/a.png?expires="));
+        let expires: u64 = url.split("expires=").nth(1).unwrap().split('&').next().unwrap().parse().unwrap();
+        assert!(url.ends_with(&format!("&signature={}", storage.signature("42/a.png", expires))));
+    }
+}
@@ -0,0 +1,98 @@
+mod local;
+mod s3;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::Duration,
+};
+use warp::Filter;
+
+use crate::config::Settings;
+use crate::error_handler::ApiError;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+// files are addressed by keys like `42/avatar.png`, relative to the root directory or the bucket
+// a key never starts with `/` and never contains `..`
+// a missing file is reported as `ApiError::Storage` with `io::ErrorKind::NotFound`
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> Result<(), ApiError>;
+
+    async fn get(&self, key: &str) -> Result<Bytes, ApiError>;
+
+    /// Deleting a file that doesn't exist is not an error
+    async fn delete(&self, key: &str) -> Result<(), ApiError>;
+
+    async fn move_to(&self, from: &str, to: &str) -> Result<(), ApiError>;
+
+    /// Keys starting with `prefix`, in no particular order
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, ApiError>;
+
+    /// A URL that lets anyone download the file until it expires
+    // avatars are served by the app for now, see `avatar::init`
+    #[allow(dead_code)]
+    async fn presign(&self, key: &str, expires_in: Duration) -> Result<String, ApiError>;
+
+    /// Fails when files can't be stored right now, used by the readiness probe
+    async fn check(&self) -> Result<(), ApiError>;
+
+    /// Where the files go, for logs and the readiness probe
+    fn location(&self) -> String;
+}
+
+/// Backend selected by `storage.backend`
+pub fn init(settings: &Settings) -> Arc<dyn Storage> {
+    match settings.storage.backend.as_str() {
+        "s3" => Arc::new(S3Storage::new(&settings.storage.s3)),
+        _ => Arc::new(LocalStorage::new(
+            settings.storage.root.clone(),
+            &settings.storage.public_url,
+            settings.auth.jwt_secret.as_bytes(),
+        )),
+    }
+}
+
+pub fn with_storage(
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = (Arc<dyn Storage>, ), Error = Infallible> + Clone {
+    warp::any().map(move || {
+        storage.clone()
+    })
+}
+
+/// Removes every file under `prefix`, like the directory of a user
+pub async fn delete_all(storage: &dyn Storage, prefix: &str) -> Result<(), ApiError> {
+    for key in storage.list(prefix).await? {
+        storage.delete(&key).await?;
+    }
+    Ok(())
+}
+
+fn check_key(key: &str) -> Result<(), ApiError> {
+    if key.is_empty() || key.starts_with('/') || key.split('/').any(|x| x == ".." || x == ".") {
+        return Err(ApiError::Storage(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid key {:?}", key),
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reject_keys_escaping_the_root() {
+        assert!(check_key("42/avatar.png").is_ok());
+        assert!(check_key("").is_err());
+        assert!(check_key("/etc/passwd").is_err());
+        assert!(check_key("42/../../etc/passwd").is_err());
+    }
+}
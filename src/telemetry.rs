@@ -0,0 +1,143 @@
+use hyper::{
+    header::HeaderValue,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn, Service},
+    Body, Request, Response, Server,
+};
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    time::Instant,
+};
+use tracing::Instrument;
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+use warp::{Filter, Reply};
+
+use crate::metrics;
+
+// logs are written to stdout as one JSON object per line
+// every request runs inside a `request` span, so controller and cypher spans below it carry the same request_id
+// spans log their busy and idle time when they close
+
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// Installs the JSON subscriber, `log` records of the driver are forwarded to it
+pub fn init(level: &str) {
+    tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_env_filter(EnvFilter::new(level))
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+}
+
+/// The id sent by the client if it is safe to log and to echo, a new one otherwise
+pub fn request_id(value: Option<&HeaderValue>) -> String {
+    value
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 128
+                && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Same as `warp::serve(..).bind_with_graceful_shutdown(..)`, and logs every request with its id and latency
+pub async fn serve<F>(
+    filter: F,
+    addr: SocketAddr,
+    signal: impl Future<Output = ()>,
+) -> Result<(), hyper::Error>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let service = warp::service(filter);
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let remote_addr: SocketAddr = conn.remote_addr();
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                handle(service.clone(), remote_addr, req)
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    tracing::info!(%addr, "Listening");
+    server.with_graceful_shutdown(signal).await
+}
+
+async fn handle<S>(
+    mut service: S,
+    remote_addr: SocketAddr,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    let id: String = request_id(req.headers().get(REQUEST_ID));
+    let value = HeaderValue::from_str(&id).unwrap();
+    // handlers see the same id as the logs, even when the client didn't send one
+    req.headers_mut().insert(REQUEST_ID, value.clone());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %req.uri().path(),
+        remote_addr = %remote_addr,
+    );
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let started = Instant::now();
+    let mut response: Response<Body> = service.call(req).instrument(span.clone()).await?;
+    metrics::record_request(&method, &path, response.status().as_u16(), started.elapsed());
+    span.in_scope(|| {
+        tracing::info!(
+            status = response.status().as_u16(),
+            latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+            "Request finished",
+        );
+    });
+    response.headers_mut().insert(REQUEST_ID, value);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_safe_request_id() {
+        let value = HeaderValue::from_static("abc-123_x.y:z");
+        assert_eq!(request_id(Some(&value)), "abc-123_x.y:z");
+    }
+
+    #[test]
+    fn should_replace_missing_or_unsafe_request_id() {
+        let value = HeaderValue::from_static("abc\" injected=1");
+        let id = request_id(Some(&value));
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+        assert!(uuid::Uuid::parse_str(&request_id(None)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_echo_request_id() {
+        let service = warp::service(warp::path("ping").map(warp::reply));
+        let req = Request::builder()
+            .uri("/ping")
+            .header(REQUEST_ID, "client-id")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle(service, ([127, 0, 0, 1], 1000).into(), req).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID], "client-id");
+
+        let req = Request::builder().uri("/missing").body(Body::empty()).unwrap();
+        let response = handle(service, ([127, 0, 0, 1], 1000).into(), req).await.unwrap();
+        assert_eq!(response.status(), 404);
+        assert!(uuid::Uuid::parse_str(response.headers()[REQUEST_ID].to_str().unwrap()).is_ok());
+    }
+}
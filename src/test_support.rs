@@ -0,0 +1,26 @@
+use chrono::DateTime;
+use neo4rs::testing::StubServer;
+use std::sync::Arc;
+
+use crate::user::UserResponse;
+
+/// Pool talking to the stub server, connections are only opened on the first query
+pub async fn connect(server: &StubServer) -> Arc<neo4rs::Graph> {
+    Arc::new(neo4rs::Graph::new(server.uri(), "neo4j", "secret").await.unwrap())
+}
+
+/// Jane, the signed in user with id 1
+pub fn actor() -> UserResponse {
+    let now = DateTime::parse_from_rfc3339("2021-06-01T09:00:00+00:00").unwrap();
+    UserResponse {
+        id: 1,
+        name: "Jane".to_string(),
+        email: "jane@example.com".to_string(),
+        avatar: String::new(),
+        created_at: now,
+        updated_at: now,
+        version: 1,
+        deleted_at: None,
+        companies: None,
+    }
+}
@@ -1,304 +1,851 @@
-use bcrypt::{DEFAULT_COST, hash, verify};
-use path_slash::{PathBufExt, PathExt};
+use bcrypt::{DEFAULT_COST, hash};
 use std::{
-    convert::Infallible,
-    env,
-    path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
     vec::Vec,
 };
-use warp::http::StatusCode;
+use tokio::sync::Mutex;
+use warp::{
+    http::StatusCode,
+    Reply,
+};
 
+use crate::audit::{self, diff, AuditAction};
+use crate::avatar::Staged;
+use crate::bulk::{self, BulkAction};
 use crate::error_handler::ApiError;
 use crate::user::{
+    BulkUserPatch,
+    BulkUsersParams,
     CreateUserParams,
     FindUsersRequest,
     UserResponse,
     UpdateUserParams,
+    SORT_KEYS,
+};
+use crate::helpers::{
+    DeleteParams,
+    fetch_one,
+    fetch_one_in,
+    parse_id,
 };
-use crate::helpers::DeleteParams;
+use crate::membership::find_company_summaries;
+use crate::pagination::{Page, Pagination};
+use crate::policy::{authorize_user, find_managed_roles, UserAction};
+use crate::precondition::{
+    not_modified,
+    with_etag,
+    Precondition,
+};
+use crate::query_builder::{QueryBuilder, Trashed};
+use crate::sorting::{parse_sort_by, SortKey};
+use crate::storage::{self, Storage};
 
+#[tracing::instrument(skip_all)]
 pub async fn find_users(
     req: FindUsersRequest,
+    trashed: Trashed,
+    include: Vec<String>,
+    pagination: Pagination,
     graph: Arc<neo4rs::Graph>,
-) -> Result<impl warp::Reply, Infallible> {
-    let mut terms = vec!["MATCH (u:User)"];
-    let search_term;
-    let sort_by_term;
-    let limit_term;
-
-    if req.search.is_some() {
-        let search: String = req.search.unwrap().trim().to_string().clone();
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut builder = QueryBuilder::new("u", "User")
+        .trashed(trashed);
+    if let Some(search) = req.search {
+        let search = search.trim();
         if !search.is_empty() {
-            search_term = format!("WHERE u.name CONTAINS '{0}' OR u.email CONTAINS '{0}'", search);
-            terms.push(search_term.as_str());
+            builder = builder.where_contains(&["name", "email"], search);
         }
     }
-    if req.sort_by.is_some() {
-        let sort_by: String = req.sort_by.unwrap();
-        sort_by_term = format!("SORT u.{} ASC", sort_by);
-        terms.push(sort_by_term.as_str());
+    builder = builder.returns();
+    if let Some(sort_by) = req.sort_by {
+        let keys: Vec<SortKey> = parse_sort_by(&sort_by, SORT_KEYS).map_err(|e| {
+            warp::reject::custom(
+                ApiError::ParsingError("sort_by".to_string(), e)
+            )
+        })?;
+        builder = builder.order_by(&keys, SORT_KEYS);
     }
+    builder = builder
+        .skip(pagination.offset)
+        .limit(pagination.per_page);
 
-    terms.push("RETURN u");
-    if req.limit.is_some() {
-        let limit: u32 = req.limit.unwrap();
-        limit_term = format!("SKIP 0 LIMIT {}", limit);
-        terms.push(limit_term.as_str());
-    }
-    let q = terms.join(" ");
+    let row: neo4rs::Row = fetch_one(&graph, builder.build_count(), "User").await?;
+    let total: i64 = row.get("total").unwrap_or(0);
 
-    let mut result: neo4rs::RowStream = graph.execute(neo4rs::query(&q)).await.unwrap();
+    let mut result: neo4rs::RowStream = graph.execute(builder.build()).await.map_err(ApiError::from)?;
     let mut records: Vec<UserResponse> = vec![];
-    while let Ok(Some(row)) = result.next().await {
+    while let Some(row) = result.next().await.map_err(ApiError::from)? {
         records.push(UserResponse::from_row(row));
     }
-    Ok(warp::reply::json(&records))
+    if include.iter().any(|x| x == "companies") {
+        let ids: Vec<i64> = records.iter().map(|x| x.id).collect();
+        let mut summaries = find_company_summaries(&graph, ids).await?;
+        for record in records.iter_mut() {
+            record.companies = Some(summaries.remove(&record.id).unwrap_or_default());
+        }
+    }
+    Ok(Page::new(records, total, &pagination).into_reply(&pagination))
 }
 
+/// ETag only covers the user node, so it's left out when related records are embedded
+#[tracing::instrument(skip_all, fields(id = %id))]
 pub async fn show_user(
     id: String,
+    trashed: Trashed,
+    include: Vec<String>,
+    precondition: Option<Precondition>,
     graph: Arc<neo4rs::Graph>,
-) -> Result<impl warp::Reply, Infallible> {
-    let q: neo4rs::Query = neo4rs::query("
-        MATCH (u:User)
-        WHERE id(u) = $id
-        RETURN u
-    ")
-    .param("id", id.parse::<i64>().unwrap());
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let q: neo4rs::Query = QueryBuilder::new("u", "User")
+        .where_id(parse_id(&id)?)
+        .trashed(trashed)
+        .returns()
+        .build();
 
-    let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-    let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-    let record: UserResponse = UserResponse::from_row(row);
-    Ok(warp::reply::json(&record))
+    let row: neo4rs::Row = fetch_one(&graph, q, "User").await?;
+    let mut record: UserResponse = UserResponse::from_row(row);
+    if include.is_empty() {
+        if precondition.is_some_and(|x| x.matches(record.version)) {
+            return Ok(not_modified(record.version));
+        }
+        return Ok(with_etag(warp::reply::json(&record), record.version));
+    }
+    if include.iter().any(|x| x == "companies") {
+        let mut summaries = find_company_summaries(&graph, vec![record.id]).await?;
+        record.companies = Some(summaries.remove(&record.id).unwrap_or_default());
+    }
+    Ok(warp::reply::json(&record).into_response())
 }
 
+#[tracing::instrument(skip_all, fields(actor = current_user.id))]
 pub async fn create_user(
+    current_user: UserResponse,
     params: CreateUserParams,
     graph: Arc<neo4rs::Graph>,
+    storage: Arc<dyn Storage>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let q: neo4rs::Query = neo4rs::query("
-        CREATE (u:User {
-            name: $name,
-            email: $email,
-            password: $password,
-            createdAt: datetime(),
-            updatedAt: datetime()
-        })
-        RETURN u
-    ")
-    .param("name", params.name.unwrap())
-    .param("email", params.email.unwrap())
-    .param("password", hash(params.password.unwrap(), DEFAULT_COST).unwrap());
-
-    let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-    let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-    let node: neo4rs::Node = row.get("u").unwrap();
-
-    let mut avatar = format!("/storage/{}", params.avatar.clone().unwrap());
-
-    // move file into record directory
-    let mut abs_dirpath = env::current_dir().unwrap();
-    abs_dirpath.push("storage");
-    abs_dirpath.push(node.id().to_string());
-    tokio::fs::create_dir_all(abs_dirpath).await.unwrap();
-    let org_rel_filepath = PathBuf::from_slash(avatar);
-    let org_abs_filepath = format!("{}{}", env::current_dir().unwrap().to_str().unwrap(), org_rel_filepath.to_str().unwrap());
-    avatar = format!("/storage/{}/{}", node.id(), params.avatar.unwrap());
-    let new_rel_filepath = PathBuf::from_slash(avatar.clone());
-    let new_abs_filepath = format!("{}{}", env::current_dir().unwrap().to_str().unwrap(), new_rel_filepath.to_str().unwrap());
-    tokio::fs::rename(
-        PathBuf::from(org_abs_filepath),
-        PathBuf::from(new_abs_filepath),
-    ).await.map_err(|e| {
-        let msg = format!("error moving file: {}", e);
-        warp::reject::custom(
-            ApiError::ParsingError("avatar".to_string(), msg)
-        )
-    }).unwrap();
-
-    // update database for avatar path
-    let q: neo4rs::Query = neo4rs::query("
-        MATCH (u:User)
-        WHERE id(u) = $id
-        SET u.avatar = $avatar, u.updatedAt = datetime()
-        RETURN u
-    ")
-    .param("id", node.id())
-    .param("avatar", avatar);
-
-    let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-    let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-    let record: UserResponse = UserResponse::from_row(row);
+    // the key of the avatar depends on the id of the new node, so the upload is moved inside the transaction,
+    // each attempt rewinds the one before and the files are only settled once it's over
+    let files = Arc::new(Mutex::new(Staged::new(storage, params.avatar.clone())));
+    let actor_id: i64 = current_user.id;
+    let result = async {
+        ensure_email_available(&graph, params.email.as_ref().unwrap(), None).await?;
+        let files = files.clone();
+        graph.write_transaction(move |txn| {
+            let files = files.clone();
+            let params = params.clone();
+            Box::pin(async move {
+                let mut files = files.lock().await;
+                files.rewind().await?;
+                insert_user(txn, &mut files, actor_id, params).await
+            })
+        }).await
+    }.await;
+    let record: UserResponse = files.lock().await.settle(result).await?;
     Ok(warp::reply::with_status(
         warp::reply::json(&record),
         StatusCode::CREATED,
     ))
 }
 
+#[tracing::instrument(skip_all, fields(id = %id, actor = current_user.id))]
 pub async fn update_user(
     id: String,
+    current_user: UserResponse,
+    precondition: Option<Precondition>,
     params: UpdateUserParams,
     graph: Arc<neo4rs::Graph>,
+    storage: Arc<dyn Storage>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut avatar = None;
-
-    if params.avatar.is_some() {
-        // make sure record directory exists
-        let mut abs_dirpath = env::current_dir().unwrap();
-        abs_dirpath.push("storage");
-        abs_dirpath.push(id.clone());
-        tokio::fs::create_dir_all(abs_dirpath).await.unwrap();
-
-        // move new image into record directory
-        let org_filename = params.avatar.unwrap();
-        let org_rel_filepath = PathBuf::from_slash(format!("/storage/{}", org_filename));
-        let org_abs_filepath = format!("{}{}", env::current_dir().unwrap().to_str().unwrap(), org_rel_filepath.to_str().unwrap());
-        let rel_filepath = format!("/storage/{}/{}", id.clone(), org_filename);
-        let new_rel_filepath = PathBuf::from_slash(rel_filepath.clone());
-        let new_abs_filepath = format!("{}{}", env::current_dir().unwrap().to_str().unwrap(), new_rel_filepath.to_str().unwrap());
-        tokio::fs::rename(
-            PathBuf::from(org_abs_filepath),
-            PathBuf::from(new_abs_filepath),
-        ).await.map_err(|e| {
-            let msg = format!("error moving file: {}", e);
-            warp::reject::custom(
-                ApiError::ParsingError("avatar".to_string(), msg)
-            )
-        }).unwrap();
-
-        // get original file path
-        let q: neo4rs::Query = neo4rs::query("
-            MATCH (c:Company)
-            WHERE id(c) = $id
-            RETURN c
-        ")
-        .param("id", id.parse::<i64>().unwrap());
-        let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-        let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-        let node: neo4rs::Node = row.get("u").unwrap();
-
-        // delete old image
-        let old_avatar: String = node.get("avatar").unwrap();
-        let old_rel_filepath = PathBuf::from_slash(old_avatar);
-        let old_abs_filepath = format!("{}{}", env::current_dir().unwrap().to_str().unwrap(), old_rel_filepath.to_str().unwrap());
-        tokio::fs::remove_file(
-            PathBuf::from(old_abs_filepath)
-        ).await.unwrap();
-        avatar = Some(rel_filepath);
-    }
-
-    let mut terms = vec!["MATCH (u:User)"];
-    let w = format!("WHERE id(u) = {}", id);
-    terms.push(w.as_str());
-
-    let mut data = vec![];
-    match params.name {
-        Some(x) => {
-            data.push(format!("u.name = '{}'", x));
-        },
-        None => {},
-    }
-    match params.email {
-        Some(x) => {
-            data.push(format!("u.email = '{}'", x));
-        },
-        None => {},
-    }
-    match params.password {
-        Some(x) => {
-            data.push(format!("u.password = '{}'", hash(x, DEFAULT_COST).unwrap()));
-        },
-        None => {},
-    }
-    match avatar {
-        Some(x) => {
-            data.push(format!("u.avatar = '{}'", x));
-        },
-        None => {},
-    }
-    let s = format!("SET {}", data.join(", "));
-    terms.push(s.as_str());
-    terms.push("RETURN u");
-
-    let t = terms.join(" ");
-    let q: neo4rs::Query = neo4rs::query(t.as_str());
-    let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-    let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-    let record: UserResponse = UserResponse::from_row(row);
-    Ok(warp::reply::with_status(
-        warp::reply::json(&record),
-        StatusCode::OK,
+    // like for create, only the files of the last attempt are settled
+    let files = Arc::new(Mutex::new(Staged::new(storage, params.avatar.clone())));
+    let actor_id: i64 = current_user.id;
+    let result = async {
+        let id: i64 = parse_id(&id)?;
+        if let Some(email) = &params.email {
+            ensure_email_available(&graph, email, Some(id)).await?;
+        }
+        let files = files.clone();
+        graph.write_transaction(move |txn| {
+            let files = files.clone();
+            let params = params.clone();
+            let precondition = precondition.clone();
+            Box::pin(async move {
+                let mut files = files.lock().await;
+                files.rewind().await?;
+                change_user(txn, &mut files, actor_id, AuditAction::Update, id, Trashed::With, Some(params), &precondition).await
+            })
+        }).await
+    }.await;
+    let record: UserResponse = files.lock().await.settle(result).await?;
+    Ok(with_etag(
+        warp::reply::with_status(warp::reply::json(&record), StatusCode::OK),
+        record.version,
     ))
 }
 
+#[tracing::instrument(skip_all, fields(id = %id, actor = current_user.id))]
 pub async fn delete_user(
     id: String,
+    current_user: UserResponse,
+    precondition: Option<Precondition>,
     params: DeleteParams,
     graph: Arc<neo4rs::Graph>,
+    storage: Arc<dyn Storage>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let empty: Vec<u8> = vec![];
+    let id: i64 = parse_id(&id)?;
+    let action: AuditAction = match AuditAction::from_delete_mode(&params.mode) {
+        Some(x) => x,
+        None => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&empty),
+                StatusCode::BAD_REQUEST,
+            ));
+        },
+    };
+    let actor_id: i64 = current_user.id;
+    let files_storage: Arc<dyn Storage> = storage.clone();
+    let record: UserResponse = graph.write_transaction(move |txn| {
+        let precondition = precondition.clone();
+        // without a patch nothing is ever staged
+        let mut files = Staged::new(files_storage.clone(), None);
+        Box::pin(async move {
+            change_user(txn, &mut files, actor_id, action, id, Trashed::With, None, &precondition).await
+        })
+    }).await?;
 
-    match params.mode.as_str() {
-        "erase" => {
-            let q: neo4rs::Query = neo4rs::query("
-                MATCH (u:User)
-                WHERE id(u) = $id
-                DETACH DELETE u
-            ")
-            .param("id", id.parse::<i64>().unwrap());
-
-            // delete record directory including image file
-            let mut abs_dirpath = env::current_dir().unwrap();
-            abs_dirpath.push("storage");
-            abs_dirpath.push(id.clone());
-            tokio::fs::remove_dir_all(abs_dirpath).await.unwrap();
-
-            graph.execute(q).await.unwrap();
+    match action {
+        AuditAction::Erase => {
+            // the node is gone already, so files that can't be removed are only logged
+            if let Err(e) = remove_user_files(storage.as_ref(), id).await {
+                tracing::error!(user_id = id, error = %e, "Failed to remove storage");
+            }
             Ok(warp::reply::with_status(
                 warp::reply::json(&empty),
                 StatusCode::NO_CONTENT,
             ))
         },
-        "trash" => {
-            let q: neo4rs::Query = neo4rs::query("
-                MATCH (u:User)
-                WHERE id(u) = $id
-                SET u.deletedAt = datetime()
-                RETURN u
-            ")
-            .param("id", id.parse::<i64>().unwrap());
-
-            let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-            let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-            let record: UserResponse = UserResponse::from_row(row);
+        _ => {
             Ok(warp::reply::with_status(
                 warp::reply::json(&record),
                 StatusCode::OK,
             ))
         },
-        "restore" => {
-            let q: neo4rs::Query = neo4rs::query("
+    }
+}
+
+/// Applies one action to many users, either all of them are changed or none
+#[tracing::instrument(skip_all, fields(actor = current_user.id))]
+pub async fn bulk_users(
+    current_user: UserResponse,
+    params: BulkUsersParams,
+    graph: Arc<neo4rs::Graph>,
+    storage: Arc<dyn Storage>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let action: BulkAction = params.action;
+    let (user_action, audit_action, trashed) = match action {
+        BulkAction::Update => (UserAction::Update, AuditAction::Update, Trashed::With),
+        BulkAction::Trash => (UserAction::Delete, AuditAction::Trash, Trashed::Without),
+        BulkAction::Restore => (UserAction::Delete, AuditAction::Restore, Trashed::Only),
+        BulkAction::Erase => (UserAction::Delete, AuditAction::Erase, Trashed::With),
+    };
+
+    // nothing is touched until every id is known to be allowed
+    let mut failed = bulk::find_duplicates(&params.ids);
+    if failed.is_empty() {
+        let roles = find_managed_roles(&graph, current_user.id, params.ids.clone()).await?;
+        for id in params.ids.iter() {
+            let roles = roles.get(id).map(Vec::as_slice).unwrap_or_default();
+            if let Err(e) = authorize_user(current_user.id, *id, roles, user_action) {
+                failed.push((*id, e));
+            }
+        }
+    }
+    if !failed.is_empty() {
+        return Ok(bulk::into_reply(&params.ids, failed));
+    }
+
+    let patch: Option<UpdateUserParams> = params.patch.map(|x: BulkUserPatch| UpdateUserParams {
+        name: x.name,
+        ..UpdateUserParams::default()
+    });
+    // the id being changed when the transaction failed, one that wasn't found rolls back the others
+    let current = Arc::new(AtomicI64::new(0));
+    let actor_id: i64 = current_user.id;
+    let ids: Vec<i64> = params.ids.clone();
+    let files_storage: Arc<dyn Storage> = storage.clone();
+    let result = graph.write_transaction({
+        let current = current.clone();
+        move |txn| {
+            let current = current.clone();
+            let ids = ids.clone();
+            let patch = patch.clone();
+            // a bulk patch has no avatar, nothing is ever staged
+            let mut files = Staged::new(files_storage.clone(), None);
+            Box::pin(async move {
+                for id in ids {
+                    current.store(id, Ordering::SeqCst);
+                    change_user(txn, &mut files, actor_id, audit_action, id, trashed, patch.clone(), &None).await?;
+                }
+                Ok(())
+            })
+        }
+    }).await;
+    match result {
+        Ok(()) => {},
+        Err(e @ ApiError::NotFound(_)) => {
+            failed.push((current.load(Ordering::SeqCst), e));
+            return Ok(bulk::into_reply(&params.ids, failed));
+        },
+        Err(e) => return Err(e.into()),
+    }
+
+    // nodes are gone already, so files that can't be removed are only logged
+    if action == BulkAction::Erase {
+        for id in params.ids.iter() {
+            if let Err(e) = remove_user_files(storage.as_ref(), *id).await {
+                tracing::error!(user_id = id, error = %e, "Failed to remove storage");
+            }
+        }
+    }
+    Ok(bulk::into_reply(&params.ids, failed))
+}
+
+async fn insert_user(
+    txn: &neo4rs::Txn,
+    files: &mut Staged,
+    actor_id: i64,
+    params: CreateUserParams,
+) -> Result<UserResponse, ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        CREATE (u:User {
+            name: $name,
+            email: $email,
+            password: $password,
+            version: 1,
+            createdAt: datetime(),
+            updatedAt: datetime()
+        })
+        RETURN u
+    ")
+    .param("name", params.name.unwrap())
+    .param("email", params.email.unwrap())
+    .param("password", hash(params.password.unwrap(), DEFAULT_COST).unwrap());
+
+    let row: neo4rs::Row = fetch_one_in(txn, q, "User").await?;
+    let node: neo4rs::Node = row.get("u").unwrap();
+
+    // move uploaded file into record directory, it is deleted again if the transaction fails
+    let upload: String = params.avatar.unwrap();
+    let key: String = avatar_key(node.id(), &upload);
+    files.add(&upload, &key).await?;
+
+    // update database for avatar path
+    let q: neo4rs::Query = QueryBuilder::new("u", "User")
+        .where_id(node.id())
+        .set("avatar", avatar_path(&key))
+        .set_now("updatedAt")
+        .returns()
+        .build();
+
+    let row: neo4rs::Row = fetch_one_in(txn, q, "User").await?;
+    let record: UserResponse = UserResponse::from_row(row);
+    audit::record(txn, actor_id, AuditAction::Create, "User", record.id, diff(None, Some(&record))).await?;
+    Ok(record)
+}
+
+/// Changes one user inside `txn` and records it in the audit log
+///
+/// `trashed` limits which users can be found at all, `precondition` is the If-Match of the request.
+/// Returns the user after the change, or as it was before for erase.
+#[allow(clippy::too_many_arguments)]
+async fn change_user(
+    txn: &neo4rs::Txn,
+    files: &mut Staged,
+    actor_id: i64,
+    action: AuditAction,
+    id: i64,
+    trashed: Trashed,
+    patch: Option<UpdateUserParams>,
+    precondition: &Option<Precondition>,
+) -> Result<UserResponse, ApiError> {
+    let q: neo4rs::Query = QueryBuilder::new("u", "User")
+        .where_id(id)
+        .trashed(trashed)
+        .returns()
+        .build();
+    let before: UserResponse = UserResponse::from_row(fetch_one_in(txn, q, "User").await?);
+    // checked before any file is touched for a stale request
+    if precondition.as_ref().is_some_and(|x| !x.matches(before.version)) {
+        return Err(ApiError::PreconditionFailed("User".to_string()));
+    }
+
+    // the version is checked again by the write, it may have moved on since the read
+    let mut builder = QueryBuilder::new("u", "User")
+        .where_id(id)
+        .where_precondition(precondition);
+    builder = match action {
+        AuditAction::Erase => {
+            audit::record(txn, actor_id, action, "User", id, diff(Some(&before), None)).await?;
+            let versions: Option<Vec<i64>> = precondition.as_ref().and_then(Precondition::versions);
+            let condition: &str = if versions.is_some() { "AND coalesce(u.version, 0) IN $versions" } else { "" };
+            let q: neo4rs::Query = neo4rs::query(&format!("
                 MATCH (u:User)
-                WHERE id(u) = $id
-                REMOVE u.deletedAt
-                RETURN u
-            ")
-            .param("id", id.parse::<i64>().unwrap());
-
-            let mut result: neo4rs::RowStream = graph.execute(q).await.unwrap();
-            let row: neo4rs::Row = result.next().await.unwrap().unwrap();
-            let record: UserResponse = UserResponse::from_row(row);
-            Ok(warp::reply::with_status(
-                warp::reply::json(&record),
-                StatusCode::OK,
-            ))
+                WHERE id(u) = $id {}
+                OPTIONAL MATCH (t:RefreshToken)-[:ISSUED_TO]->(u)
+                DETACH DELETE t, u
+                RETURN count(DISTINCT u) AS count
+            ", condition))
+            .param("id", id)
+            .param("versions", versions.unwrap_or_default());
+
+            let row: neo4rs::Row = fetch_one_in(txn, q, "User").await?;
+            if row.get::<i64>("count") != Some(1) {
+                return Err(ApiError::PreconditionFailed("User".to_string()));
+            }
+            return Ok(before);
         },
-        &_ => {
-            Ok(warp::reply::with_status(
-                warp::reply::json(&empty),
-                StatusCode::BAD_REQUEST,
-            ))
+        AuditAction::Trash => builder.set_now("deletedAt"),
+        AuditAction::Restore => builder.remove("deletedAt"),
+        AuditAction::Create | AuditAction::Update => {
+            let params: UpdateUserParams = patch.unwrap_or_default();
+            if let Some(upload) = params.avatar {
+                // move new image into record directory
+                let key: String = avatar_key(id, &upload);
+                files.add(&upload, &key).await?;
+
+                // old image is deleted once the new one is committed
+                if !before.avatar.is_empty() {
+                    files.replace(stored_key(&before.avatar));
+                }
+                builder = builder.set("avatar", avatar_path(&key));
+            }
+            if let Some(x) = params.name {
+                builder = builder.set("name", x);
+            }
+            if let Some(x) = params.email {
+                builder = builder.set("email", x);
+            }
+            if let Some(x) = params.password {
+                builder = builder.set("password", hash(x, DEFAULT_COST).unwrap());
+            }
+            builder.set_now("updatedAt")
         },
+    };
+    let q: neo4rs::Query = builder
+        .bump_version()
+        .returns()
+        .build();
+    let after: UserResponse = match fetch_one_in(txn, q, "User").await {
+        Ok(row) => UserResponse::from_row(row),
+        Err(ApiError::NotFound(_)) => return Err(ApiError::PreconditionFailed("User".to_string())),
+        Err(e) => return Err(e),
+    };
+    audit::record(txn, actor_id, action, "User", id, diff(Some(&before), Some(&after))).await?;
+    Ok(after)
+}
+
+/// Deletes every file of the user, including the avatar, if any
+pub async fn remove_user_files(storage: &dyn Storage, id: i64) -> Result<(), ApiError> {
+    storage::delete_all(storage, &format!("{}/", id)).await
+}
+
+// avatars are kept under the id of their user, like `42/<uuid>.png`, next to their thumbnails
+// the database keeps the key behind "/storage/", as it always has
+
+fn avatar_key(id: i64, upload_key: &str) -> String {
+    let filename: &str = upload_key.rsplit('/').next().unwrap_or(upload_key);
+    format!("{}/{}", id, filename)
+}
+
+fn avatar_path(key: &str) -> String {
+    format!("/storage/{}", key)
+}
+
+fn stored_key(avatar: &str) -> &str {
+    avatar.trim_start_matches("/storage/")
+}
+
+async fn ensure_email_available(
+    graph: &neo4rs::Graph,
+    email: &str,
+    except: Option<i64>,
+) -> Result<(), ApiError> {
+    let q: neo4rs::Query = neo4rs::query("
+        MATCH (u:User)
+        WHERE u.email = $email AND id(u) <> $id
+        RETURN count(u) AS count
+    ")
+    .param("email", email)
+    .param("id", except.unwrap_or(-1));
+
+    let row: neo4rs::Row = fetch_one(graph, q, "User").await?;
+    match row.get::<i64>("count") {
+        Some(0) => Ok(()),
+        _ => Err(ApiError::Conflict(format!("email {} has already been taken", email))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::avatar;
+    use crate::storage::LocalStorage;
+    use chrono::DateTime;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use neo4rs::testing::{node, BoltType, Expect, Reply, Script, StubServer};
+    use std::io::Cursor;
+    use crate::test_support::{actor, connect};
+
+    /// The request of a write that is answered with FAILURE
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Failing {
+        Read,
+        Insert,
+        Avatar,
+        Audit,
+        Commit,
+    }
+
+    fn user(id: i64, avatar: &str, version: i64) -> Vec<BoltType> {
+        let now = DateTime::parse_from_rfc3339("2021-06-01T09:00:00+00:00").unwrap();
+        vec![node(id, &["User"], vec![
+            ("name", "John".into()),
+            ("email", "john@example.com".into()),
+            ("avatar", avatar.into()),
+            ("createdAt", now.into()),
+            ("updatedAt", now.into()),
+            ("version", version.into()),
+        ])]
+    }
+
+    async fn local_storage(name: &str) -> Arc<LocalStorage> {
+        let root = std::env::temp_dir().join(format!("groupware-user-{}-{}", name, std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        Arc::new(LocalStorage::new(root, "/storage", b"secret"))
+    }
+
+    /// Stores an image in tmp/ like an upload, returns its key
+    async fn upload(storage: &LocalStorage) -> String {
+        let mut png: Vec<u8> = vec![];
+        DynamicImage::ImageRgb8(RgbImage::new(300, 200))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        avatar::store(storage, "tmp/new", png.into()).await.unwrap()
+    }
+
+    async fn keys(storage: &LocalStorage) -> Vec<String> {
+        let mut keys: Vec<String> = storage.list("").await.unwrap();
+        keys.sort();
+        keys
+    }
+
+    /// Answers the last request with `ok`, or with a FAILURE when it is the failing one
+    fn answer(script: Script, fails: bool, ok: impl FnOnce(Script) -> Script) -> Script {
+        if !fails {
+            return ok(script);
+        }
+        script
+            .reply(Reply::failure("Neo.DatabaseError.General.UnknownError", "disk on fire"))
+            .expect(Expect::reset())
+            .reply(Reply::success())
+    }
+
+    fn audit(script: Script, failing: Option<Failing>) -> Script {
+        let script = script.expect(Expect::run_containing("CREATE (e:AuditEvent"));
+        let script = answer(script, failing == Some(Failing::Audit), |x| {
+            x.reply(Reply::success()).expect(Expect::discard()).reply(Reply::success())
+        });
+        if failing == Some(Failing::Audit) {
+            return script;
+        }
+        answer(script.expect(Expect::commit()), failing == Some(Failing::Commit), |x| x.reply(Reply::success()))
+    }
+
+    /// Every request of a create up to the failing one, if any
+    fn create_script(failing: Option<Failing>) -> Script {
+        let script = Script::new()
+            .expect(Expect::run_containing("WHERE u.email = $email AND id(u) <> $id"))
+            .returns(&["count"], vec![vec![0i64.into()]])
+            // recycled by the pool
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::begin())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("CREATE (u:User {"));
+        let script = answer(script, failing == Some(Failing::Insert), |x| x.returns(&["u"], vec![user(42, "", 1)]));
+        if failing == Some(Failing::Insert) {
+            return script;
+        }
+        let script = script.expect(Expect::run_containing("u.avatar = $set_avatar"));
+        let script = answer(script, failing == Some(Failing::Avatar), |x| x.returns(&["u"], vec![user(42, "/storage/42/new.png", 1)]));
+        if failing == Some(Failing::Avatar) {
+            return script;
+        }
+        audit(script, failing)
+    }
+
+    /// Every request of an update of the avatar up to the failing one, if any
+    fn update_script(failing: Option<Failing>) -> Script {
+        let script = Script::new()
+            .expect(Expect::begin())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("MATCH (u:User)").param("id", 42i64));
+        let script = answer(script, failing == Some(Failing::Read), |x| x.returns(&["u"], vec![user(42, "/storage/42/old.png", 1)]));
+        if failing == Some(Failing::Read) {
+            return script;
+        }
+        let script = script.expect(Expect::run_containing("u.avatar = $set_avatar"));
+        let script = answer(script, failing == Some(Failing::Avatar), |x| x.returns(&["u"], vec![user(42, "/storage/42/new.png", 2)]));
+        if failing == Some(Failing::Avatar) {
+            return script;
+        }
+        audit(script, failing)
+    }
+
+    #[tokio::test]
+    async fn should_leave_no_file_behind_when_create_fails() {
+        for failing in [Failing::Insert, Failing::Avatar, Failing::Audit, Failing::Commit] {
+            let server = StubServer::start(create_script(Some(failing))).await;
+            let storage = local_storage("create").await;
+            let params = CreateUserParams {
+                name: Some("John".to_string()),
+                email: Some("john@example.com".to_string()),
+                password: Some("secret".to_string()),
+                password_confirmation: Some("secret".to_string()),
+                avatar: Some(upload(&storage).await),
+            };
+
+            let result = create_user(actor(), params, connect(&server).await, storage.clone()).await;
+            assert!(result.is_err(), "{:?} didn't fail", failing);
+            assert_eq!(keys(&storage).await, Vec::<String>::new(), "{:?} left files behind", failing);
+            server.finish();
+            let _ = tokio::fs::remove_dir_all(storage.location()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn should_keep_old_avatar_when_update_fails() {
+        for failing in [Failing::Read, Failing::Avatar, Failing::Audit, Failing::Commit] {
+            let server = StubServer::start(update_script(Some(failing))).await;
+            let storage = local_storage("update").await;
+            avatar::move_to(storage.as_ref(), &upload(&storage).await, "42/old.png").await.unwrap();
+            let old: Vec<String> = keys(&storage).await;
+            let params = UpdateUserParams {
+                avatar: Some(upload(&storage).await),
+                ..UpdateUserParams::default()
+            };
+
+            let result = update_user("42".to_string(), actor(), None, params, connect(&server).await, storage.clone()).await;
+            assert!(result.is_err(), "{:?} didn't fail", failing);
+            assert_eq!(keys(&storage).await, old, "{:?} didn't keep the old avatar only", failing);
+            server.finish();
+            let _ = tokio::fs::remove_dir_all(storage.location()).await;
+        }
+    }
+
+    fn trash(script: Script, id: i64) -> Script {
+        script
+            .expect(Expect::run_containing("MATCH (u:User)").param("id", id))
+            .returns(&["u"], vec![user(id, "", 1)])
+            .expect(Expect::run_containing("u.deletedAt = datetime()"))
+            .returns(&["u"], vec![user(id, "", 2)])
+            .expect(Expect::run_containing("CREATE (e:AuditEvent"))
+            .reply(Reply::success())
+            .expect(Expect::discard())
+            .reply(Reply::success())
+    }
+
+    fn managed_roles(script: Script) -> Script {
+        script
+            .expect(Expect::run_containing("OPTIONAL MATCH (me:User)-[n:MEMBER_OF]->(c)")
+                .param("ids", vec![42i64, 43i64])
+                .param("user_id", 1i64))
+            .returns(&["id", "role", "manager"], vec![
+                vec![42i64.into(), "member".into(), "admin".into()],
+                vec![43i64.into(), "member".into(), "admin".into()],
+            ])
+            .expect(Expect::reset())
+            .reply(Reply::success())
+            .expect(Expect::begin())
+            .reply(Reply::success())
+    }
+
+    fn bulk_trash() -> BulkUsersParams {
+        BulkUsersParams {
+            action: BulkAction::Trash,
+            ids: vec![42, 43],
+            patch: None,
+        }
+    }
+
+    async fn json(reply: impl warp::Reply) -> (StatusCode, serde_json::Value) {
+        let response = warp::Reply::into_response(reply);
+        let status: StatusCode = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn should_move_avatar_of_last_attempt_only() {
+        let mut script = Script::new()
+            .expect(Expect::run_containing("WHERE u.email = $email AND id(u) <> $id"))
+            .returns(&["count"], vec![vec![0i64.into()]]);
+        // the first commit hits a deadlock, each attempt creates another node
+        for (id, committed) in [(42i64, false), (43i64, true)] {
+            script = script
+                // recycled by the pool
+                .expect(Expect::reset())
+                .reply(Reply::success())
+                .expect(Expect::begin())
+                .reply(Reply::success())
+                .expect(Expect::run_containing("CREATE (u:User {"))
+                .returns(&["u"], vec![user(id, "", 1)])
+                .expect(Expect::run_containing("u.avatar = $set_avatar"))
+                .returns(&["u"], vec![user(id, &format!("/storage/{}/new.png", id), 1)])
+                .expect(Expect::run_containing("CREATE (e:AuditEvent"))
+                .reply(Reply::success())
+                .expect(Expect::discard())
+                .reply(Reply::success())
+                .expect(Expect::commit());
+            script = match committed {
+                true => script.reply(Reply::success()),
+                false => script
+                    .reply(Reply::failure("Neo.TransientError.Transaction.DeadlockDetected", "ForsetiClient can't acquire ExclusiveLock"))
+                    .expect(Expect::reset())
+                    .reply(Reply::success()),
+            };
+        }
+        let server = StubServer::start(script).await;
+        let storage = local_storage("retry").await;
+        let params = CreateUserParams {
+            name: Some("John".to_string()),
+            email: Some("john@example.com".to_string()),
+            password: Some("secret".to_string()),
+            password_confirmation: Some("secret".to_string()),
+            avatar: Some(upload(&storage).await),
+        };
+
+        let reply = create_user(actor(), params, connect(&server).await, storage.clone()).await.unwrap();
+        let (status, body) = json(reply).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["id"], 43);
+        let keys: Vec<String> = keys(&storage).await;
+        assert_eq!(keys.len(), 1 + avatar::THUMBNAIL_SIZES.len());
+        assert!(keys.iter().all(|x| x.starts_with("43/")), "{:?}", keys);
+        server.finish();
+        let _ = tokio::fs::remove_dir_all(storage.location()).await;
+    }
+
+    #[tokio::test]
+    async fn should_delete_replaced_avatar_after_update() {
+        let server = StubServer::start(update_script(None)).await;
+        let storage = local_storage("replace").await;
+        avatar::move_to(storage.as_ref(), &upload(&storage).await, "42/old.png").await.unwrap();
+        let params = UpdateUserParams {
+            avatar: Some(upload(&storage).await),
+            ..UpdateUserParams::default()
+        };
+
+        let reply = update_user("42".to_string(), actor(), None, params, connect(&server).await, storage.clone()).await.unwrap();
+        let (status, body) = json(reply).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], 2);
+        let keys: Vec<String> = keys(&storage).await;
+        assert_eq!(keys.len(), 1 + avatar::THUMBNAIL_SIZES.len());
+        assert!(keys.iter().all(|x| x.starts_with("42/") && !x.starts_with("42/old")), "{:?}", keys);
+        server.finish();
+        let _ = tokio::fs::remove_dir_all(storage.location()).await;
+    }
+
+    #[tokio::test]
+    async fn should_erase_user_with_files() {
+        let script = Script::new()
+            .expect(Expect::begin())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("MATCH (u:User)").param("id", 42i64))
+            .returns(&["u"], vec![user(42, "/storage/42/old.png", 1)])
+            .expect(Expect::run_containing("CREATE (e:AuditEvent").param("action", "erase")
+                .param("diff", r#"{"avatar":{"from":"/storage/42/old.png","to":null},"email":{"from":"john@example.com","to":null},"name":{"from":"John","to":null}}"#)
+                .param("actor_id", 1i64)
+                .param("label", "User")
+                .param("target_id", 42i64))
+            .reply(Reply::success())
+            .expect(Expect::discard())
+            .reply(Reply::success())
+            .expect(Expect::run_containing("DETACH DELETE t, u"))
+            .returns(&["count"], vec![vec![1i64.into()]])
+            .expect(Expect::commit())
+            .reply(Reply::success());
+        let server = StubServer::start(script).await;
+        let storage = local_storage("erase").await;
+        avatar::move_to(storage.as_ref(), &upload(&storage).await, "42/old.png").await.unwrap();
+        let params = DeleteParams {
+            mode: "erase".to_string(),
+        };
+
+        let reply = delete_user("42".to_string(), actor(), None, params, connect(&server).await, storage.clone()).await.unwrap();
+        assert_eq!(warp::Reply::into_response(reply).status(), StatusCode::NO_CONTENT);
+        assert!(keys(&storage).await.is_empty());
+        server.finish();
+        let _ = tokio::fs::remove_dir_all(storage.location()).await;
+    }
+
+    #[tokio::test]
+    async fn should_trash_members_of_managed_company_in_bulk() {
+        let script = trash(trash(managed_roles(Script::new()), 42), 43)
+            .expect(Expect::commit())
+            .reply(Reply::success());
+        let server = StubServer::start(script).await;
+        let storage = local_storage("bulk").await;
+
+        let reply = bulk_users(actor(), bulk_trash(), connect(&server).await, storage.clone()).await.unwrap();
+        let (status, body) = json(reply).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(body["results"][0]["status"], 200);
+        assert_eq!(body["results"][1]["status"], 200);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_bulk_when_a_user_is_missing() {
+        let script = trash(managed_roles(Script::new()), 42)
+            .expect(Expect::run_containing("MATCH (u:User)").param("id", 43i64))
+            .returns(&["u"], vec![])
+            .expect(Expect::rollback())
+            .reply(Reply::success());
+        let server = StubServer::start(script).await;
+        let storage = local_storage("bulk-missing").await;
+
+        let reply = bulk_users(actor(), bulk_trash(), connect(&server).await, storage.clone()).await.unwrap();
+        let (status, body) = json(reply).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["results"][0]["status"], 424);
+        assert_eq!(body["results"][1]["status"], 404);
+        server.finish();
+    }
+
+    #[tokio::test]
+    async fn should_not_touch_users_outside_managed_companies() {
+        let script = Script::new()
+            .expect(Expect::run_containing("OPTIONAL MATCH (me:User)-[n:MEMBER_OF]->(c)"))
+            .returns(&["id", "role", "manager"], vec![
+                // 43 is in no company of the admin
+                vec![42i64.into(), "member".into(), "admin".into()],
+            ]);
+        let server = StubServer::start(script).await;
+        let storage = local_storage("bulk-forbidden").await;
+
+        let reply = bulk_users(actor(), bulk_trash(), connect(&server).await, storage.clone()).await.unwrap();
+        let (status, body) = json(reply).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["results"][0]["status"], 424);
+        assert_eq!(body["results"][1]["status"], 403);
+        server.finish();
     }
 }
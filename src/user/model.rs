@@ -4,17 +4,22 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
+use crate::bulk::BulkAction;
+use crate::membership::MembershipSummary;
+use crate::sorting::sort_by_regex;
+
 // find
 
 #[derive(Default, Deserialize)]
 pub struct FindUsersParams {
     pub search: Option<String>,
     pub sort_by: Option<String>,
-    pub limit: Option<String>,
 }
 
+pub const SORT_KEYS: &[&str] = &["name", "email", "createdAt"];
+
 lazy_static! {
-    static ref REGEX_SORT_BY: Regex = Regex::new(r"(name|email)").unwrap();
+    static ref REGEX_SORT_BY: Regex = sort_by_regex(SORT_KEYS);
 }
 
 #[derive(Default, Validate)]
@@ -22,10 +27,10 @@ pub struct FindUsersRequest {
     pub search: Option<String>,
     #[validate(regex = "REGEX_SORT_BY")]
     pub sort_by: Option<String>,
-    #[validate(range(min = 5, max = 100))]
-    pub limit: Option<u32>,
 }
 
+pub const INCLUDES: &[&str] = &["companies"];
+
 // create
 
 #[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
@@ -56,6 +61,24 @@ pub struct UpdateUserParams {
     pub avatar: Option<String>,
 }
 
+// bulk
+
+#[derive(Clone, Debug, Validate, Serialize, Deserialize)]
+pub struct BulkUsersParams {
+    pub action: BulkAction,
+    #[validate(length(min = 1, max = 100))]
+    pub ids: Vec<i64>,
+    #[validate]
+    pub patch: Option<BulkUserPatch>,
+}
+
+// email must stay unique and files can't be shared, so only name can be set on many users at once
+#[derive(Clone, Debug, Default, Validate, Serialize, Deserialize)]
+pub struct BulkUserPatch {
+    #[validate(length(min = 1))]
+    pub name: Option<String>,
+}
+
 // response
 
 #[derive(Clone, Debug, Serialize)]
@@ -67,8 +90,11 @@ pub struct UserResponse {
     pub avatar: String,
     pub created_at: DateTime<FixedOffset>,
     pub updated_at: DateTime<FixedOffset>,
+    pub version: i64,
     #[serde(skip_serializing_if = "Option::is_none")] // if none, excluded from query
     pub deleted_at: Option<DateTime<FixedOffset>>,
+    #[serde(skip_serializing_if = "Option::is_none")] // only with ?include=companies
+    pub companies: Option<Vec<MembershipSummary>>,
 }
 
 impl UserResponse {
@@ -78,10 +104,12 @@ impl UserResponse {
             id: u.id(),
             name: u.get("name").unwrap(),
             email: u.get("email").unwrap(),
-            avatar: u.get("avatar").unwrap(),
+            avatar: u.get("avatar").unwrap_or_default(),
             created_at: u.get("createdAt").unwrap(),
             updated_at: u.get("updatedAt").unwrap(),
+            version: u.get("version").unwrap_or(0),
             deleted_at: u.get("deletedAt"),
+            companies: None,
         }
     }
 }
@@ -1,13 +1,9 @@
 use bytes::{Buf, BufMut};
 use std::{
     collections::HashMap,
-    env,
-    ffi::OsStr,
-    path::Path,
     sync::Arc,
 };
-use futures::{StreamExt, TryStreamExt};
-use serde_json::Deserializer;
+use futures::TryStreamExt;
 use uuid::Uuid;
 use validator::Validate;
 use warp::{
@@ -16,36 +12,77 @@ use warp::{
     Filter,
 };
 
+use crate::bulk::require_patch;
 use crate::helpers::{
+    with_json_body,
     DeleteParams,
+    parse_id,
     with_db,
+    with_include,
+    with_trashed,
 };
+use crate::auth::{require_auth, with_auth, JwtKeys};
+use crate::avatar;
+use crate::config::settings;
 use crate::error_handler::ApiError;
+use crate::pagination::with_pagination;
+use crate::precondition::{with_if_match, with_if_none_match};
+use crate::query_builder::Trashed;
+use crate::policy::{authorize_user, find_managed_roles, UserAction};
+use crate::storage::{with_storage, Storage};
 use crate::user::{
     self,
+    BulkUsersParams,
     CreateUserParams,
     FindUsersParams,
     FindUsersRequest,
     UpdateUserParams,
+    UserResponse,
 };
 
 pub fn init(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+    storage: Arc<dyn Storage>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    find_users(graph.clone())
-        .or(show_user(graph.clone()))
-        .or(create_user(graph.clone()))
-        .or(update_user(graph.clone()))
-        .or(delete_user(graph))
+    find_users(graph.clone(), keys.clone())
+        .or(find_trashed_users(graph.clone(), keys.clone()))
+        .or(show_user(graph.clone(), keys.clone()))
+        .or(create_user(graph.clone(), keys.clone(), storage.clone()))
+        .or(bulk_users(graph.clone(), keys.clone(), storage.clone()))
+        .or(update_user(graph.clone(), keys.clone(), storage.clone()))
+        .or(delete_user(graph, keys, storage))
 }
 
 /// GET /users
 fn find_users(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("users")
         .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
         .and(with_find_request())
+        .and(with_trashed())
+        .and(with_include(user::INCLUDES))
+        .and(with_pagination())
+        .and(with_db(graph))
+        .and_then(user::find_users)
+}
+
+/// GET /users/trash
+// must be tried before GET /users/:id
+fn find_trashed_users(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("users" / "trash")
+        .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_find_request())
+        .and(warp::any().map(|| Trashed::Only))
+        .and(with_include(user::INCLUDES))
+        .and(with_pagination())
         .and(with_db(graph))
         .and_then(user::find_users)
 }
@@ -53,9 +90,14 @@ fn find_users(
 /// GET /users/:id
 fn show_user(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("users" / String)
         .and(warp::get())
+        .and(require_auth(graph.clone(), keys))
+        .and(with_trashed())
+        .and(with_include(user::INCLUDES))
+        .and(with_if_none_match())
         .and(with_db(graph))
         .and_then(user::show_user)
 }
@@ -63,36 +105,88 @@ fn show_user(
 /// POST /users
 fn create_user(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+    storage: Arc<dyn Storage>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("users")
         .and(warp::post())
-        .and(with_create_params())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_create_params(storage.clone()))
         .and(with_db(graph))
+        .and(with_storage(storage))
         .and_then(user::create_user)
 }
 
+/// POST /users/bulk
+fn bulk_users(
+    graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("users" / "bulk")
+        .and(warp::post())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_bulk_params())
+        .and(with_db(graph))
+        .and(with_storage(storage))
+        .and_then(user::bulk_users)
+}
+
 /// PATCH /users/:id
 fn update_user(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+    storage: Arc<dyn Storage>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("users" / String)
         .and(warp::patch())
-        .and(with_update_params())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph, UserAction::Update).await?;
+            Ok::<(String, UserResponse), warp::Rejection>((id, current_user))
+        })
+        .untuple_one()
+        .and(with_if_match())
+        .and(with_update_params(storage.clone()))
         .and(with_db(graph))
+        .and(with_storage(storage))
         .and_then(user::update_user)
 }
 
 /// DELETE /users/:id
 fn delete_user(
     graph: Arc<neo4rs::Graph>,
+    keys: Arc<JwtKeys>,
+    storage: Arc<dyn Storage>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("users" / String)
         .and(warp::delete())
+        .and(with_auth(graph.clone(), keys))
+        .and(with_db(graph.clone()))
+        .and_then(|id: String, current_user: UserResponse, graph: Arc<neo4rs::Graph>| async move {
+            authorize(&id, &current_user, &graph, UserAction::Delete).await?;
+            Ok::<(String, UserResponse), warp::Rejection>((id, current_user))
+        })
+        .untuple_one()
+        .and(with_if_match())
         .and(with_delete_params())
         .and(with_db(graph))
+        .and(with_storage(storage))
         .and_then(user::delete_user)
 }
 
+async fn authorize(
+    id: &str,
+    current_user: &UserResponse,
+    graph: &neo4rs::Graph,
+    action: UserAction,
+) -> Result<(), ApiError> {
+    let id: i64 = parse_id(id)?;
+    let roles = find_managed_roles(graph, current_user.id, vec![id]).await?;
+    authorize_user(current_user.id, id, roles.get(&id).map(Vec::as_slice).unwrap_or_default(), action)
+}
+
 // warp::query::raw can't hook rejection of InvalidQuery for incorrect data type
 // so define FindUsersParams that contains string field
 // and define FindUsersRequest that contains number field
@@ -106,17 +200,6 @@ fn with_find_request() -> impl Filter<Extract = (FindUsersRequest, ), Error = wa
         if params.sort_by.is_some() {
             req.sort_by = params.sort_by;
         }
-        if params.limit.is_some() {
-            let limit = match params.limit.unwrap().parse::<u32>() {
-                Ok(r) => r,
-                Err(e) => {
-                    return Err(warp::reject::custom(
-                        ApiError::ParsingError("limit".to_string(), e.to_string())
-                    ));
-                },
-            };
-            req.limit = Some(limit);
-        }
         match req.validate() {
             Ok(_) => Ok(req),
             Err(e) => Err(warp::reject::custom(
@@ -126,30 +209,27 @@ fn with_find_request() -> impl Filter<Extract = (FindUsersRequest, ), Error = wa
     })
 }
 
-fn with_create_params() -> impl Filter<Extract = (CreateUserParams, ), Error = warp::Rejection> + Clone {
+fn with_create_params(
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = (CreateUserParams, ), Error = warp::Rejection> + Clone {
     warp::any()
         .and(warp::header::value("content-type"))
-        .and(warp::multipart::form().max_length(5_000_000))
+        .and(warp::multipart::form().max_length(settings().storage.max_upload_bytes))
+        .and(with_storage(storage))
         .and_then(validate_create_params)
 }
 
 async fn validate_create_params(
     content_type: HeaderValue,
     form: FormData,
+    storage: Arc<dyn Storage>,
 ) -> Result<CreateUserParams, warp::Rejection> {
-    if content_type.to_str().unwrap().starts_with("multipart/form-data") == false {
+    if !content_type.to_str().unwrap().starts_with("multipart/form-data") {
         return Err(warp::reject::custom(
             ApiError::ParsingError("content-type".to_string(), "Must be multipart/form-data".to_string())
         ));
     }
-    let parts: Vec<Part> = form.try_collect().await.map_err(|e| {
-        println!("{:?}", e);
-        warp::reject::custom(
-            ApiError::ParsingError("sort_by".to_string(), "Must be one of name and email".to_string())
-        )
-    }).unwrap();
-
-    let vars: HashMap<String, String> = accept_uploading(parts).await.unwrap();
+    let vars: HashMap<String, String> = accept_uploading(form, storage.as_ref()).await?;
 
     let params = CreateUserParams {
         name: if vars.contains_key("name") {
@@ -181,6 +261,7 @@ async fn validate_create_params(
     match params.validate() {
         Ok(_) => Ok(params),
         Err(e) => {
+            discard_uploads(storage.as_ref(), params.avatar.as_slice()).await;
             Err(warp::reject::custom(
                 ApiError::ValidationErrors(e)
             ))
@@ -188,30 +269,27 @@ async fn validate_create_params(
     }
 }
 
-fn with_update_params() -> impl Filter<Extract = (UpdateUserParams, ), Error = warp::Rejection> + Clone {
+fn with_update_params(
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = (UpdateUserParams, ), Error = warp::Rejection> + Clone {
     warp::any()
         .and(warp::header::value("content-type"))
-        .and(warp::multipart::form().max_length(5_000_000))
+        .and(warp::multipart::form().max_length(settings().storage.max_upload_bytes))
+        .and(with_storage(storage))
         .and_then(validate_update_params)
 }
 
 async fn validate_update_params(
     content_type: HeaderValue,
     form: FormData,
+    storage: Arc<dyn Storage>,
 ) -> Result<UpdateUserParams, warp::Rejection> {
-    if content_type.to_str().unwrap().starts_with("multipart/form-data") == false {
+    if !content_type.to_str().unwrap().starts_with("multipart/form-data") {
         return Err(warp::reject::custom(
             ApiError::ParsingError("content-type".to_string(), "Must be multipart/form-data".to_string())
         ));
     }
-    let parts: Vec<Part> = form.try_collect().await.map_err(|e| {
-        println!("{:?}", e);
-        warp::reject::custom(
-            ApiError::ParsingError("sort_by".to_string(), "Must be one of name and email".to_string())
-        )
-    }).unwrap();
-
-    let vars: HashMap<String, String> = accept_uploading(parts).await.unwrap();
+    let vars: HashMap<String, String> = accept_uploading(form, storage.as_ref()).await?;
 
     let params = UpdateUserParams {
         name: if vars.contains_key("name") {
@@ -243,6 +321,7 @@ async fn validate_update_params(
     match params.validate() {
         Ok(_) => Ok(params),
         Err(e) => {
+            discard_uploads(storage.as_ref(), params.avatar.as_slice()).await;
             Err(warp::reject::custom(
                 ApiError::ValidationErrors(e)
             ))
@@ -250,88 +329,220 @@ async fn validate_update_params(
     }
 }
 
+/// Files are stored under `tmp/` until the user they belong to has been saved,
+/// the key of the file is returned in place of the field value
+///
+/// Files already stored are deleted again when a later part is rejected.
+// every part has to be read before the next one can be taken from the form
 async fn accept_uploading(
-    parts: Vec<Part>,
+    mut form: FormData,
+    storage: &dyn Storage,
 ) -> Result<HashMap<String, String>, warp::Rejection> {
     let mut vars: HashMap<String, String> = HashMap::new();
-    for p in parts {
-        let field_name = p.name().clone().to_string();
-        let org_filename = p.filename().clone();
-        let mut file_extension: Option<String> = None;
-        if org_filename.is_some() {
-            let content_type = p.content_type().unwrap();
-            if content_type.starts_with("image/") {
-                file_extension = Some(Path::new(org_filename.unwrap()).extension().and_then(OsStr::to_str).unwrap().to_string());
-            } else {
-                let msg = format!("invalid file type found: {}", content_type);
-                return Err(warp::reject::custom(
-                    ApiError::ParsingError("avatar".to_string(), msg)
-                ));
-            }
+    let mut uploads: Vec<String> = vec![];
+    loop {
+        let accepted = match form.try_next().await {
+            Ok(Some(p)) => accept_part(p, storage, &mut vars, &mut uploads).await,
+            Ok(None) => return Ok(vars),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to read multipart form");
+                Err(warp::reject::custom(
+                    ApiError::ParsingError("form".to_string(), e.to_string())
+                ))
+            },
+        };
+        if let Err(e) = accepted {
+            discard_uploads(storage, &uploads).await;
+            return Err(e);
         }
+    }
+}
 
-        let value = p.stream().try_fold(Vec::new(), |mut vec, data| {
-            vec.put(data);
-            async move { Ok(vec) }
-        }).await.map_err(|e| {
-            let msg = format!("reading file error: {}", e);
-            warp::reject::custom(
-                ApiError::ParsingError("avatar".to_string(), msg)
-            )
-        }).unwrap();
-
-        if file_extension.is_some() {
-            let mut file_path = env::current_dir().unwrap();
-            file_path.push("storage");
-            let new_filename = format!("{}.{}", Uuid::new_v4().to_string(), file_extension.unwrap().as_str());
-            file_path.push(new_filename.clone());
-            tokio::fs::write(&file_path, value).await.map_err(|e| {
+async fn accept_part(
+    p: Part,
+    storage: &dyn Storage,
+    vars: &mut HashMap<String, String>,
+    uploads: &mut Vec<String>,
+) -> Result<(), warp::Rejection> {
+    let field_name = p.name().to_string();
+    let filename: Option<String> = p.filename().map(str::to_string);
+    let content_type: Option<String> = p.content_type().map(str::to_string);
+    if filename.is_some() && !UPLOAD_FIELDS.contains(&field_name.as_str()) {
+        return Err(warp::reject::custom(
+            ApiError::ParsingError(field_name, "Must not be a file".to_string())
+        ));
+    }
+
+    let limit: u64 = field_limit(&field_name);
+    let mut value: Vec<u8> = Vec::new();
+    let mut stream = Box::pin(p.stream());
+    while let Some(data) = stream.try_next().await.map_err(|e| {
+        let msg = format!("reading file error: {}", e);
+        warp::reject::custom(
+            ApiError::ParsingError(field_name.clone(), msg)
+        )
+    })? {
+        if (value.len() + data.remaining()) as u64 > limit {
+            let msg = format!("Must not be larger than {} bytes", limit);
+            return Err(warp::reject::custom(
+                ApiError::PayloadTooLarge(field_name, msg)
+            ));
+        }
+        value.put(data);
+    }
+
+    if let Some(filename) = filename {
+        avatar::check_upload(&field_name, &value, content_type.as_deref(), &filename)?;
+        let stem = format!("tmp/{}", Uuid::new_v4());
+        let key = avatar::store(storage, &stem, value.into()).await.map_err(|e| match e {
+            ApiError::Storage(e) => {
                 let msg = format!("error writing file: {}", e);
                 warp::reject::custom(
-                    ApiError::ParsingError("avatar".to_string(), msg)
+                    ApiError::ParsingError(field_name.clone(), msg)
                 )
-            }).unwrap();
-            vars.insert(field_name, new_filename);
-        } else {
-            vars.insert(field_name, String::from_utf8(value).unwrap());
+            },
+            e => warp::reject::custom(e),
+        })?;
+        uploads.push(key.clone());
+        vars.insert(field_name, key);
+    } else {
+        let value = String::from_utf8(value).map_err(|e| {
+            warp::reject::custom(
+                ApiError::ParsingError(field_name.clone(), e.to_string())
+            )
+        })?;
+        vars.insert(field_name, value);
+    }
+    Ok(())
+}
+
+// the only fields of a user form that take a file
+const UPLOAD_FIELDS: &[&str] = &["avatar"];
+
+fn field_limit(field_name: &str) -> u64 {
+    let storage = &settings().storage;
+    match field_name {
+        "avatar" => storage.max_avatar_bytes,
+        _ => storage.max_field_bytes,
+    }
+}
+
+/// Uploads of a form that was rejected will never be moved out of `tmp/`
+async fn discard_uploads(storage: &dyn Storage, keys: &[String]) {
+    for key in keys {
+        if let Err(e) = avatar::delete(storage, key).await {
+            tracing::warn!(key = %key, error = %e, "Failed to discard upload");
         }
     }
-    Ok(vars)
+}
+
+fn with_bulk_params() -> impl Filter<Extract = (BulkUsersParams, ), Error = warp::Rejection> + Clone {
+    with_json_body().and_then(validate_bulk_params)
+}
+
+async fn validate_bulk_params(params: BulkUsersParams) -> Result<BulkUsersParams, warp::Rejection> {
+    require_patch(params.action, &params.patch)?;
+    Ok(params)
 }
 
 fn with_delete_params() -> impl Filter<Extract = (DeleteParams, ), Error = warp::Rejection> + Clone {
-    warp::any()
-        .and(warp::header::value("content-type"))
-        .and(warp::body::aggregate())
-        .and_then(validate_delete_params)
+    with_json_body()
 }
 
-async fn validate_delete_params(
-    content_type: HeaderValue,
-    buf: impl Buf,
-) -> Result<DeleteParams, warp::Rejection> {
-    if content_type.to_str().unwrap().starts_with("application/json") == false {
-        return Err(warp::reject::custom(
-            ApiError::ParsingError("content-type".to_string(), "Must be application/json".to_string())
-        ));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulk::BulkAction;
+    use crate::storage::LocalStorage;
+
+    const BOUNDARY: &str = "groupware";
+
+    fn form(fields: &[(&str, &str)], file: (&str, &str, &[u8])) -> Vec<u8> {
+        let mut body: Vec<u8> = vec![];
+        for (name, value) in fields {
+            body.extend_from_slice(format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                BOUNDARY, name, value,
+            ).as_bytes());
+        }
+        let (filename, content_type, data) = file;
+        body.extend_from_slice(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"avatar\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            BOUNDARY, filename, content_type,
+        ).as_bytes());
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        body
     }
-    let deserializer = &mut Deserializer::from_reader(buf.reader());
-    let params: DeleteParams = match serde_path_to_error::deserialize(deserializer) {
-        Ok(r) => r,
-        Err(e) => {
-            let pieces: Vec<String> = e.to_string().as_str().split(": ").map(String::from).collect();
-            return Err(warp::reject::custom(
-                ApiError::ParsingError(pieces[0].clone(), pieces[1].clone())
-            ));
-        },
-    };
-    match params.validate() {
-        Ok(_) => (),
-        Err(e) => {
-            return Err(warp::reject::custom(
-                ApiError::ValidationErrors(e)
-            ));
-        },
+
+    fn gif() -> Vec<u8> {
+        let mut data: Vec<u8> = vec![];
+        image::DynamicImage::new_rgba8(4, 4)
+            .write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Gif)
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn should_discard_upload_when_validation_fails() {
+        let root = std::env::temp_dir().join(format!("groupware-upload-{}", std::process::id()));
+        let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(root.clone(), "/storage", b"secret"));
+        let filter = with_create_params(storage.clone());
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+
+        let body = form(&[("name", "Jane"), ("email", "nope")], ("me.gif", "image/gif", &gif()));
+        let rejection = warp::test::request()
+            .method("POST")
+            .header("content-type", &content_type)
+            .body(body)
+            .filter(&filter)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::ValidationErrors(_))));
+        assert!(storage.list("tmp/").await.unwrap().is_empty());
+
+        let body = form(&[("name", "Jane")], ("me.png", "image/png", &gif()));
+        let rejection = warp::test::request()
+            .method("POST")
+            .header("content-type", &content_type)
+            .body(body)
+            .filter(&filter)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::UnsupportedMediaType(_, _))));
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+
+    #[tokio::test]
+    async fn should_reject_bad_bulk_bodies() {
+        let filter = with_bulk_params();
+        for (body, field) in [
+            ("{}", "."),
+            (r#"{"action":"purge","ids":[1]}"#, "action"),
+            (r#"{"action":"update","ids":[1]}"#, "patch"),
+        ] {
+            let rejection = warp::test::request()
+                .header("content-type", "application/json")
+                .body(body)
+                .filter(&filter)
+                .await
+                .err()
+                .unwrap();
+            assert!(
+                matches!(rejection.find::<ApiError>(), Some(ApiError::ParsingError(path, _)) if path == field),
+                "{}",
+                body,
+            );
+        }
+
+        let params = warp::test::request()
+            .header("content-type", "application/json")
+            .body(r#"{"action":"trash","ids":[1,2]}"#)
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(params.action, BulkAction::Trash);
     }
-    Ok(params)
 }